@@ -0,0 +1,130 @@
+//! Compares the crowd passes on a `RowMajorGrid` and a `MortonGrid` the size
+//! of our largest maps.
+//!
+//! Run with `cargo bench --bench crowd_layout`.
+
+use simulation::collections::grid::{Boundary, Difference, Grid, MortonGrid, RowMajorGrid, Scheme};
+use simulation::component::{Position, Velocity};
+use simulation::systems::continuum_crowds::splat_densities_and_velocities;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SIZE: usize = 1024;
+const AGENTS: usize = 200_000;
+const RUNS: u32 = 10;
+
+const PASSES: [&str; 4] = [
+    "reset",
+    "splat densities",
+    "density gradient",
+    "density laplacian",
+];
+
+/// Returns agents spread across the map by a fixed pseudo-random sequence,
+/// so that every run sees the same crowd.
+fn agents() -> (Vec<Position>, Vec<Velocity>) {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 40) as f32 / (1u64 << 24) as f32
+    };
+    (0..AGENTS)
+        .map(|_| {
+            let position = Position {
+                x: next() * SIZE as f32,
+                y: next() * SIZE as f32,
+            };
+            let velocity = Velocity {
+                x: next() * 2.0 - 1.0,
+                y: next() * 2.0 - 1.0,
+            };
+            (position, velocity)
+        })
+        .unzip()
+}
+
+/// Returns the mean time each pass takes on the density and velocity grids.
+/// `reset` resets the grids in the way fastest for their layout.
+fn time_passes<D, V>(
+    density: &mut D,
+    avg_velocity: &mut V,
+    reset: impl Fn(&mut D, &mut V),
+    positions: &[Position],
+    velocities: &[Velocity],
+) -> [Duration; 4]
+where
+    D: Grid<f32>,
+    V: Grid<(f32, f32)>,
+{
+    let mut totals = [Duration::default(); 4];
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        reset(density, avg_velocity);
+        totals[0] += start.elapsed();
+
+        let start = Instant::now();
+        let agents = positions.iter().zip(velocities);
+        splat_densities_and_velocities(density, avg_velocity, agents);
+        totals[1] += start.elapsed();
+
+        let start = Instant::now();
+        black_box(density.gradient_field(Scheme::Central, Boundary::Clamp));
+        totals[2] += start.elapsed();
+
+        let start = Instant::now();
+        black_box(density.laplacian_field(Boundary::Clamp));
+        totals[3] += start.elapsed();
+    }
+    let mut means = [Duration::default(); 4];
+    for (mean, total) in means.iter_mut().zip(totals.iter()) {
+        *mean = *total / RUNS;
+    }
+    means
+}
+
+fn main() {
+    let (positions, velocities) = agents();
+
+    let row_major_times = time_passes(
+        &mut RowMajorGrid::new(SIZE, SIZE, 0.0),
+        &mut RowMajorGrid::new(SIZE, SIZE, (0.0, 0.0)),
+        |density, avg_velocity| {
+            density.fill(0.0);
+            avg_velocity.fill((0.0, 0.0));
+        },
+        &positions,
+        &velocities,
+    );
+    let morton_times = time_passes(
+        &mut MortonGrid::new(SIZE, SIZE, 0.0),
+        &mut MortonGrid::new(SIZE, SIZE, (0.0, 0.0)),
+        |density, avg_velocity| {
+            density.fill(0.0);
+            avg_velocity.fill((0.0, 0.0));
+        },
+        &positions,
+        &velocities,
+    );
+
+    println!(
+        "{}x{} grid, {} agents, mean of {} runs",
+        SIZE, SIZE, AGENTS, RUNS
+    );
+    println!(
+        "{:<18} {:>14} {:>14} {:>8}",
+        "pass", "row-major ms", "morton ms", "ratio"
+    );
+    for (i, pass) in PASSES.iter().enumerate() {
+        let row_major = row_major_times[i].as_secs_f64() * 1000.0;
+        let morton = morton_times[i].as_secs_f64() * 1000.0;
+        println!(
+            "{:<18} {:>14.3} {:>14.3} {:>8.3}",
+            pass,
+            row_major,
+            morton,
+            morton / row_major
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Times each system of the crowd pipeline over a number of frames on a map
+//! the size of our largest, with a crowd spread across it and a border of
+//! ghost cells from a neighbouring region. The same frames are also run with
+//! the shared cells stored as an array of structs, as they were before
+//! `SharedGrid` kept each field in a grid of its own, as a baseline.
+//!
+//! Run with `cargo bench --bench crowd_pipeline`, and with `--features
+//! morton-crowd-grids` added to time the systems with the crowd grids in
+//! Morton order.
+
+use simulation::collections::grid::RowMajorGrid;
+use simulation::component::{Position, Velocity};
+use simulation::resources::continuum_crowds::{CrowdGrid, GhostCells, SharedCell, SharedGrid};
+use simulation::resources::DurationSinceLastFrame;
+use simulation::systems::continuum_crowds::{
+    ApplyGhostCells, AssignDensitiesAndVelocities, ResetShared,
+};
+use simulation::systems::UpdatePos;
+use specs::{Builder, RunNow, World, WorldExt};
+use std::time::{Duration, Instant};
+
+/// The shared crowd systems as they were when each cell of the shared grid
+/// was one `SharedCell`.
+mod array_of_structs {
+    use simulation::collections::grid::{Grid, RowMajorGrid};
+    use simulation::component::{Position, Velocity};
+    use simulation::resources::continuum_crowds::{GhostCells, SharedCell};
+    use specs::{Join, ReadExpect, ReadStorage, System, WriteExpect};
+
+    pub struct SharedCells(pub RowMajorGrid<SharedCell>);
+
+    pub struct ResetShared;
+
+    impl<'a> System<'a> for ResetShared {
+        type SystemData = WriteExpect<'a, SharedCells>;
+
+        fn run(&mut self, mut shared_cells: Self::SystemData) {
+            for cell in shared_cells.0.iter_mut() {
+                cell.density = 0.0;
+                cell.avg_velocity = (0.0, 0.0);
+            }
+        }
+    }
+
+    pub struct AssignDensitiesAndVelocities;
+
+    impl<'a> System<'a> for AssignDensitiesAndVelocities {
+        type SystemData = (
+            WriteExpect<'a, SharedCells>,
+            ReadStorage<'a, Position>,
+            ReadStorage<'a, Velocity>,
+        );
+
+        fn run(&mut self, data: Self::SystemData) {
+            let (mut shared_cells, positions, velocities) = data;
+            let grid = &mut shared_cells.0;
+            for (pos, vel) in (&positions, &velocities).join() {
+                let a_center_x = (pos.x + 0.5).floor() - 0.5;
+                let a_center_y = (pos.y + 0.5).floor() - 0.5;
+                let delta_x = pos.x - a_center_x;
+                let delta_y = pos.y - a_center_y;
+                let neighbours = [
+                    (a_center_x, a_center_y, (1.0 - delta_x).min(1.0 - delta_y)),
+                    (a_center_x + 1.0, a_center_y, delta_x.min(1.0 - delta_y)),
+                    (a_center_x + 1.0, a_center_y + 1.0, delta_x.min(delta_y)),
+                    (a_center_x, a_center_y + 1.0, (1.0 - delta_x).min(delta_y)),
+                ];
+                for &(x, y, density) in &neighbours {
+                    if x < 0.0 || y < 0.0 {
+                        continue;
+                    }
+                    if let Some(cell) = grid.get_mut(x as usize, y as usize) {
+                        cell.density += density;
+                        cell.avg_velocity = (
+                            cell.avg_velocity.0 + density * vel.x,
+                            cell.avg_velocity.1 + density * vel.y,
+                        );
+                    }
+                }
+            }
+            for cell in grid.iter_mut() {
+                cell.avg_velocity = (
+                    cell.avg_velocity.0 / cell.density,
+                    cell.avg_velocity.1 / cell.density,
+                );
+            }
+        }
+    }
+
+    pub struct ApplyGhostCells;
+
+    impl<'a> System<'a> for ApplyGhostCells {
+        type SystemData = (WriteExpect<'a, SharedCells>, ReadExpect<'a, GhostCells>);
+
+        fn run(&mut self, data: Self::SystemData) {
+            let (mut shared_cells, ghost_cells) = data;
+            for (&(x, y), cell) in ghost_cells.0.iter() {
+                shared_cells.0.set(x, y, *cell);
+            }
+        }
+    }
+}
+
+const SIZE: usize = 1024;
+const AGENTS: usize = 200_000;
+const FRAMES: u32 = 20;
+
+/// Returns a world with agents spread across the map by a fixed pseudo-random
+/// sequence, so that every run sees the same crowd.
+fn world() -> World {
+    let mut world = World::new();
+    world.register::<Position>();
+    world.register::<Velocity>();
+
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 40) as f32 / (1u64 << 24) as f32
+    };
+    for _ in 0..AGENTS {
+        let position = Position {
+            x: next() * SIZE as f32,
+            y: next() * SIZE as f32,
+        };
+        let velocity = Velocity {
+            x: next() * 2.0 - 1.0,
+            y: next() * 2.0 - 1.0,
+        };
+        world.create_entity().with(position).with(velocity).build();
+    }
+
+    let ghost_cells = (0..SIZE)
+        .flat_map(|y| (SIZE - 2..SIZE).map(move |x| (x, y)))
+        .map(|pos| {
+            let cell = SharedCell {
+                density: 0.5,
+                avg_velocity: (1.0, 0.0),
+                ..SharedCell::default()
+            };
+            (pos, cell)
+        })
+        .collect();
+    world.insert(SharedGrid::new(SIZE, SIZE));
+    world.insert(array_of_structs::SharedCells(RowMajorGrid::new(
+        SIZE,
+        SIZE,
+        SharedCell::default(),
+    )));
+    world.insert(GhostCells(ghost_cells));
+    world.insert(DurationSinceLastFrame(Duration::from_millis(1)));
+    world
+}
+
+fn time<S: for<'a> RunNow<'a>>(system: &mut S, world: &World, total: &mut Duration) {
+    let start = Instant::now();
+    system.run_now(world);
+    *total += start.elapsed();
+}
+
+/// Returns the mean time per frame of each system.
+fn run_frames<R, A, G>(reset: &mut R, assign: &mut A, ghost: &mut G) -> [Duration; 4]
+where
+    R: for<'a> RunNow<'a>,
+    A: for<'a> RunNow<'a>,
+    G: for<'a> RunNow<'a>,
+{
+    let mut world = world();
+    let mut totals = [Duration::default(); 4];
+    for _ in 0..FRAMES {
+        time(reset, &world, &mut totals[0]);
+        time(assign, &world, &mut totals[1]);
+        time(ghost, &world, &mut totals[2]);
+        time(&mut UpdatePos, &world, &mut totals[3]);
+        world.maintain();
+    }
+    for total in &mut totals {
+        *total /= FRAMES;
+    }
+    totals
+}
+
+fn main() {
+    let separate = run_frames(
+        &mut ResetShared,
+        &mut AssignDensitiesAndVelocities,
+        &mut ApplyGhostCells,
+    );
+    let combined = run_frames(
+        &mut array_of_structs::ResetShared,
+        &mut array_of_structs::AssignDensitiesAndVelocities,
+        &mut array_of_structs::ApplyGhostCells,
+    );
+
+    let names = [
+        "reset_shared",
+        "assign_densities_and_velocities",
+        "apply_ghost_cells",
+        "update_pos",
+    ];
+    println!(
+        "{}x{} grid, {} agents, mean of {} frames in ms, crowd grids are {}",
+        SIZE,
+        SIZE,
+        AGENTS,
+        FRAMES,
+        std::any::type_name::<CrowdGrid<f32>>()
+    );
+    println!(
+        "{:<32} {:>16} {:>16}",
+        "system", "array of structs", "separate grids"
+    );
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    for (i, name) in names.iter().enumerate() {
+        println!(
+            "{:<32} {:>16.3} {:>16.3}",
+            name,
+            ms(combined[i]),
+            ms(separate[i])
+        );
+    }
+    println!(
+        "{:<32} {:>16.3} {:>16.3}",
+        "frame",
+        ms(combined.iter().sum()),
+        ms(separate.iter().sum())
+    );
+}
@@ -0,0 +1,126 @@
+//! Compares the memory allocated by a dense `RowMajorGrid` and a
+//! `ChunkedGrid` holding the same cells, for a large venue in a few states.
+//!
+//! Run with `cargo bench --bench grid_memory`.
+
+use simulation::collections::grid::{ChunkedGrid, Grid, RowMajorGrid};
+use simulation::resources::continuum_crowds::SharedCell;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Allocator that keeps count of the bytes currently allocated.
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const SIZE: usize = 2048;
+
+/// A state of the venue.
+#[derive(Clone, Copy, Debug)]
+enum Venue {
+    Empty,
+    /// Walls around the edges of the venue and across it every 512 cells.
+    Walled,
+    /// A dense crowd in one 256 by 256 corner of the venue.
+    Crowded,
+    /// The crowded venue after the crowd has left.
+    Dispersed,
+}
+
+impl Venue {
+    const ALL: [Venue; 4] = [
+        Venue::Empty,
+        Venue::Walled,
+        Venue::Crowded,
+        Venue::Dispersed,
+    ];
+
+    fn paint<G: Grid<SharedCell>>(self, grid: &mut G) {
+        match self {
+            Venue::Empty => {}
+            Venue::Walled => {
+                for i in 0..SIZE {
+                    for j in (0..SIZE).step_by(512).chain(Some(SIZE - 1)) {
+                        grid.get_mut(i, j).unwrap().is_obstacle = true;
+                        grid.get_mut(j, i).unwrap().is_obstacle = true;
+                    }
+                }
+            }
+            Venue::Crowded => {
+                for y in 0..256 {
+                    for x in 0..256 {
+                        let cell = grid.get_mut(x, y).unwrap();
+                        cell.density = 1.0;
+                        cell.avg_velocity = (1.0, 0.0);
+                    }
+                }
+            }
+            Venue::Dispersed => {
+                Venue::Crowded.paint(grid);
+                for y in 0..256 {
+                    for x in 0..256 {
+                        grid.set(x, y, SharedCell::default());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the bytes allocated by building the value, which stays allocated
+/// until it is dropped after measuring.
+fn measure<T>(build: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let value = build();
+    let after = ALLOCATED.load(Ordering::Relaxed);
+    drop(value);
+    after - before
+}
+
+fn main() {
+    println!(
+        "{}x{} grid of {}-byte cells",
+        SIZE,
+        SIZE,
+        std::mem::size_of::<SharedCell>()
+    );
+    println!(
+        "{:<16} {:>14} {:>14} {:>8}",
+        "venue", "dense bytes", "chunked bytes", "ratio"
+    );
+    for &venue in Venue::ALL.iter() {
+        let dense = measure(|| {
+            let mut grid = RowMajorGrid::new(SIZE, SIZE, SharedCell::default());
+            venue.paint(&mut grid);
+            grid
+        });
+        let chunked = measure(|| {
+            let mut grid = ChunkedGrid::new(SIZE, SIZE, SharedCell::default());
+            venue.paint(&mut grid);
+            grid.free_default_chunks();
+            grid
+        });
+        println!(
+            "{:<16} {:>14} {:>14} {:>8.3}",
+            format!("{:?}", venue),
+            dense,
+            chunked,
+            chunked as f64 / dense as f64
+        );
+    }
+}
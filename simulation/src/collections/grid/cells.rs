@@ -0,0 +1,178 @@
+use super::{Grid, GridError, RowMajorGrid};
+use std::iter::Sum;
+
+/// Every grid holds a cell at each position within its bounds, so a missing
+/// cell is a bug in the grid rather than something a pass can skip.
+const MISSING_CELL: &str = "the grid has no cell at a position within its bounds";
+
+/// Passes over every cell of a grid, visiting the cells in row-major order
+/// from the grid's offsets.
+///
+/// The methods that combine two grids pair the cells at the same position
+/// relative to each grid's offsets, so the grids may cover different parts of
+/// a larger one. They fail with `GridError::DimensionMismatch` unless both
+/// grids have the same width and height.
+pub trait Cells<T>: Grid<T> {
+    /// Returns a grid with the same dimensions and offsets whose cells hold
+    /// the function applied to each cell.
+    fn map<U, F>(&self, f: F) -> RowMajorGrid<U>
+    where
+        F: FnMut(&T) -> U,
+    {
+        let cells = cells(self).map(f).collect();
+        RowMajorGrid::from_cells(
+            self.width(),
+            self.height(),
+            self.x_offset(),
+            self.y_offset(),
+            cells,
+        )
+    }
+
+    /// Returns a grid with the same dimensions and offsets whose cells hold
+    /// the function applied to each cell and the matching cell of the other
+    /// grid.
+    fn zip_with<U, V, G, F>(&self, other: &G, mut f: F) -> Result<RowMajorGrid<V>, GridError>
+    where
+        G: Grid<U>,
+        F: FnMut(&T, &U) -> V,
+    {
+        check_dimensions(self, other)?;
+        let cells = cells(self)
+            .zip(cells(other))
+            .map(|(cell, other)| f(cell, other))
+            .collect();
+        Ok(RowMajorGrid::from_cells(
+            self.width(),
+            self.height(),
+            self.x_offset(),
+            self.y_offset(),
+            cells,
+        ))
+    }
+
+    /// Calls the function on each cell and the matching cell of the other
+    /// grid, allowing it to modify the cell.
+    fn zip_mut_with<U, G, F>(&mut self, other: &G, mut f: F) -> Result<(), GridError>
+    where
+        G: Grid<U>,
+        F: FnMut(&mut T, &U),
+    {
+        check_dimensions(self, other)?;
+        let (x_offset, y_offset) = (self.x_offset(), self.y_offset());
+        let (dx, dy) = (other.x_offset(), other.y_offset());
+        for y in y_offset..y_offset + self.height() {
+            let other_y = y - y_offset + dy;
+            if let (Some(row), Some(other_row)) = (self.row_slice_mut(y), other.row_slice(other_y))
+            {
+                for (cell, other) in row.iter_mut().zip(other_row) {
+                    f(cell, other);
+                }
+                continue;
+            }
+            for x in x_offset..x_offset + self.width() {
+                let other = other.get(x - x_offset + dx, other_y).expect(MISSING_CELL);
+                f(self.get_mut(x, y).expect(MISSING_CELL), other);
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls the function on each cell, allowing it to modify the cell.
+    fn for_each_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut T),
+    {
+        let x_offset = self.x_offset();
+        for y in self.y_offset()..self.y_offset() + self.height() {
+            if let Some(row) = self.row_slice_mut(y) {
+                row.iter_mut().for_each(&mut f);
+                continue;
+            }
+            for x in x_offset..x_offset + self.width() {
+                f(self.get_mut(x, y).expect(MISSING_CELL));
+            }
+        }
+    }
+
+    /// Folds every cell into an accumulator, starting from `init`.
+    fn fold<A, F>(&self, init: A, f: F) -> A
+    where
+        F: FnMut(A, &T) -> A,
+    {
+        cells(self).fold(init, f)
+    }
+
+    /// Returns the smallest cell, or `None` if the grid is empty. Cells that
+    /// can't be compared, such as NaN, are skipped.
+    fn min(&self) -> Option<T>
+    where
+        T: PartialOrd + Copy,
+    {
+        extreme(self, |cell, min| cell < min)
+    }
+
+    /// Returns the largest cell, or `None` if the grid is empty. Cells that
+    /// can't be compared, such as NaN, are skipped.
+    fn max(&self) -> Option<T>
+    where
+        T: PartialOrd + Copy,
+    {
+        extreme(self, |cell, max| cell > max)
+    }
+
+    /// Returns the sum of every cell.
+    fn sum(&self) -> T
+    where
+        T: for<'a> Sum<&'a T>,
+    {
+        cells(self).sum()
+    }
+}
+
+impl<T, G: Grid<T> + ?Sized> Cells<T> for G {}
+
+/// Returns an iterator over the cells of the grid in row-major order, which
+/// reads whole rows where the grid stores them contiguously.
+fn cells<'a, T: 'a, G: Grid<T> + ?Sized>(grid: &'a G) -> impl Iterator<Item = &'a T> {
+    let (x_offset, width) = (grid.x_offset(), grid.width());
+    (grid.y_offset()..grid.y_offset() + grid.height()).flat_map(move |y| {
+        let row = grid.row_slice(y);
+        let columns = match row {
+            Some(_) => 0..0,
+            None => x_offset..x_offset + width,
+        };
+        let by_position = columns.map(move |x| grid.get(x, y).expect(MISSING_CELL));
+        row.into_iter().flatten().chain(by_position)
+    })
+}
+
+fn check_dimensions<T, U, G, H>(grid: &G, other: &H) -> Result<(), GridError>
+where
+    G: Grid<T> + ?Sized,
+    H: Grid<U> + ?Sized,
+{
+    let expected = (grid.width(), grid.height());
+    let found = (other.width(), other.height());
+    if expected == found {
+        Ok(())
+    } else {
+        Err(GridError::DimensionMismatch { expected, found })
+    }
+}
+
+/// Returns the cell that `replaces` prefers over every other, skipping cells
+/// that aren't equal to themselves.
+fn extreme<T, G, F>(grid: &G, replaces: F) -> Option<T>
+where
+    T: PartialOrd + Copy,
+    G: Grid<T> + ?Sized,
+    F: Fn(&T, &T) -> bool,
+{
+    cells(grid)
+        .filter(|cell| cell.partial_cmp(cell).is_some())
+        .fold(None, |best, cell| match best {
+            Some(best) if !replaces(cell, &best) => Some(best),
+            _ => Some(*cell),
+        })
+}
@@ -0,0 +1,204 @@
+use super::{Grid, GridView, GridViewMut};
+
+/// The width and height of each chunk of a `ChunkedGrid`, in cells.
+pub const CHUNK_SIZE: usize = 16;
+
+/// Grid that stores its cells in square chunks of `CHUNK_SIZE` cells a side,
+/// allocating each chunk only when one of its cells is first written. Cells of
+/// chunks that haven't been allocated read as the grid's default value, so a
+/// large grid that is mostly left at the default, such as a venue that is
+/// mostly empty or blocked, only uses memory for the parts that aren't.
+///
+/// Chunks are aligned to the grid's offsets, so the chunks along the far
+/// edges may extend beyond the grid.
+#[derive(Debug)]
+pub struct ChunkedGrid<T> {
+    inner_width: usize,
+    inner_height: usize,
+    x_offset: usize,
+    y_offset: usize,
+    default: T,
+    /// Each chunk, or `None` if it hasn't been allocated, in row-major order.
+    chunks: Vec<Option<Box<[T]>>>,
+}
+
+impl<T: Clone> ChunkedGrid<T> {
+    pub fn new(width: usize, height: usize, default: T) -> Self {
+        ChunkedGrid::new_sub_grid(width, height, 0, 0, default)
+    }
+
+    pub fn new_sub_grid(
+        inner_width: usize,
+        inner_height: usize,
+        x_offset: usize,
+        y_offset: usize,
+        default: T,
+    ) -> Self {
+        let chunk_count = chunks_along(inner_width) * chunks_along(inner_height);
+        ChunkedGrid {
+            inner_width,
+            inner_height,
+            x_offset,
+            y_offset,
+            default,
+            chunks: (0..chunk_count).map(|_| None).collect(),
+        }
+    }
+}
+
+impl<T> ChunkedGrid<T> {
+    /// The value of cells that haven't been written.
+    pub fn default_value(&self) -> &T {
+        &self.default
+    }
+
+    pub fn allocated_chunks(&self) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.is_some()).count()
+    }
+
+    /// Returns an iterator over views of the allocated chunks, in row-major
+    /// order. Each view covers the part of its chunk that lies in the grid.
+    pub fn chunks(&self) -> impl Iterator<Item = GridView<'_, T>> + '_ {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, chunk)| {
+                let chunk = chunk.as_ref()?;
+                let (x, y, width, height) = chunk_bounds(self.bounds(), i);
+                GridView::new(chunk, CHUNK_SIZE, CHUNK_SIZE, x, y).window(x, y, width, height)
+            })
+    }
+
+    /// Returns an iterator over mutable views of the allocated chunks, in
+    /// row-major order. Each view covers the part of its chunk that lies in
+    /// the grid.
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = GridViewMut<'_, T>> + '_ {
+        let bounds = self.bounds();
+        self.chunks
+            .iter_mut()
+            .enumerate()
+            .filter_map(move |(i, chunk)| {
+                let chunk = chunk.as_mut()?;
+                let (x, y, width, height) = chunk_bounds(bounds, i);
+                GridViewMut::new(chunk, CHUNK_SIZE, CHUNK_SIZE, x, y).window(x, y, width, height)
+            })
+    }
+
+    /// Frees every allocated chunk whose cells all hold the default value,
+    /// returning the number of chunks freed.
+    pub fn free_default_chunks(&mut self) -> usize
+    where
+        T: PartialEq,
+    {
+        let default = &self.default;
+        let mut freed = 0;
+        for slot in self.chunks.iter_mut() {
+            let is_default = match slot {
+                Some(chunk) => chunk.iter().all(|cell| cell == default),
+                None => false,
+            };
+            if is_default {
+                *slot = None;
+                freed += 1;
+            }
+        }
+        freed
+    }
+
+    /// Returns the index of the chunk that holds the cell at the position and
+    /// the index of the cell in the chunk.
+    fn index(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        let x = x.checked_sub(self.x_offset)?;
+        let y = y.checked_sub(self.y_offset)?;
+        if x >= self.inner_width || y >= self.inner_height {
+            return None;
+        }
+        let chunk = (y / CHUNK_SIZE) * chunks_along(self.inner_width) + x / CHUNK_SIZE;
+        let cell = (y % CHUNK_SIZE) * CHUNK_SIZE + x % CHUNK_SIZE;
+        Some((chunk, cell))
+    }
+
+    fn bounds(&self) -> (usize, usize, usize, usize) {
+        (
+            self.inner_width,
+            self.inner_height,
+            self.x_offset,
+            self.y_offset,
+        )
+    }
+}
+
+impl<T: Clone> Grid<T> for ChunkedGrid<T> {
+    fn get(&self, x: usize, y: usize) -> Option<&T> {
+        let (chunk, cell) = self.index(x, y)?;
+        match &self.chunks[chunk] {
+            Some(chunk) => Some(&chunk[cell]),
+            None => Some(&self.default),
+        }
+    }
+
+    /// Returns the cell at the position, allocating its chunk if it hasn't
+    /// been allocated. The chunk is allocated even if the cell is only read
+    /// through the reference, so use `get` to read cells.
+    fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
+        let (chunk, cell) = self.index(x, y)?;
+        let default = &self.default;
+        let chunk = self.chunks[chunk]
+            .get_or_insert_with(|| vec![default.clone(); CHUNK_SIZE * CHUNK_SIZE].into());
+        Some(&mut chunk[cell])
+    }
+
+    fn set(&mut self, x: usize, y: usize, val: T) {
+        if let Some(cell) = self.get_mut(x, y) {
+            *cell = val;
+        }
+    }
+
+    /// Sets every cell to the value by making it the default and freeing
+    /// every chunk, so filling never allocates.
+    fn fill(&mut self, value: T) {
+        self.default = value;
+        for chunk in self.chunks.iter_mut() {
+            *chunk = None;
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.inner_width
+    }
+
+    fn height(&self) -> usize {
+        self.inner_height
+    }
+
+    fn x_offset(&self) -> usize {
+        self.x_offset
+    }
+
+    fn y_offset(&self) -> usize {
+        self.y_offset
+    }
+}
+
+/// Returns the number of chunks needed to cover the given number of cells.
+fn chunks_along(cells: usize) -> usize {
+    cells.div_ceil(CHUNK_SIZE)
+}
+
+/// Returns the offsets of the chunk at the index, in a grid with the given
+/// dimensions and offsets, and the dimensions of the part of it that lies in
+/// the grid.
+fn chunk_bounds(
+    (inner_width, inner_height, x_offset, y_offset): (usize, usize, usize, usize),
+    chunk: usize,
+) -> (usize, usize, usize, usize) {
+    let chunks_wide = chunks_along(inner_width);
+    let x = (chunk % chunks_wide) * CHUNK_SIZE;
+    let y = (chunk / chunks_wide) * CHUNK_SIZE;
+    (
+        x_offset + x,
+        y_offset + y,
+        CHUNK_SIZE.min(inner_width - x),
+        CHUNK_SIZE.min(inner_height - y),
+    )
+}
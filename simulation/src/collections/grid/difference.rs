@@ -0,0 +1,322 @@
+use super::sample::cell_value;
+use super::{Edge, Grid, RowMajorGrid};
+
+/// Which cells a first difference reads along an axis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    /// Half the difference between the next and the previous cell.
+    Central,
+
+    /// The difference between the next cell and the cell itself.
+    Forward,
+
+    /// The difference between the cell itself and the previous cell.
+    Backward,
+}
+
+/// How differences treat cells beyond the edges of the grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Boundary {
+    /// Cells beyond an edge repeat the nearest cell on the edge, so that
+    /// nothing changes across the edge.
+    Clamp,
+
+    /// Cells beyond an edge are zero.
+    Zero,
+
+    /// Differences never read beyond an edge. At an edge, first differences
+    /// fall back to the one-sided difference into the grid, and second
+    /// differences move their stencil inwards by a cell.
+    OneSided,
+}
+
+/// Finite differences of the values in a grid, in units of cells. Differences
+/// at positions outside the grid are zero.
+///
+/// The `_by` methods take differences of a value projected out of each cell,
+/// such as one field of a struct.
+pub trait Difference<C>: Grid<C> {
+    /// Returns the first difference along x of the cell at the position.
+    fn difference_x_by<F>(
+        &self,
+        x: usize,
+        y: usize,
+        scheme: Scheme,
+        boundary: Boundary,
+        project: F,
+    ) -> f32
+    where
+        F: Fn(&C) -> f32,
+    {
+        first_difference(self, x, y, (1, 0), scheme, boundary, &project)
+    }
+
+    /// Returns the first difference along y of the cell at the position.
+    fn difference_y_by<F>(
+        &self,
+        x: usize,
+        y: usize,
+        scheme: Scheme,
+        boundary: Boundary,
+        project: F,
+    ) -> f32
+    where
+        F: Fn(&C) -> f32,
+    {
+        first_difference(self, x, y, (0, 1), scheme, boundary, &project)
+    }
+
+    fn gradient_by<F>(
+        &self,
+        x: usize,
+        y: usize,
+        scheme: Scheme,
+        boundary: Boundary,
+        project: F,
+    ) -> (f32, f32)
+    where
+        F: Fn(&C) -> f32,
+    {
+        (
+            first_difference(self, x, y, (1, 0), scheme, boundary, &project),
+            first_difference(self, x, y, (0, 1), scheme, boundary, &project),
+        )
+    }
+
+    fn divergence_by<F>(
+        &self,
+        x: usize,
+        y: usize,
+        scheme: Scheme,
+        boundary: Boundary,
+        project: F,
+    ) -> f32
+    where
+        F: Fn(&C) -> (f32, f32),
+    {
+        let project_x = |cell: &C| project(cell).0;
+        let project_y = |cell: &C| project(cell).1;
+        first_difference(self, x, y, (1, 0), scheme, boundary, &project_x)
+            + first_difference(self, x, y, (0, 1), scheme, boundary, &project_y)
+    }
+
+    /// Returns the Laplacian of the cell at the position, using the five point
+    /// stencil of the cell and its four neighbours.
+    fn laplacian_by<F>(&self, x: usize, y: usize, boundary: Boundary, project: F) -> f32
+    where
+        F: Fn(&C) -> f32,
+    {
+        second_difference(self, x, y, (1, 0), boundary, &project)
+            + second_difference(self, x, y, (0, 1), boundary, &project)
+    }
+
+    /// Returns a grid with the same dimensions and offsets as this one that
+    /// holds the gradient of each cell.
+    fn gradient_field_by<F>(
+        &self,
+        scheme: Scheme,
+        boundary: Boundary,
+        project: F,
+    ) -> RowMajorGrid<(f32, f32)>
+    where
+        F: Fn(&C) -> f32,
+    {
+        field(self, |x, y| {
+            self.gradient_by(x, y, scheme, boundary, &project)
+        })
+    }
+
+    /// Returns a grid with the same dimensions and offsets as this one that
+    /// holds the divergence of each cell.
+    fn divergence_field_by<F>(
+        &self,
+        scheme: Scheme,
+        boundary: Boundary,
+        project: F,
+    ) -> RowMajorGrid<f32>
+    where
+        F: Fn(&C) -> (f32, f32),
+    {
+        field(self, |x, y| {
+            self.divergence_by(x, y, scheme, boundary, &project)
+        })
+    }
+
+    /// Returns a grid with the same dimensions and offsets as this one that
+    /// holds the Laplacian of each cell.
+    fn laplacian_field_by<F>(&self, boundary: Boundary, project: F) -> RowMajorGrid<f32>
+    where
+        F: Fn(&C) -> f32,
+    {
+        field(self, |x, y| self.laplacian_by(x, y, boundary, &project))
+    }
+
+    fn gradient(&self, x: usize, y: usize, scheme: Scheme, boundary: Boundary) -> (f32, f32)
+    where
+        C: Copy + Into<f32>,
+    {
+        self.gradient_by(x, y, scheme, boundary, |&cell| cell.into())
+    }
+
+    fn divergence(&self, x: usize, y: usize, scheme: Scheme, boundary: Boundary) -> f32
+    where
+        C: Copy + Into<(f32, f32)>,
+    {
+        self.divergence_by(x, y, scheme, boundary, |&cell| cell.into())
+    }
+
+    fn laplacian(&self, x: usize, y: usize, boundary: Boundary) -> f32
+    where
+        C: Copy + Into<f32>,
+    {
+        self.laplacian_by(x, y, boundary, |&cell| cell.into())
+    }
+
+    fn gradient_field(&self, scheme: Scheme, boundary: Boundary) -> RowMajorGrid<(f32, f32)>
+    where
+        C: Copy + Into<f32>,
+    {
+        self.gradient_field_by(scheme, boundary, |&cell| cell.into())
+    }
+
+    fn divergence_field(&self, scheme: Scheme, boundary: Boundary) -> RowMajorGrid<f32>
+    where
+        C: Copy + Into<(f32, f32)>,
+    {
+        self.divergence_field_by(scheme, boundary, |&cell| cell.into())
+    }
+
+    fn laplacian_field(&self, boundary: Boundary) -> RowMajorGrid<f32>
+    where
+        C: Copy + Into<f32>,
+    {
+        self.laplacian_field_by(boundary, |&cell| cell.into())
+    }
+}
+
+impl<C, G: Grid<C> + ?Sized> Difference<C> for G {}
+
+fn first_difference<C, G, F>(
+    grid: &G,
+    x: usize,
+    y: usize,
+    (dx, dy): (isize, isize),
+    scheme: Scheme,
+    boundary: Boundary,
+    project: &F,
+) -> f32
+where
+    G: Grid<C> + ?Sized,
+    F: Fn(&C) -> f32,
+{
+    if !grid.in_bounds(x, y) {
+        return 0.0;
+    }
+    let (x, y) = (x as isize, y as isize);
+    let (has_previous, has_next) = match boundary {
+        Boundary::OneSided => (
+            contains(grid, x - dx, y - dy),
+            contains(grid, x + dx, y + dy),
+        ),
+        Boundary::Clamp | Boundary::Zero => (true, true),
+    };
+    let scheme = match (has_previous, has_next) {
+        (true, true) => scheme,
+        (false, true) => Scheme::Forward,
+        (true, false) => Scheme::Backward,
+        // The grid is a single cell wide along this axis.
+        (false, false) => return 0.0,
+    };
+    let read = |steps: isize| {
+        cell_value(
+            grid,
+            x + dx * steps,
+            y + dy * steps,
+            edge(boundary),
+            project,
+        )
+    };
+    match scheme {
+        Scheme::Central => (read(1) - read(-1)) / 2.0,
+        Scheme::Forward => read(1) - read(0),
+        Scheme::Backward => read(0) - read(-1),
+    }
+}
+
+fn second_difference<C, G, F>(
+    grid: &G,
+    x: usize,
+    y: usize,
+    (dx, dy): (isize, isize),
+    boundary: Boundary,
+    project: &F,
+) -> f32
+where
+    G: Grid<C> + ?Sized,
+    F: Fn(&C) -> f32,
+{
+    if !grid.in_bounds(x, y) {
+        return 0.0;
+    }
+    let (mut x, mut y) = (x as isize, y as isize);
+    if boundary == Boundary::OneSided {
+        let length = if dx != 0 { grid.width() } else { grid.height() };
+        if length < 3 {
+            return 0.0;
+        }
+        if !contains(grid, x - dx, y - dy) {
+            x += dx;
+            y += dy;
+        } else if !contains(grid, x + dx, y + dy) {
+            x -= dx;
+            y -= dy;
+        }
+    }
+    let read = |steps: isize| {
+        cell_value(
+            grid,
+            x + dx * steps,
+            y + dy * steps,
+            edge(boundary),
+            project,
+        )
+    };
+    read(1) - 2.0 * read(0) + read(-1)
+}
+
+/// Returns the edge behaviour with which a boundary reads cells. One-sided
+/// differences only read cells in the grid, so their edge behaviour is never
+/// used.
+fn edge(boundary: Boundary) -> Edge {
+    match boundary {
+        Boundary::Clamp => Edge::Clamp,
+        Boundary::Zero | Boundary::OneSided => Edge::Zero,
+    }
+}
+
+fn contains<C, G: Grid<C> + ?Sized>(grid: &G, x: isize, y: isize) -> bool {
+    x >= 0 && y >= 0 && grid.in_bounds(x as usize, y as usize)
+}
+
+/// Returns a grid with the same dimensions and offsets as the given one, with
+/// each cell computed from its position.
+fn field<C, G, V, F>(grid: &G, mut value: F) -> RowMajorGrid<V>
+where
+    G: Grid<C> + ?Sized,
+    V: Clone + Default,
+    F: FnMut(usize, usize) -> V,
+{
+    let mut field = RowMajorGrid::new_sub_grid(
+        grid.width(),
+        grid.height(),
+        grid.x_offset(),
+        grid.y_offset(),
+        V::default(),
+    );
+    for y in grid.y_offset()..grid.y_offset() + grid.height() {
+        for x in grid.x_offset()..grid.x_offset() + grid.width() {
+            field.set(x, y, value(x, y));
+        }
+    }
+    field
+}
@@ -0,0 +1,117 @@
+use super::Grid;
+use std::marker::PhantomData;
+use std::slice;
+
+/// A direction in which a cell shares a face with a neighbouring cell. A
+/// cell's east face, as looked up in a `StaggeredGrid`, lies between it and
+/// its `East` neighbour. North points towards increasing y.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    East,
+    North,
+    West,
+    South,
+}
+
+impl Direction {
+    /// Every direction, counterclockwise from east.
+    pub const ALL: [Direction; 4] = [
+        Direction::East,
+        Direction::North,
+        Direction::West,
+        Direction::South,
+    ];
+
+    /// The change in x and y from a cell to its neighbour in this direction.
+    pub fn offset(self) -> (isize, isize) {
+        match self {
+            Direction::East => (1, 0),
+            Direction::North => (0, 1),
+            Direction::West => (-1, 0),
+            Direction::South => (0, -1),
+        }
+    }
+
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::East => Direction::West,
+            Direction::North => Direction::South,
+            Direction::West => Direction::East,
+            Direction::South => Direction::North,
+        }
+    }
+
+    /// Returns the position of the neighbour of the cell at the given position
+    /// in this direction, or `None` if it would have a negative coordinate.
+    pub fn step(self, x: usize, y: usize) -> Option<(usize, usize)> {
+        offset_pos(x, y, self.offset())
+    }
+}
+
+/// Offsets of the neighbours that share a face with a cell, in the same order
+/// as `Direction::ALL`.
+pub(super) const NEIGHBOURHOOD_4: [(isize, isize); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+/// Offsets of the neighbours that share a face or a corner with a cell,
+/// counterclockwise from east.
+pub(super) const NEIGHBOURHOOD_8: [(isize, isize); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+/// Iterator over the neighbours of a cell that lie in a grid, created by
+/// `Grid::neighbours4` and `Grid::neighbours8`. Yields the absolute position of
+/// each neighbour along with the neighbour.
+pub struct Neighbours<'a, T, G: ?Sized> {
+    grid: &'a G,
+    x: usize,
+    y: usize,
+    offsets: slice::Iter<'static, (isize, isize)>,
+    cell: PhantomData<&'a T>,
+}
+
+impl<'a, T, G: ?Sized> Neighbours<'a, T, G> {
+    pub(super) fn new(
+        grid: &'a G,
+        x: usize,
+        y: usize,
+        offsets: &'static [(isize, isize)],
+    ) -> Neighbours<'a, T, G> {
+        Neighbours {
+            grid,
+            x,
+            y,
+            offsets: offsets.iter(),
+            cell: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: 'a, G: Grid<T> + ?Sized> Iterator for Neighbours<'a, T, G> {
+    type Item = (usize, usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for &offset in self.offsets.by_ref() {
+            if let Some((x, y)) = offset_pos(self.x, self.y, offset) {
+                if let Some(cell) = self.grid.get(x, y) {
+                    return Some((x, y, cell));
+                }
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.offsets.len()))
+    }
+}
+
+fn offset_pos(x: usize, y: usize, (dx, dy): (isize, isize)) -> Option<(usize, usize)> {
+    Some((x.checked_add_signed(dx)?, y.checked_add_signed(dy)?))
+}
@@ -0,0 +1,27 @@
+use std::error;
+use std::fmt;
+
+/// Reason that an operation on grids failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GridError {
+    /// The grids combined by the operation have different widths or heights.
+    /// Dimensions are given as (width, height).
+    DimensionMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GridError::DimensionMismatch { expected, found } => write!(
+                f,
+                "expected a {}x{} grid, found a {}x{} grid",
+                expected.0, expected.1, found.0, found.1
+            ),
+        }
+    }
+}
+
+impl error::Error for GridError {}
@@ -1,8 +1,113 @@
+mod cells;
+mod chunked_grid;
+mod difference;
+mod direction;
+mod error;
+mod morton_grid;
 mod row_major_grid;
+mod sample;
+mod staggered_grid;
+mod view;
 
-pub use row_major_grid::RowMajorGrid;
+pub use cells::Cells;
+pub use chunked_grid::{ChunkedGrid, CHUNK_SIZE};
+pub use difference::{Boundary, Difference, Scheme};
+pub use direction::{Direction, Neighbours};
+pub use error::GridError;
+pub use morton_grid::MortonGrid;
+pub use row_major_grid::{Enumerate, Iter, IterMut, PositionIter, RowMajorGrid, Rows, RowsMut};
+pub use sample::{Edge, Interpolate, Sample};
+pub use staggered_grid::StaggeredGrid;
+pub use view::{GridView, GridViewMut};
+
+use direction::{NEIGHBOURHOOD_4, NEIGHBOURHOOD_8};
+
+/// A rectangular grid of cells. A grid may be a sub grid that covers part of a
+/// larger one, in which case its cells are addressed by their absolute
+/// positions, starting at the grid's offsets.
 pub trait Grid<T> {
     fn get(&self, x: usize, y: usize) -> Option<&T>;
     fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut T>;
     fn set(&mut self, x: usize, y: usize, val: T);
+
+    /// The number of columns in the grid.
+    fn width(&self) -> usize;
+
+    /// The number of rows in the grid.
+    fn height(&self) -> usize;
+
+    /// The x position of the grid's first column.
+    fn x_offset(&self) -> usize;
+
+    /// The y position of the grid's first row.
+    fn y_offset(&self) -> usize;
+
+    /// Returns an iterator over the positions of the grid's cells in row-major
+    /// order, starting at the grid's offsets.
+    fn position_iter(&self) -> PositionIter {
+        PositionIter::new(
+            self.width(),
+            self.height(),
+            self.x_offset(),
+            self.y_offset(),
+        )
+    }
+
+    /// Returns the cells of the row at the position, if the grid stores its
+    /// rows contiguously and the row lies in the grid. Passes over every cell
+    /// use it to avoid looking cells up one at a time.
+    fn row_slice(&self, _y: usize) -> Option<&[T]> {
+        None
+    }
+
+    /// Returns the cells of the row at the position, if the grid stores its
+    /// rows contiguously and the row lies in the grid.
+    fn row_slice_mut(&mut self, _y: usize) -> Option<&mut [T]> {
+        None
+    }
+
+    /// Sets every cell to the value. Grids that store their cells in a single
+    /// buffer override it to fill the buffer at once.
+    fn fill(&mut self, value: T)
+    where
+        T: Clone,
+    {
+        let x_offset = self.x_offset();
+        for y in self.y_offset()..self.y_offset() + self.height() {
+            if let Some(row) = self.row_slice_mut(y) {
+                row.fill(value.clone());
+                continue;
+            }
+            for x in x_offset..x_offset + self.width() {
+                self.set(x, y, value.clone());
+            }
+        }
+    }
+
+    fn in_bounds(&self, x: usize, y: usize) -> bool {
+        x >= self.x_offset()
+            && x < self.x_offset() + self.width()
+            && y >= self.y_offset()
+            && y < self.y_offset() + self.height()
+    }
+
+    /// Returns the neighbour that shares the face in the given direction with
+    /// the cell at the position, if the neighbour lies in the grid.
+    fn neighbour(&self, x: usize, y: usize, direction: Direction) -> Option<&T> {
+        let (x, y) = direction.step(x, y)?;
+        self.get(x, y)
+    }
+
+    /// Returns an iterator over the neighbours that share a face with the cell
+    /// at the position and lie in the grid, in the order of `Direction::ALL`.
+    fn neighbours4(&self, x: usize, y: usize) -> Neighbours<'_, T, Self> {
+        Neighbours::new(self, x, y, &NEIGHBOURHOOD_4)
+    }
+
+    /// Returns an iterator over the neighbours that share a face or a corner
+    /// with the cell at the position and lie in the grid, counterclockwise
+    /// from east.
+    fn neighbours8(&self, x: usize, y: usize) -> Neighbours<'_, T, Self> {
+        Neighbours::new(self, x, y, &NEIGHBOURHOOD_8)
+    }
 }
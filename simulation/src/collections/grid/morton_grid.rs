@@ -0,0 +1,149 @@
+use super::Grid;
+
+/// The width and height of each tile of a `MortonGrid`, in cells.
+const TILE_SIZE: usize = 8;
+
+/// The number of cells in each tile of a `MortonGrid`.
+const TILE_CELLS: usize = TILE_SIZE * TILE_SIZE;
+
+/// Grid in which cells are stored in square tiles of eight cells a side, with
+/// the tiles in row-major order and the cells of each tile in Z-order (Morton
+/// order). A cell's neighbours above and below are usually stored close to
+/// it, rather than a whole row apart as in a `RowMajorGrid`, which suits
+/// passes that read each cell's neighbours on wide grids.
+///
+/// Tiles are aligned to the grid's offsets. The tiles along the far edges
+/// hold padding cells beyond the grid if its dimensions aren't multiples of
+/// eight, which cannot be read or written.
+#[derive(Debug)]
+pub struct MortonGrid<T> {
+    inner_width: usize,
+    inner_height: usize,
+    x_offset: usize,
+    y_offset: usize,
+    cells: Vec<T>,
+}
+
+impl<T: Clone> MortonGrid<T> {
+    pub fn new(width: usize, height: usize, default: T) -> Self {
+        MortonGrid::new_sub_grid(width, height, 0, 0, default)
+    }
+
+    pub fn new_sub_grid(
+        inner_width: usize,
+        inner_height: usize,
+        x_offset: usize,
+        y_offset: usize,
+        default: T,
+    ) -> Self {
+        let tiles = tiles_along(inner_width) * tiles_along(inner_height);
+        MortonGrid {
+            inner_width,
+            inner_height,
+            x_offset,
+            y_offset,
+            cells: vec![default; tiles * TILE_CELLS],
+        }
+    }
+}
+
+impl<T> MortonGrid<T> {
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        let x = x.checked_sub(self.x_offset)?;
+        let y = y.checked_sub(self.y_offset)?;
+        if x >= self.inner_width || y >= self.inner_height {
+            return None;
+        }
+        let tile = (y / TILE_SIZE) * tiles_along(self.inner_width) + x / TILE_SIZE;
+        let cell = spread(x % TILE_SIZE) | spread(y % TILE_SIZE) << 1;
+        Some(tile * TILE_CELLS + cell)
+    }
+
+    /// Returns an iterator over the cells in the order they are stored, which
+    /// is faster than visiting them by position.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(move |&(i, _)| is_in_grid(i, self.inner_width, self.inner_height))
+            .map(|(_, cell)| cell)
+    }
+
+    /// Returns an iterator over mutable references to the cells in the order
+    /// they are stored, which is faster than visiting them by position.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        let (width, height) = (self.inner_width, self.inner_height);
+        self.cells
+            .iter_mut()
+            .enumerate()
+            .filter(move |(i, _)| is_in_grid(*i, width, height))
+            .map(|(_, cell)| cell)
+    }
+}
+
+impl<T> Grid<T> for MortonGrid<T> {
+    fn get(&self, x: usize, y: usize) -> Option<&T> {
+        self.index(x, y).map(|i| &self.cells[i])
+    }
+
+    fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
+        self.index(x, y).map(move |i| &mut self.cells[i])
+    }
+
+    fn set(&mut self, x: usize, y: usize, val: T) {
+        if let Some(i) = self.index(x, y) {
+            self.cells[i] = val
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.inner_width
+    }
+
+    fn height(&self) -> usize {
+        self.inner_height
+    }
+
+    fn x_offset(&self) -> usize {
+        self.x_offset
+    }
+
+    fn y_offset(&self) -> usize {
+        self.y_offset
+    }
+
+    /// Fills the padding cells too, since they can't be read.
+    fn fill(&mut self, value: T)
+    where
+        T: Clone,
+    {
+        self.cells.fill(value);
+    }
+}
+
+/// Returns the number of tiles needed to cover the given number of cells.
+fn tiles_along(cells: usize) -> usize {
+    cells.div_ceil(TILE_SIZE)
+}
+
+/// Returns whether the cell at the index lies in a grid with the given
+/// dimensions, rather than in the padding of a tile along its far edges.
+fn is_in_grid(index: usize, width: usize, height: usize) -> bool {
+    let tile = index / TILE_CELLS;
+    let cell = index % TILE_CELLS;
+    let tiles_wide = tiles_along(width);
+    let x = (tile % tiles_wide) * TILE_SIZE + compact(cell);
+    let y = (tile / tiles_wide) * TILE_SIZE + compact(cell >> 1);
+    x < width && y < height
+}
+
+/// Spreads the three bits of a coordinate in a tile out to every other bit.
+fn spread(coord: usize) -> usize {
+    (coord & 1) | (coord & 2) << 1 | (coord & 4) << 2
+}
+
+/// Gathers every other bit of a cell's index in a tile, the inverse of
+/// `spread`.
+fn compact(index: usize) -> usize {
+    (index & 1) | (index >> 1 & 2) | (index >> 2 & 4)
+}
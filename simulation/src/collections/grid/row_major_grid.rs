@@ -1,4 +1,6 @@
-use super::Grid;
+use super::{Grid, GridView, GridViewMut};
+use std::mem;
+use std::slice;
 
 /// Grid in which cells are stored in row-major order.
 #[derive(Debug)]
@@ -39,6 +41,24 @@ impl<T: Clone> RowMajorGrid<T> {
 }
 
 impl<T> RowMajorGrid<T> {
+    /// Creates a sub grid from its cells in row-major order.
+    pub(super) fn from_cells(
+        inner_width: usize,
+        inner_height: usize,
+        x_offset: usize,
+        y_offset: usize,
+        cells: Vec<T>,
+    ) -> Self {
+        debug_assert_eq!(cells.len(), inner_width * inner_height);
+        RowMajorGrid {
+            inner_width,
+            inner_height,
+            x_offset,
+            y_offset,
+            cells,
+        }
+    }
+
     fn index(&self, x: usize, y: usize) -> Option<usize> {
         pos_to_index(
             x,
@@ -50,15 +70,55 @@ impl<T> RowMajorGrid<T> {
         )
     }
 
-    pub fn in_bounds(&self, x: usize, y: usize) -> bool {
-        x >= self.x_offset
-            && x < self.x_offset + self.inner_width
-            && y >= self.y_offset
-            && y < self.y_offset + self.inner_height
+    /// Returns an iterator over the cells in row-major order, starting at the
+    /// sub grid's origin.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            cells: self.cells.iter(),
+        }
+    }
+
+    /// Returns an iterator that allows modifying each cell, in row-major order.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            cells: self.cells.iter_mut(),
+        }
+    }
+
+    /// Returns an iterator over the rows of the grid, from the row at the sub
+    /// grid's y offset upwards.
+    pub fn rows(&self) -> Rows<'_, T> {
+        Rows {
+            cells: &self.cells,
+            width: self.inner_width,
+            remaining: self.inner_height,
+        }
+    }
+
+    /// Returns an iterator that allows modifying each row of the grid.
+    pub fn rows_mut(&mut self) -> RowsMut<'_, T> {
+        RowsMut {
+            cells: &mut self.cells,
+            width: self.inner_width,
+            remaining: self.inner_height,
+        }
+    }
+
+    /// Returns an iterator over the cells in row-major order along with their
+    /// positions. Positions are absolute, so they include the sub grid's
+    /// offsets.
+    pub fn enumerate(&self) -> Enumerate<'_, T> {
+        Enumerate {
+            grid: self,
+            next_index: 0,
+        }
     }
 
-    pub fn position_iter(&self) -> PositionIter {
-        PositionIter::new(
+    /// Returns a view of the whole grid, which can be narrowed to a window or
+    /// split without copying any cells.
+    pub fn view(&self) -> GridView<'_, T> {
+        GridView::new(
+            &self.cells,
             self.inner_width,
             self.inner_height,
             self.x_offset,
@@ -66,15 +126,21 @@ impl<T> RowMajorGrid<T> {
         )
     }
 
-    pub fn row_iter(&self) -> RowIter<T> {
-        RowIter {
-            grid: &self,
-            prev_row: None,
-        }
+    /// Returns a mutable view of the whole grid, which can be narrowed to a
+    /// window or split into views of separate regions without copying any
+    /// cells.
+    pub fn view_mut(&mut self) -> GridViewMut<'_, T> {
+        GridViewMut::new(
+            &mut self.cells,
+            self.inner_width,
+            self.inner_height,
+            self.x_offset,
+            self.y_offset,
+        )
     }
 }
 
-impl<T: Clone> Grid<T> for RowMajorGrid<T> {
+impl<T> Grid<T> for RowMajorGrid<T> {
     fn get(&self, x: usize, y: usize) -> Option<&T> {
         if let Some(i) = self.index(x, y) {
             Some(&self.cells[i])
@@ -96,53 +162,132 @@ impl<T: Clone> Grid<T> for RowMajorGrid<T> {
             self.cells[i] = val
         }
     }
+
+    fn width(&self) -> usize {
+        self.inner_width
+    }
+
+    fn height(&self) -> usize {
+        self.inner_height
+    }
+
+    fn x_offset(&self) -> usize {
+        self.x_offset
+    }
+
+    fn y_offset(&self) -> usize {
+        self.y_offset
+    }
+
+    fn row_slice(&self, y: usize) -> Option<&[T]> {
+        let start = self.index(self.x_offset, y)?;
+        Some(&self.cells[start..start + self.inner_width])
+    }
+
+    fn row_slice_mut(&mut self, y: usize) -> Option<&mut [T]> {
+        let start = self.index(self.x_offset, y)?;
+        Some(&mut self.cells[start..start + self.inner_width])
+    }
+
+    fn fill(&mut self, value: T)
+    where
+        T: Clone,
+    {
+        self.cells.fill(value);
+    }
 }
 
+/// Iterator over the cells of a `RowMajorGrid`, created by `iter`.
 pub struct Iter<'a, T> {
-    // Iter must live as long as the grid.
-    grid: &'a RowMajorGrid<T>,
-    prev_index: Option<usize>,
+    cells: slice::Iter<'a, T>,
 }
 
 impl<'a, T> Iterator for Iter<'a, T> {
-    type Item = (usize, usize, &'a T);
+    type Item = &'a T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next_index = if let Some(index) = self.prev_index {
-            index + 1
-        } else {
-            0
-        };
-
-        let next_pos = index_to_pos(
-            next_index,
-            self.grid.inner_width,
-            self.grid.inner_height,
-            self.grid.x_offset,
-            self.grid.y_offset,
-        );
+        self.cells.next()
+    }
 
-        if let Some((x, y)) = next_pos {
-            self.prev_index = Some(next_index);
-            Some((x, y, &self.grid.cells[next_index]))
-        } else {
-            None
-        }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.cells.size_hint()
     }
 }
 
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
 impl<'a, T> IntoIterator for &'a RowMajorGrid<T> {
-    type Item = (usize, usize, &'a T);
+    type Item = &'a T;
     type IntoIter = Iter<'a, T>;
 
     fn into_iter(self) -> Self::IntoIter {
-        Iter {
-            grid: self,
-            prev_index: None,
-        }
+        self.iter()
+    }
+}
+
+/// Iterator over mutable references to the cells of a `RowMajorGrid`, created
+/// by `iter_mut`.
+pub struct IterMut<'a, T> {
+    cells: slice::IterMut<'a, T>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cells.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.cells.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for IterMut<'_, T> {}
+
+impl<'a, T> IntoIterator for &'a mut RowMajorGrid<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
     }
 }
 
+/// Iterator over the cells of a `RowMajorGrid` and their absolute positions,
+/// created by `enumerate`.
+pub struct Enumerate<'a, T> {
+    // Iter must live as long as the grid.
+    grid: &'a RowMajorGrid<T>,
+    next_index: usize,
+}
+
+impl<'a, T> Iterator for Enumerate<'a, T> {
+    type Item = (usize, usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (x, y) = index_to_pos(
+            self.next_index,
+            self.grid.inner_width,
+            self.grid.inner_height,
+            self.grid.x_offset,
+            self.grid.y_offset,
+        )?;
+        let cell = &self.grid.cells[self.next_index];
+        self.next_index += 1;
+        Some((x, y, cell))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.grid.cells.len() - self.next_index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for Enumerate<'_, T> {}
+
+/// Iterator over the positions of the cells of a grid, created by
+/// `Grid::position_iter`.
 pub struct PositionIter {
     inner_width: usize,
     inner_height: usize,
@@ -152,7 +297,12 @@ pub struct PositionIter {
 }
 
 impl PositionIter {
-    fn new(inner_width: usize, inner_height: usize, x_offset: usize, y_offset: usize) -> Self {
+    pub(super) fn new(
+        inner_width: usize,
+        inner_height: usize,
+        x_offset: usize,
+        y_offset: usize,
+    ) -> Self {
         PositionIter {
             inner_width,
             inner_height,
@@ -186,33 +336,62 @@ impl Iterator for PositionIter {
     }
 }
 
-pub struct RowIter<'a, T> {
-    // Iter must live as long as the grid.
-    grid: &'a RowMajorGrid<T>,
-    prev_row: Option<usize>,
+/// Iterator over the rows of a `RowMajorGrid`, created by `rows`.
+pub struct Rows<'a, T> {
+    cells: &'a [T],
+    width: usize,
+    remaining: usize,
 }
 
-impl<'a, T> Iterator for RowIter<'a, T> {
+impl<'a, T> Iterator for Rows<'a, T> {
     type Item = &'a [T];
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next_row = if let Some(row) = self.prev_row {
-            row + 1
-        } else {
-            0
-        };
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let (row, rest) = self.cells.split_at(self.width);
+        self.cells = rest;
+        Some(row)
+    }
 
-        if next_row < self.grid.inner_height {
-            self.prev_row = Some(next_row);
-            let start = next_row * self.grid.inner_width;
-            let end = start + self.grid.inner_width;
-            Some(&self.grid.cells[start..end])
-        } else {
-            None
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for Rows<'_, T> {}
+
+/// Iterator over mutable rows of a `RowMajorGrid`, created by `rows_mut`.
+pub struct RowsMut<'a, T> {
+    cells: &'a mut [T],
+    width: usize,
+    remaining: usize,
+}
+
+impl<'a, T> Iterator for RowsMut<'a, T> {
+    type Item = &'a mut [T];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
         }
+        self.remaining -= 1;
+        // Take the slice out of the iterator so that the row can outlive the
+        // borrow of `self`.
+        let (row, rest) = mem::take(&mut self.cells).split_at_mut(self.width);
+        self.cells = rest;
+        Some(row)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
     }
 }
 
+impl<T> ExactSizeIterator for RowsMut<'_, T> {}
+
 /// Calcuate the x and y position that correspondes to the given index.
 fn index_to_pos(
     index: usize,
@@ -222,12 +401,12 @@ fn index_to_pos(
     y_offset: usize,
 ) -> Option<(usize, usize)> {
     if index < inner_width * inner_height {
-        None
-    } else {
         Some((
             index % inner_width + x_offset,
-            index / inner_height + y_offset,
+            index / inner_width + y_offset,
         ))
+    } else {
+        None
     }
 }
 
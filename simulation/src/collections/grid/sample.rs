@@ -0,0 +1,173 @@
+use super::Grid;
+
+/// Values that can be interpolated between cells.
+pub trait Interpolate: Copy {
+    fn zero() -> Self;
+
+    /// Returns `self + other * weight`.
+    fn add_scaled(self, other: Self, weight: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn zero() -> Self {
+        0.0
+    }
+
+    fn add_scaled(self, other: Self, weight: f32) -> Self {
+        self + other * weight
+    }
+}
+
+impl Interpolate for (f32, f32) {
+    fn zero() -> Self {
+        (0.0, 0.0)
+    }
+
+    fn add_scaled(self, other: Self, weight: f32) -> Self {
+        (self.0 + other.0 * weight, self.1 + other.1 * weight)
+    }
+}
+
+/// What a sample reads for cells beyond the edges of the grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    /// Cells beyond an edge repeat the nearest cell on the edge.
+    Clamp,
+
+    /// Cells beyond an edge are zero.
+    Zero,
+}
+
+/// Sampling of a grid at fractional positions. The cell at (x, y) covers the
+/// square from (x, y) to (x + 1, y + 1), and its value lies at its center,
+/// (x + 0.5, y + 0.5). Sampling an empty grid gives zero.
+///
+/// The `_by` methods sample a value projected out of each cell, such as one
+/// field of a struct.
+pub trait Sample<C>: Grid<C> {
+    /// Returns the value of the cell that contains the position.
+    fn sample_nearest_by<V, F>(&self, x: f32, y: f32, edge: Edge, project: F) -> V
+    where
+        V: Interpolate,
+        F: Fn(&C) -> V,
+    {
+        cell_value(self, x.floor() as isize, y.floor() as isize, edge, &project)
+    }
+
+    /// Interpolates linearly between the four cells whose centers surround the
+    /// position.
+    fn sample_bilinear_by<V, F>(&self, x: f32, y: f32, edge: Edge, project: F) -> V
+    where
+        V: Interpolate,
+        F: Fn(&C) -> V,
+    {
+        let (x0, tx) = split(x - 0.5);
+        let (y0, ty) = split(y - 0.5);
+        let weights_x = [1.0 - tx, tx];
+        let weights_y = [1.0 - ty, ty];
+        let mut sum = V::zero();
+        for (j, weight_y) in weights_y.iter().enumerate() {
+            for (i, weight_x) in weights_x.iter().enumerate() {
+                let value = cell_value(self, x0 + i as isize, y0 + j as isize, edge, &project);
+                sum = sum.add_scaled(value, weight_x * weight_y);
+            }
+        }
+        sum
+    }
+
+    /// Interpolates between the sixteen cells whose centers surround the
+    /// position with a Catmull-Rom spline, which passes through every cell's
+    /// value and is smooth across cells. Unlike bilinear sampling it can
+    /// overshoot the values of the surrounding cells.
+    fn sample_bicubic_by<V, F>(&self, x: f32, y: f32, edge: Edge, project: F) -> V
+    where
+        V: Interpolate,
+        F: Fn(&C) -> V,
+    {
+        let (x0, tx) = split(x - 0.5);
+        let (y0, ty) = split(y - 0.5);
+        let weights_x = catmull_rom_weights(tx);
+        let weights_y = catmull_rom_weights(ty);
+        let mut sum = V::zero();
+        for (j, weight_y) in weights_y.iter().enumerate() {
+            for (i, weight_x) in weights_x.iter().enumerate() {
+                let value = cell_value(
+                    self,
+                    x0 + i as isize - 1,
+                    y0 + j as isize - 1,
+                    edge,
+                    &project,
+                );
+                sum = sum.add_scaled(value, weight_x * weight_y);
+            }
+        }
+        sum
+    }
+
+    fn sample_nearest(&self, x: f32, y: f32, edge: Edge) -> C
+    where
+        C: Interpolate,
+    {
+        self.sample_nearest_by(x, y, edge, |&cell| cell)
+    }
+
+    fn sample_bilinear(&self, x: f32, y: f32, edge: Edge) -> C
+    where
+        C: Interpolate,
+    {
+        self.sample_bilinear_by(x, y, edge, |&cell| cell)
+    }
+
+    fn sample_bicubic(&self, x: f32, y: f32, edge: Edge) -> C
+    where
+        C: Interpolate,
+    {
+        self.sample_bicubic_by(x, y, edge, |&cell| cell)
+    }
+}
+
+impl<C, G: Grid<C> + ?Sized> Sample<C> for G {}
+
+/// Splits a coordinate into the cell before it and the fraction of the way
+/// to the next cell.
+fn split(coord: f32) -> (isize, f32) {
+    let floor = coord.floor();
+    (floor as isize, coord - floor)
+}
+
+/// Weights of the cells at offsets -1, 0, 1 and 2 for a point that lies a
+/// fraction `t` of the way from the cell at 0 to the cell at 1.
+fn catmull_rom_weights(t: f32) -> [f32; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        (-t3 + 2.0 * t2 - t) / 2.0,
+        (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+        (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+        (t3 - t2) / 2.0,
+    ]
+}
+
+/// Returns the projected value of the cell at the position, applying the edge
+/// behaviour if the cell lies beyond the edges of the grid.
+pub(super) fn cell_value<C, V, G, F>(grid: &G, x: isize, y: isize, edge: Edge, project: &F) -> V
+where
+    V: Interpolate,
+    G: Grid<C> + ?Sized,
+    F: Fn(&C) -> V,
+{
+    if grid.width() == 0 || grid.height() == 0 {
+        return V::zero();
+    }
+    let min_x = grid.x_offset() as isize;
+    let min_y = grid.y_offset() as isize;
+    let max_x = min_x + grid.width() as isize - 1;
+    let max_y = min_y + grid.height() as isize - 1;
+    let (x, y) = match edge {
+        Edge::Clamp => (x.clamp(min_x, max_x), y.clamp(min_y, max_y)),
+        Edge::Zero if x < min_x || x > max_x || y < min_y || y > max_y => return V::zero(),
+        Edge::Zero => (x, y),
+    };
+    grid.get(x as usize, y as usize)
+        .map_or_else(V::zero, project)
+}
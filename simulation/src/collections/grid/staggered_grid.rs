@@ -0,0 +1,142 @@
+use super::{Direction, Grid, RowMajorGrid};
+
+/// Grid that stores a value on each face between cells, rather than on the
+/// cells themselves. Each face is stored once and is shared by the two cells
+/// on either side of it.
+///
+/// Faces crossed when moving east or west are vertical faces. The vertical face
+/// at (x, y) is the west face of cell (x, y) and the east face of cell
+/// (x - 1, y). Faces crossed when moving north or south are horizontal faces.
+/// The horizontal face at (x, y) is the south face of cell (x, y) and the north
+/// face of cell (x, y - 1).
+#[derive(Debug)]
+pub struct StaggeredGrid<T> {
+    inner_width: usize,
+    inner_height: usize,
+    x_offset: usize,
+    y_offset: usize,
+    vertical: RowMajorGrid<T>,
+    horizontal: RowMajorGrid<T>,
+}
+
+impl<T: Clone> StaggeredGrid<T> {
+    /// Creates the faces of a grid of `width` by `height` cells.
+    pub fn new(width: usize, height: usize, default: T) -> Self {
+        StaggeredGrid::new_sub_grid(width, height, 0, 0, default)
+    }
+
+    /// Creates the faces of a sub grid of `inner_width` by `inner_height`
+    /// cells whose first cell is at the given offsets.
+    pub fn new_sub_grid(
+        inner_width: usize,
+        inner_height: usize,
+        x_offset: usize,
+        y_offset: usize,
+        default: T,
+    ) -> Self {
+        StaggeredGrid {
+            inner_width,
+            inner_height,
+            x_offset,
+            y_offset,
+            vertical: RowMajorGrid::new_sub_grid(
+                inner_width + 1,
+                inner_height,
+                x_offset,
+                y_offset,
+                default.clone(),
+            ),
+            horizontal: RowMajorGrid::new_sub_grid(
+                inner_width,
+                inner_height + 1,
+                x_offset,
+                y_offset,
+                default,
+            ),
+        }
+    }
+}
+
+impl<T> StaggeredGrid<T> {
+    /// The number of columns of cells.
+    pub fn width(&self) -> usize {
+        self.inner_width
+    }
+
+    /// The number of rows of cells.
+    pub fn height(&self) -> usize {
+        self.inner_height
+    }
+
+    pub fn x_offset(&self) -> usize {
+        self.x_offset
+    }
+
+    pub fn y_offset(&self) -> usize {
+        self.y_offset
+    }
+
+    /// Returns whether the cell lies in the grid.
+    pub fn in_bounds(&self, x: usize, y: usize) -> bool {
+        x >= self.x_offset
+            && x < self.x_offset + self.inner_width
+            && y >= self.y_offset
+            && y < self.y_offset + self.inner_height
+    }
+
+    /// Returns the face of the cell at the position in the given direction, or
+    /// `None` if the cell doesn't lie in the grid.
+    pub fn face(&self, x: usize, y: usize, direction: Direction) -> Option<&T> {
+        if !self.in_bounds(x, y) {
+            return None;
+        }
+        match direction {
+            Direction::East => self.vertical.get(x + 1, y),
+            Direction::North => self.horizontal.get(x, y + 1),
+            Direction::West => self.vertical.get(x, y),
+            Direction::South => self.horizontal.get(x, y),
+        }
+    }
+
+    /// Returns the face of the cell at the position in the given direction
+    /// mutably, or `None` if the cell doesn't lie in the grid.
+    pub fn face_mut(&mut self, x: usize, y: usize, direction: Direction) -> Option<&mut T> {
+        if !self.in_bounds(x, y) {
+            return None;
+        }
+        match direction {
+            Direction::East => self.vertical.get_mut(x + 1, y),
+            Direction::North => self.horizontal.get_mut(x, y + 1),
+            Direction::West => self.vertical.get_mut(x, y),
+            Direction::South => self.horizontal.get_mut(x, y),
+        }
+    }
+
+    /// Sets the face of the cell at the position in the given direction. Does
+    /// nothing if the cell doesn't lie in the grid.
+    pub fn set_face(&mut self, x: usize, y: usize, direction: Direction, val: T) {
+        if let Some(face) = self.face_mut(x, y, direction) {
+            *face = val;
+        }
+    }
+
+    /// The faces crossed when moving east or west, one column wider than the
+    /// grid of cells.
+    pub fn vertical_faces(&self) -> &RowMajorGrid<T> {
+        &self.vertical
+    }
+
+    pub fn vertical_faces_mut(&mut self) -> &mut RowMajorGrid<T> {
+        &mut self.vertical
+    }
+
+    /// The faces crossed when moving north or south, one row taller than the
+    /// grid of cells.
+    pub fn horizontal_faces(&self) -> &RowMajorGrid<T> {
+        &self.horizontal
+    }
+
+    pub fn horizontal_faces_mut(&mut self) -> &mut RowMajorGrid<T> {
+        &mut self.horizontal
+    }
+}
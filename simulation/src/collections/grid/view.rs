@@ -0,0 +1,469 @@
+use super::Grid;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+use std::slice;
+
+/// Borrowed window of a `RowMajorGrid`, created by `RowMajorGrid::view`. Its
+/// cells are addressed by their absolute positions, as in the grid it views.
+/// Splitting a view borrows the same cells rather than copying them.
+#[derive(Debug)]
+pub struct GridView<'a, T> {
+    /// The cells from the window's first cell to its last, including the cells
+    /// outside the window at the ends of its rows.
+    cells: &'a [T],
+    stride: usize,
+    width: usize,
+    height: usize,
+    x_offset: usize,
+    y_offset: usize,
+}
+
+impl<T> Clone for GridView<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for GridView<'_, T> {}
+
+impl<'a, T> GridView<'a, T> {
+    pub(super) fn new(
+        cells: &'a [T],
+        width: usize,
+        height: usize,
+        x_offset: usize,
+        y_offset: usize,
+    ) -> Self {
+        GridView {
+            cells,
+            stride: width,
+            width,
+            height,
+            x_offset,
+            y_offset,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<&'a T> {
+        if self.in_bounds(x, y) {
+            Some(&self.cells[(y - self.y_offset) * self.stride + x - self.x_offset])
+        } else {
+            None
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn x_offset(&self) -> usize {
+        self.x_offset
+    }
+
+    pub fn y_offset(&self) -> usize {
+        self.y_offset
+    }
+
+    pub fn in_bounds(&self, x: usize, y: usize) -> bool {
+        x >= self.x_offset
+            && x < self.x_offset + self.width
+            && y >= self.y_offset
+            && y < self.y_offset + self.height
+    }
+
+    /// Returns the row at the position, if it lies in the view.
+    pub fn row(&self, y: usize) -> Option<&'a [T]> {
+        if y < self.y_offset || y >= self.y_offset + self.height {
+            return None;
+        }
+        let start = (y - self.y_offset) * self.stride;
+        Some(&self.cells[start..start + self.width])
+    }
+
+    /// Returns an iterator over the rows of the view, from its first row.
+    pub fn rows(&self) -> impl Iterator<Item = &'a [T]> + 'a {
+        let view = *self;
+        (view.y_offset..view.y_offset + view.height).filter_map(move |y| view.row(y))
+    }
+
+    /// Returns the view of the window with the given offsets and dimensions,
+    /// or `None` if the window does not lie in this view.
+    pub fn window(&self, x: usize, y: usize, width: usize, height: usize) -> Option<Self> {
+        if !window_in_bounds(
+            (self.x_offset, self.y_offset, self.width, self.height),
+            x,
+            y,
+            width,
+            height,
+        ) {
+            return None;
+        }
+        let cells = if width == 0 || height == 0 {
+            &[]
+        } else {
+            let start = (y - self.y_offset) * self.stride + x - self.x_offset;
+            &self.cells[start..start + (height - 1) * self.stride + width]
+        };
+        Some(GridView {
+            cells,
+            stride: self.stride,
+            width,
+            height,
+            x_offset: x,
+            y_offset: y,
+        })
+    }
+
+    /// Splits the view into the rows before the row at `y` and the rows from
+    /// it onwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `y` is not between the view's first row and one past its
+    /// last row.
+    pub fn split_at_row(&self, y: usize) -> (Self, Self) {
+        let rows = split_count(y, self.y_offset, self.height, "row");
+        let (x, width) = (self.x_offset, self.width);
+        (
+            self.window(x, self.y_offset, width, rows).unwrap(),
+            self.window(x, y, width, self.height - rows).unwrap(),
+        )
+    }
+
+    /// Splits the view into the columns before the column at `x` and the
+    /// columns from it onwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `x` is not between the view's first column and one past its
+    /// last column.
+    pub fn split_at_column(&self, x: usize) -> (Self, Self) {
+        let columns = split_count(x, self.x_offset, self.width, "column");
+        let (y, height) = (self.y_offset, self.height);
+        (
+            self.window(self.x_offset, y, columns, height).unwrap(),
+            self.window(x, y, self.width - columns, height).unwrap(),
+        )
+    }
+
+    /// Splits the view into tiles of the given dimensions, in row-major order.
+    /// Tiles at the far edges are smaller if the view's dimensions are not
+    /// multiples of the tile's.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either of the tile's dimensions is zero.
+    pub fn tiles(&self, tile_width: usize, tile_height: usize) -> Vec<Self> {
+        assert!(tile_width > 0 && tile_height > 0, "tiles must not be empty");
+        let mut tiles = Vec::new();
+        for y in (self.y_offset..self.y_offset + self.height).step_by(tile_height) {
+            let height = tile_height.min(self.y_offset + self.height - y);
+            for x in (self.x_offset..self.x_offset + self.width).step_by(tile_width) {
+                let width = tile_width.min(self.x_offset + self.width - x);
+                tiles.push(self.window(x, y, width, height).unwrap());
+            }
+        }
+        tiles
+    }
+}
+
+/// Mutably borrowed window of a `RowMajorGrid`, created by
+/// `RowMajorGrid::view_mut`. Its cells are addressed by their absolute
+/// positions, as in the grid it views.
+///
+/// Splitting a view divides its cells between views that don't overlap, so
+/// that separate regions of a grid can be updated at the same time, such as
+/// from different threads.
+pub struct GridViewMut<'a, T> {
+    /// The window's first cell. Each row starts `stride` cells after the one
+    /// before it. The cells between the end of one row and the start of the
+    /// next may belong to other views, so they are never touched, which is
+    /// why the view can't hold a slice of its cells as `GridView` does.
+    cells: NonNull<T>,
+    stride: usize,
+    width: usize,
+    height: usize,
+    x_offset: usize,
+    y_offset: usize,
+    marker: PhantomData<&'a mut T>,
+}
+
+// A view is as good as a mutable borrow of each of its cells.
+unsafe impl<T: Send> Send for GridViewMut<'_, T> {}
+unsafe impl<T: Sync> Sync for GridViewMut<'_, T> {}
+
+impl<'a, T> GridViewMut<'a, T> {
+    pub(super) fn new(
+        cells: &'a mut [T],
+        width: usize,
+        height: usize,
+        x_offset: usize,
+        y_offset: usize,
+    ) -> Self {
+        assert_eq!(cells.len(), width * height);
+        GridViewMut {
+            cells: NonNull::from(cells).cast(),
+            stride: width,
+            width,
+            height,
+            x_offset,
+            y_offset,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the row at the position, if it lies in the view.
+    pub fn row_mut(&mut self, y: usize) -> Option<&mut [T]> {
+        let i = y.checked_sub(self.y_offset)?;
+        if i < self.height {
+            Some(self.nth_row_mut(i))
+        } else {
+            None
+        }
+    }
+
+    /// Returns an iterator over the rows of the view, from its first row.
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> + '_ {
+        (0..self.height).map(move |i| self.nth_row(i))
+    }
+
+    /// Returns an iterator over the rows of the view, from its first row.
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [T]> + use<'_, 'a, T> {
+        let view = self.reborrow();
+        // Each row is yielded once and rows don't overlap, so no cell is
+        // borrowed twice while the view is.
+        (0..view.height)
+            .map(move |i| unsafe { slice::from_raw_parts_mut(view.row_start(i), view.width) })
+    }
+
+    /// Reborrows the view, so that it can be split without giving it up.
+    pub fn reborrow(&mut self) -> GridViewMut<'_, T> {
+        GridViewMut {
+            marker: PhantomData,
+            ..*self
+        }
+    }
+
+    /// Returns the view of the window with the given offsets and dimensions,
+    /// or `None` if the window does not lie in this view.
+    pub fn window(
+        self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<GridViewMut<'a, T>> {
+        if !window_in_bounds(
+            (self.x_offset, self.y_offset, self.width, self.height),
+            x,
+            y,
+            width,
+            height,
+        ) {
+            return None;
+        }
+        let start = (y - self.y_offset) * self.stride + x - self.x_offset;
+        Some(GridViewMut {
+            cells: self.offset(start),
+            width,
+            height,
+            x_offset: x,
+            y_offset: y,
+            ..self
+        })
+    }
+
+    /// Splits the view into the rows before the row at `y` and the rows from
+    /// it onwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `y` is not between the view's first row and one past its
+    /// last row.
+    pub fn split_at_row(self, y: usize) -> (GridViewMut<'a, T>, GridViewMut<'a, T>) {
+        let rows = split_count(y, self.y_offset, self.height, "row");
+        let after = GridViewMut {
+            cells: self.offset(rows * self.stride),
+            height: self.height - rows,
+            y_offset: y,
+            marker: PhantomData,
+            ..self
+        };
+        (
+            GridViewMut {
+                height: rows,
+                ..self
+            },
+            after,
+        )
+    }
+
+    /// Splits the view into the columns before the column at `x` and the
+    /// columns from it onwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `x` is not between the view's first column and one past its
+    /// last column.
+    pub fn split_at_column(self, x: usize) -> (GridViewMut<'a, T>, GridViewMut<'a, T>) {
+        let columns = split_count(x, self.x_offset, self.width, "column");
+        let after = GridViewMut {
+            cells: self.offset(columns),
+            width: self.width - columns,
+            x_offset: x,
+            marker: PhantomData,
+            ..self
+        };
+        (
+            GridViewMut {
+                width: columns,
+                ..self
+            },
+            after,
+        )
+    }
+
+    /// Splits the view into tiles of the given dimensions, in row-major order.
+    /// Tiles at the far edges are smaller if the view's dimensions are not
+    /// multiples of the tile's.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either of the tile's dimensions is zero.
+    pub fn tiles(self, tile_width: usize, tile_height: usize) -> Vec<GridViewMut<'a, T>> {
+        assert!(tile_width > 0 && tile_height > 0, "tiles must not be empty");
+        let mut tiles = Vec::new();
+        let mut band = self;
+        while band.height > 0 {
+            let split = band.y_offset + tile_height.min(band.height);
+            let (mut columns, rest) = band.split_at_row(split);
+            while columns.width > 0 {
+                let split = columns.x_offset + tile_width.min(columns.width);
+                let (tile, rest) = columns.split_at_column(split);
+                tiles.push(tile);
+                columns = rest;
+            }
+            band = rest;
+        }
+        tiles
+    }
+
+    /// Returns a pointer `count` cells after the view's first cell. It is only
+    /// dereferenced if it points to a cell of a non-empty view, which lies in
+    /// the borrowed cells.
+    fn offset(&self, count: usize) -> NonNull<T> {
+        let cells = self.cells.as_ptr().wrapping_add(count);
+        NonNull::new(cells).unwrap_or(self.cells)
+    }
+
+    /// Returns the `i`th row of the view, counted from its first row.
+    fn nth_row(&self, i: usize) -> &[T] {
+        // The row can't be written through this view while it's borrowed.
+        unsafe { slice::from_raw_parts(self.row_start(i), self.width) }
+    }
+
+    /// Returns the `i`th row of the view, counted from its first row.
+    fn nth_row_mut(&mut self, i: usize) -> &mut [T] {
+        // The row can't be reached through this view while it's borrowed.
+        unsafe { slice::from_raw_parts_mut(self.row_start(i), self.width) }
+    }
+
+    /// Returns a pointer to the first cell of the `i`th row of the view. The
+    /// row's cells lie in the cells that the view borrows, and no other view
+    /// can reach them, unless the view has no columns.
+    fn row_start(&self, i: usize) -> *mut T {
+        assert!(i < self.height);
+        self.offset(i * self.stride).as_ptr()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for GridViewMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GridViewMut")
+            .field("rows", &self.rows().collect::<Vec<_>>())
+            .field("width", &self.width)
+            .field("x_offset", &self.x_offset)
+            .field("y_offset", &self.y_offset)
+            .finish()
+    }
+}
+
+impl<T> Grid<T> for GridViewMut<'_, T> {
+    fn get(&self, x: usize, y: usize) -> Option<&T> {
+        self.row_slice(y)?.get(x.checked_sub(self.x_offset)?)
+    }
+
+    fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
+        let j = x.checked_sub(self.x_offset)?;
+        self.row_mut(y)?.get_mut(j)
+    }
+
+    fn set(&mut self, x: usize, y: usize, val: T) {
+        if let Some(cell) = self.get_mut(x, y) {
+            *cell = val;
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn x_offset(&self) -> usize {
+        self.x_offset
+    }
+
+    fn y_offset(&self) -> usize {
+        self.y_offset
+    }
+
+    fn row_slice(&self, y: usize) -> Option<&[T]> {
+        let i = y.checked_sub(self.y_offset)?;
+        if i < self.height {
+            Some(self.nth_row(i))
+        } else {
+            None
+        }
+    }
+
+    fn row_slice_mut(&mut self, y: usize) -> Option<&mut [T]> {
+        self.row_mut(y)
+    }
+}
+
+/// Returns whether the window with the given offsets and dimensions lies in
+/// a view with the offsets and dimensions in `bounds`. Only differences are
+/// taken, so windows at the far end of `usize` don't wrap around into bounds.
+fn window_in_bounds(
+    (x_offset, y_offset, view_width, view_height): (usize, usize, usize, usize),
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> bool {
+    let fits = |at: usize, len: usize, offset: usize, view_len: usize| {
+        at >= offset && at - offset <= view_len && len <= view_len - (at - offset)
+    };
+    fits(x, width, x_offset, view_width) && fits(y, height, y_offset, view_height)
+}
+
+/// Returns the number of rows or columns before the one at `at`.
+fn split_count(at: usize, offset: usize, len: usize, axis: &str) -> usize {
+    assert!(
+        at >= offset && at <= offset + len,
+        "cannot split at {} {} of a view covering {}..{}",
+        axis,
+        at,
+        offset,
+        offset + len
+    );
+    at - offset
+}
@@ -1,4 +1,4 @@
-///! ECS components for the simulation.
+//! ECS components for the simulation.
 use specs::{Component, VecStorage};
 
 #[derive(Component, Debug)]
@@ -14,3 +14,8 @@ pub struct Velocity {
     pub x: f32,
     pub y: f32,
 }
+
+/// The index of the continuum crowds group that an agent belongs to.
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(VecStorage)]
+pub struct Group(pub usize);
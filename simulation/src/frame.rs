@@ -3,7 +3,7 @@ use std::time::{Duration, Instant};
 // A counter that tracks the index and timing of a fixed length frame.
 #[derive(Clone, Copy, Debug)]
 pub struct Frame {
-    /// Ideal duration of a fixed length frame in milliseconds.
+    /// Ideal duration of a fixed length frame.
     pub ideal_duration: Duration,
 
     /// The index of the frame.
@@ -29,8 +29,8 @@ impl Frame {
     }
 
     pub fn next(&self, start_time: Instant) -> Frame {
-        let elapsed_frames_count = ((start_time - self.ideal_start_time).as_millis()
-            / self.ideal_duration.as_millis()) as u32;
+        let elapsed_frames_count = ((start_time - self.ideal_start_time).as_nanos()
+            / self.ideal_duration.as_nanos()) as u32;
         Frame {
             ideal_duration: self.ideal_duration,
             index: self.index + elapsed_frames_count as u64,
@@ -0,0 +1,155 @@
+//! Timing of the systems run by a dispatcher. Systems added with
+//! `WithTimed::with_timed` are only timed when the `profiling` feature is
+//! enabled; otherwise they're added as is and cost nothing extra.
+#[cfg(feature = "profiling")]
+use specs::shred::{AccessorCow, RunningTime};
+#[cfg(feature = "profiling")]
+use specs::World;
+use specs::{DispatcherBuilder, System};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(feature = "profiling")]
+use std::time::Instant;
+
+/// The number of recent runs of each system that rolling stats cover.
+pub const ROLLING_WINDOW: usize = 256;
+
+/// Recent run times of each timed system, keyed by the name it was registered
+/// with. Clones share the same timings.
+#[derive(Clone, Debug, Default)]
+pub struct SystemTimings(Arc<Mutex<BTreeMap<&'static str, VecDeque<Duration>>>>);
+
+/// Stats of a system's run times over its last `samples` runs.
+#[derive(Clone, Copy, Debug)]
+pub struct SystemStats {
+    pub name: &'static str,
+    pub samples: usize,
+    pub min: Duration,
+    pub mean: Duration,
+    pub p99: Duration,
+}
+
+impl SystemTimings {
+    pub fn record(&self, name: &'static str, duration: Duration) {
+        let mut timings = self.0.lock().unwrap();
+        let samples = timings.entry(name).or_default();
+        if samples.len() == ROLLING_WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(duration);
+    }
+
+    /// Returns how long each timed system took the last time it ran, ordered
+    /// by name.
+    pub fn durations(&self) -> Vec<(&'static str, Duration)> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(&name, samples)| Some((name, *samples.back()?)))
+            .collect()
+    }
+
+    /// Returns the stats of each timed system over its last `ROLLING_WINDOW`
+    /// runs, ordered by name.
+    pub fn stats(&self) -> Vec<SystemStats> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, samples)| !samples.is_empty())
+            .map(|(&name, samples)| {
+                let mut sorted: Vec<Duration> = samples.iter().copied().collect();
+                sorted.sort_unstable();
+                let p99_index = (sorted.len() * 99).div_ceil(100) - 1;
+                SystemStats {
+                    name,
+                    samples: sorted.len(),
+                    min: sorted[0],
+                    mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+                    p99: sorted[p99_index],
+                }
+            })
+            .collect()
+    }
+}
+
+/// Wraps a system and records how long it takes to run in the world's
+/// `SystemTimings`. The timings are shared with the wrapper when it's set up
+/// rather than fetched each frame, so wrapping a system doesn't change which
+/// systems it can run in parallel with.
+#[cfg(feature = "profiling")]
+pub struct Timed<S> {
+    name: &'static str,
+    system: S,
+    timings: Option<SystemTimings>,
+}
+
+#[cfg(feature = "profiling")]
+impl<S> Timed<S> {
+    pub fn new(system: S, name: &'static str) -> Timed<S> {
+        Timed {
+            name,
+            system,
+            timings: None,
+        }
+    }
+}
+
+#[cfg(feature = "profiling")]
+impl<'a, S> System<'a> for Timed<S>
+where
+    S: System<'a>,
+{
+    type SystemData = S::SystemData;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let start = Instant::now();
+        self.system.run(data);
+        if let Some(timings) = &self.timings {
+            timings.record(self.name, start.elapsed());
+        }
+    }
+
+    fn running_time(&self) -> RunningTime {
+        self.system.running_time()
+    }
+
+    fn accessor<'b>(&'b self) -> AccessorCow<'a, 'b, Self> {
+        match self.system.accessor() {
+            AccessorCow::Ref(accessor) => AccessorCow::Ref(accessor),
+            AccessorCow::Owned(accessor) => AccessorCow::Owned(accessor),
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        self.system.setup(world);
+        let timings = world
+            .entry::<SystemTimings>()
+            .or_insert_with(Default::default);
+        self.timings = Some(timings.clone());
+    }
+}
+
+/// Adds timed systems to a `DispatcherBuilder`.
+pub trait WithTimed<'a> {
+    /// Adds a system like `DispatcherBuilder::with`, timing it under `name`
+    /// if profiling is enabled.
+    fn with_timed<S>(self, system: S, name: &'static str, dep: &[&str]) -> Self
+    where
+        S: for<'c> System<'c> + Send + 'a;
+}
+
+impl<'a, 'b> WithTimed<'a> for DispatcherBuilder<'a, 'b> {
+    fn with_timed<S>(self, system: S, name: &'static str, dep: &[&str]) -> Self
+    where
+        S: for<'c> System<'c> + Send + 'a,
+    {
+        #[cfg(feature = "profiling")]
+        let builder = self.with(Timed::new(system, name), name, dep);
+        #[cfg(not(feature = "profiling"))]
+        let builder = self.with(system, name, dep);
+        builder
+    }
+}
@@ -1,27 +1,122 @@
+#[cfg(feature = "morton-crowd-grids")]
+use crate::collections::grid::MortonGrid;
+#[cfg(not(feature = "morton-crowd-grids"))]
 use crate::collections::grid::RowMajorGrid;
+use crate::collections::grid::{Grid, StaggeredGrid};
+use std::collections::HashMap;
 
+/// The layout of `SharedGrid` and of each grid in `GroupGrids`. It is
+/// row-major unless the `morton-crowd-grids` feature is enabled, which
+/// switches it to `MortonGrid`. Compare the two with the `crowd_layout` and
+/// `crowd_pipeline` benchmarks.
+#[cfg(not(feature = "morton-crowd-grids"))]
+pub type CrowdGrid<T> = RowMajorGrid<T>;
+
+/// The layout of `SharedGrid` and of each grid in `GroupGrids`, switched to
+/// Morton order by the `morton-crowd-grids` feature.
+#[cfg(feature = "morton-crowd-grids")]
+pub type CrowdGrid<T> = MortonGrid<T>;
+
+/// The cells shared by every group, with each field of the cells in a grid
+/// of its own, so that a pass over some of the fields only touches their
+/// memory. `cell` and `set_cell` read and write every field of a cell at once.
 #[derive(Debug)]
-pub struct SharedGrid(pub RowMajorGrid<SharedCell>);
+pub struct SharedGrid {
+    pub density: CrowdGrid<f32>,
+    pub height: CrowdGrid<f32>, // Only set to zero for now.
+    pub discomfort: CrowdGrid<f32>,
+    pub is_obstacle: CrowdGrid<bool>,
+    pub avg_velocity: CrowdGrid<(f32, f32)>,
+}
+
+impl SharedGrid {
+    /// Returns a grid whose cells all hold the default `SharedCell`.
+    pub fn new(width: usize, height: usize) -> Self {
+        SharedGrid {
+            density: CrowdGrid::new(width, height, 0.0),
+            height: CrowdGrid::new(width, height, 0.0),
+            discomfort: CrowdGrid::new(width, height, 0.0),
+            is_obstacle: CrowdGrid::new(width, height, false),
+            avg_velocity: CrowdGrid::new(width, height, (0.0, 0.0)),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.density.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.density.height()
+    }
+
+    pub fn in_bounds(&self, x: usize, y: usize) -> bool {
+        self.density.in_bounds(x, y)
+    }
+
+    /// Returns a copy of every field of the cell at the position.
+    pub fn cell(&self, x: usize, y: usize) -> Option<SharedCell> {
+        Some(SharedCell {
+            density: *self.density.get(x, y)?,
+            height: *self.height.get(x, y)?,
+            discomfort: *self.discomfort.get(x, y)?,
+            is_obstacle: *self.is_obstacle.get(x, y)?,
+            avg_velocity: *self.avg_velocity.get(x, y)?,
+        })
+    }
+
+    /// Sets every field of the cell at the position, if it lies in the grid.
+    pub fn set_cell(&mut self, x: usize, y: usize, cell: SharedCell) {
+        self.density.set(x, y, cell.density);
+        self.height.set(x, y, cell.height);
+        self.discomfort.set(x, y, cell.discomfort);
+        self.is_obstacle.set(x, y, cell.is_obstacle);
+        self.avg_velocity.set(x, y, cell.avg_velocity);
+    }
+}
 
 #[derive(Debug)]
 pub struct GroupGrids(
-    pub RowMajorGrid<GroupCell>,
-    pub RowMajorGrid<GroupCell>,
-    pub RowMajorGrid<GroupCell>,
-    pub RowMajorGrid<GroupCell>,
+    pub CrowdGrid<GroupCell>,
+    pub CrowdGrid<GroupCell>,
+    pub CrowdGrid<GroupCell>,
+    pub CrowdGrid<GroupCell>,
 );
 
-#[derive(Debug, Default, Clone)]
+/// Values on the faces between the cells of `SharedGrid`.
+#[derive(Debug)]
+pub struct SharedFaces(pub StaggeredGrid<SharedCellFace>);
+
+/// Values on the faces between the cells of each group's grid in `GroupGrids`.
+#[derive(Debug)]
+pub struct GroupFaces(
+    pub StaggeredGrid<GroupCellFace>,
+    pub StaggeredGrid<GroupCellFace>,
+    pub StaggeredGrid<GroupCellFace>,
+    pub StaggeredGrid<GroupCellFace>,
+);
+
+/// The number of groups that agents can belong to. Each group has its own
+/// `GroupCell` grid in `GroupGrids`.
+pub const GROUP_COUNT: usize = 4;
+
+/// The goal position of each group, if the group has one.
+#[derive(Debug, Default)]
+pub struct GroupGoals(pub [Option<(f32, f32)>; GROUP_COUNT]);
+
+/// Cells of the shared grid that are simulated by a neighboring region of the
+/// world, keyed by position. They overwrite the locally computed cells at the
+/// same positions once densities and velocities have been assigned.
+#[derive(Debug, Default)]
+pub struct GhostCells(pub HashMap<(usize, usize), SharedCell>);
+
+/// The fields of one cell of `SharedGrid`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct SharedCell {
     pub density: f32,
-    pub height: f32, // // Only set to zero for now.
+    pub height: f32, // Only set to zero for now.
     pub discomfort: f32,
+    pub is_obstacle: bool,
     pub avg_velocity: (f32, f32),
-
-    pub east_face: SharedCellFace,
-    pub north_face: SharedCellFace,
-    pub west_face: SharedCellFace,
-    pub south_face: SharedCellFace,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -32,12 +127,10 @@ pub struct SharedCellFace {
 #[derive(Debug, Default, Clone)]
 pub struct GroupCell {
     pub potential: f32,
-
-    pub east_face: GroupCellFace,
-    pub north_face: GroupCellFace,
-    pub west_face: GroupCellFace,
-    pub south_face: GroupCellFace,
 }
 
 #[derive(Debug, Default, Clone)]
-pub struct GroupCellFace {}
+pub struct GroupCellFace {
+    pub speed: f32, // Only set to zero for now.
+    pub cost: f32,  // Only set to zero for now.
+}
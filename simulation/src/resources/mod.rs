@@ -1,4 +1,4 @@
-///! ECS resources for the simulation.
+//! ECS resources for the simulation.
 pub mod continuum_crowds;
 
 use std::time::Duration;
@@ -1,7 +1,7 @@
 use crate::{
-    collections::grid::Grid,
+    collections::grid::{Cells, Grid},
     component::{Position, Velocity},
-    resources::continuum_crowds::SharedGrid,
+    resources::continuum_crowds::{GhostCells, SharedGrid},
 };
 use specs::{Join, ReadExpect, ReadStorage, System, WriteExpect};
 
@@ -11,11 +11,12 @@ impl<'a> System<'a> for PrintDensityGrid {
     type SystemData = ReadExpect<'a, SharedGrid>;
 
     fn run(&mut self, data: Self::SystemData) {
-        let shared_grid = data;
+        let density = &data.density;
         println!("------------------------------------------------------------");
-        for row in shared_grid.0.row_iter() {
-            for cell in row.iter() {
-                print!("|{:.2}", cell.density);
+        let (x_offset, y_offset) = (density.x_offset(), density.y_offset());
+        for y in y_offset..y_offset + density.height() {
+            for x in x_offset..x_offset + density.width() {
+                print!("|{:.2}", density.get(x, y).unwrap());
             }
             println!("|");
         }
@@ -29,12 +30,8 @@ impl<'a> System<'a> for ResetShared {
 
     fn run(&mut self, data: Self::SystemData) {
         let mut shared_grid = data;
-        for (x, y) in shared_grid.0.position_iter() {
-            if let Some(mut cell) = shared_grid.0.get_mut(x, y) {
-                cell.density = 0.0;
-                cell.avg_velocity = (0.0, 0.0);
-            }
-        }
+        shared_grid.density.fill(0.0);
+        shared_grid.avg_velocity.fill((0.0, 0.0));
     }
 }
 
@@ -55,84 +52,111 @@ impl<'a> System<'a> for AssignDensitiesAndVelocities {
 
     fn run(&mut self, data: Self::SystemData) {
         let (mut shared_grid, positions, velocities) = data;
+        let shared_grid = &mut *shared_grid;
+        splat_densities_and_velocities(
+            &mut shared_grid.density,
+            &mut shared_grid.avg_velocity,
+            (&positions, &velocities).join(),
+        );
+
+        shared_grid
+            .avg_velocity
+            .zip_mut_with(&shared_grid.density, |avg_velocity, &density| {
+                *avg_velocity = (avg_velocity.0 / density, avg_velocity.1 / density);
+            })
+            .expect("the shared grids have the same dimensions");
+    }
+}
 
-        for (pos, vel) in (&positions, &velocities).join() {
-            // Calculate the center of cell "A", the closest cell whose center x
-            // and y are both less than pos's x and y. Cell "A" might not exist
-            // if it's out of bounds, but we will still calculate all other
-            // neighbor cell positions off of it.
-            let a_center_x = (pos.x + 0.5).floor() - 0.5;
-            let a_center_y = (pos.y + 0.5).floor() - 0.5;
-
-            // Calculate the distance of pos from the center of the cell at
-            // (x-1, y-1).
-            let delta_x = pos.x - a_center_x;
-            let delta_y = pos.y - a_center_y;
-
-            // Cell "A".
-            if a_center_x >= 0.0 && a_center_y >= 0.0 {
-                let cell_x = a_center_x as usize;
-                let cell_y = a_center_y as usize;
-                if let Some(mut cell) = shared_grid.0.get_mut(cell_x, cell_y) {
-                    let density = (1.0 - delta_x).min(1.0 - delta_y).powf(DENSITY_EXPONENT);
-                    cell.density += density;
-                    cell.avg_velocity = (
-                        cell.avg_velocity.0 + density * vel.x,
-                        cell.avg_velocity.1 + density * vel.y,
-                    );
-                }
-            }
+/// Adds the density of each agent to the four cells whose centers surround it,
+/// and the agent's velocity weighted by that density to each cell's velocity.
+/// Works on grids of any layout.
+pub fn splat_densities_and_velocities<'a, D, V, I>(density: &mut D, avg_velocity: &mut V, agents: I)
+where
+    D: Grid<f32>,
+    V: Grid<(f32, f32)>,
+    I: IntoIterator<Item = (&'a Position, &'a Velocity)>,
+{
+    for (pos, vel) in agents {
+        // Calculate the center of cell "A", the closest cell whose center x
+        // and y are both less than pos's x and y. Cell "A" might not exist
+        // if it's out of bounds, but we will still calculate all other
+        // neighbor cell positions off of it.
+        let a_center_x = (pos.x + 0.5).floor() - 0.5;
+        let a_center_y = (pos.y + 0.5).floor() - 0.5;
+
+        // Calculate the distance of pos from the center of the cell at
+        // (x-1, y-1).
+        let delta_x = pos.x - a_center_x;
+        let delta_y = pos.y - a_center_y;
+
+        // Cell "A".
+        if a_center_x >= 0.0 && a_center_y >= 0.0 {
+            let cell_x = a_center_x as usize;
+            let cell_y = a_center_y as usize;
+            let weight = (1.0 - delta_x).min(1.0 - delta_y).powf(DENSITY_EXPONENT);
+            add_agent(density, avg_velocity, cell_x, cell_y, weight, vel);
+        }
 
-            // Cell "B".
-            if a_center_y >= 0.0 {
-                let cell_x = (a_center_x + 1.0) as usize;
-                let cell_y = a_center_y as usize;
-                if let Some(mut cell) = shared_grid.0.get_mut(cell_x, cell_y) {
-                    let density = (delta_x).min(1.0 - delta_y).powf(DENSITY_EXPONENT);
-                    cell.density += density;
-                    cell.avg_velocity = (
-                        cell.avg_velocity.0 + density * vel.x,
-                        cell.avg_velocity.1 + density * vel.y,
-                    );
-                }
-            }
+        // Cell "B".
+        if a_center_y >= 0.0 {
+            let cell_x = (a_center_x + 1.0) as usize;
+            let cell_y = a_center_y as usize;
+            let weight = (delta_x).min(1.0 - delta_y).powf(DENSITY_EXPONENT);
+            add_agent(density, avg_velocity, cell_x, cell_y, weight, vel);
+        }
 
-            // Cell "C".
-            {
-                let cell_x = (a_center_x + 1.0) as usize;
-                let cell_y = (a_center_y + 1.0) as usize;
-                if let Some(mut cell) = shared_grid.0.get_mut(cell_x, cell_y) {
-                    let density = (delta_x).min(delta_y).powf(DENSITY_EXPONENT);
-                    cell.density += density;
-                    cell.avg_velocity = (
-                        cell.avg_velocity.0 + density * vel.x,
-                        cell.avg_velocity.1 + density * vel.y,
-                    );
-                }
-            }
+        // Cell "C".
+        {
+            let cell_x = (a_center_x + 1.0) as usize;
+            let cell_y = (a_center_y + 1.0) as usize;
+            let weight = (delta_x).min(delta_y).powf(DENSITY_EXPONENT);
+            add_agent(density, avg_velocity, cell_x, cell_y, weight, vel);
+        }
 
-            // Cell "D".
-            if pos.x >= 0.0 {
-                let cell_x = a_center_x as usize;
-                let cell_y = (a_center_y + 1.0) as usize;
-                if let Some(mut cell) = shared_grid.0.get_mut(cell_x, cell_y) {
-                    let density = (1.0 - delta_x).min(delta_y).powf(DENSITY_EXPONENT);
-                    cell.density += density;
-                    cell.avg_velocity = (
-                        cell.avg_velocity.0 + density * vel.x,
-                        cell.avg_velocity.1 + density * vel.y,
-                    );
-                }
-            }
+        // Cell "D".
+        if pos.x >= 0.0 {
+            let cell_x = a_center_x as usize;
+            let cell_y = (a_center_y + 1.0) as usize;
+            let weight = (1.0 - delta_x).min(delta_y).powf(DENSITY_EXPONENT);
+            add_agent(density, avg_velocity, cell_x, cell_y, weight, vel);
         }
+    }
+}
 
-        for (x, y) in shared_grid.0.position_iter() {
-            if let Some(mut cell) = shared_grid.0.get_mut(x, y) {
-                cell.avg_velocity = (
-                    cell.avg_velocity.0 / cell.density,
-                    cell.avg_velocity.1 / cell.density,
-                );
-            }
+/// Adds an agent's density and density-weighted velocity to the cell at the
+/// position, if it lies in the grids.
+fn add_agent<D, V>(
+    density: &mut D,
+    avg_velocity: &mut V,
+    x: usize,
+    y: usize,
+    weight: f32,
+    vel: &Velocity,
+) where
+    D: Grid<f32>,
+    V: Grid<(f32, f32)>,
+{
+    if let (Some(density), Some(avg_velocity)) = (density.get_mut(x, y), avg_velocity.get_mut(x, y))
+    {
+        *density += weight;
+        *avg_velocity = (
+            avg_velocity.0 + weight * vel.x,
+            avg_velocity.1 + weight * vel.y,
+        );
+    }
+}
+
+/// Copies the cells received from neighboring regions into the shared grid.
+pub struct ApplyGhostCells;
+
+impl<'a> System<'a> for ApplyGhostCells {
+    type SystemData = (WriteExpect<'a, SharedGrid>, ReadExpect<'a, GhostCells>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut shared_grid, ghost_cells) = data;
+        for (&(x, y), cell) in ghost_cells.0.iter() {
+            shared_grid.set_cell(x, y, *cell);
         }
     }
 }
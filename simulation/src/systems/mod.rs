@@ -1,9 +1,9 @@
-///! ECS systems for the simulation.
+//! ECS systems for the simulation.
 pub mod continuum_crowds;
 
 use crate::component::{Position, Velocity};
 use crate::resources::DurationSinceLastFrame;
-use specs::{Read, ReadExpect, ReadStorage, System, WriteStorage};
+use specs::{Read, ReadStorage, System, WriteStorage};
 
 pub struct SayHello;
 
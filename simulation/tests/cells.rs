@@ -0,0 +1,186 @@
+//! Tests of the per-cell passes over grids, on each layout and across grids
+//! with different offsets.
+
+use proptest::prelude::*;
+use simulation::collections::grid::{
+    Cells, ChunkedGrid, Grid, GridError, MortonGrid, RowMajorGrid,
+};
+
+/// A sub grid whose cells hold their index in row-major order.
+fn numbered(width: usize, height: usize, x_offset: usize, y_offset: usize) -> RowMajorGrid<u32> {
+    let mut grid = RowMajorGrid::new_sub_grid(width, height, x_offset, y_offset, 0);
+    for (i, (x, y)) in grid.position_iter().enumerate() {
+        grid.set(x, y, i as u32);
+    }
+    grid
+}
+
+#[test]
+fn map_keeps_dimensions_and_offsets() {
+    let grid = numbered(4, 3, 2, 5);
+    let doubled = grid.map(|&cell| cell as f32 * 2.0);
+    assert_eq!(
+        (
+            doubled.width(),
+            doubled.height(),
+            doubled.x_offset(),
+            doubled.y_offset()
+        ),
+        (4, 3, 2, 5)
+    );
+    for (x, y) in grid.position_iter() {
+        assert_eq!(
+            doubled.get(x, y),
+            Some(&(*grid.get(x, y).unwrap() as f32 * 2.0))
+        );
+    }
+}
+
+#[test]
+fn zip_with_pairs_cells_relative_to_each_offset() {
+    let grid = numbered(3, 2, 0, 0);
+    let other = numbered(3, 2, 10, 4);
+    let sums = grid.zip_with(&other, |a, b| a + b).unwrap();
+    assert_eq!(
+        sums.iter().copied().collect::<Vec<_>>(),
+        [0, 2, 4, 6, 8, 10]
+    );
+}
+
+#[test]
+fn mismatched_dimensions_are_errors() {
+    let mut grid = numbered(3, 2, 0, 0);
+    let other = numbered(2, 3, 0, 0);
+    let mismatch = GridError::DimensionMismatch {
+        expected: (3, 2),
+        found: (2, 3),
+    };
+    assert_eq!(grid.zip_with(&other, |a, b| a + b).unwrap_err(), mismatch);
+    assert_eq!(grid.zip_mut_with(&other, |a, b| *a += b), Err(mismatch));
+    assert_eq!(grid.iter().copied().collect::<Vec<_>>(), [0, 1, 2, 3, 4, 5]);
+    assert_eq!(
+        mismatch.to_string(),
+        "expected a 3x2 grid, found a 2x3 grid"
+    );
+}
+
+#[test]
+fn zip_mut_with_works_across_layouts() {
+    let mut velocities = MortonGrid::new_sub_grid(11, 9, 3, 1, (2.0f32, 4.0f32));
+    let densities = RowMajorGrid::new(11, 9, 2.0f32);
+    velocities
+        .zip_mut_with(&densities, |velocity, &density| {
+            *velocity = (velocity.0 / density, velocity.1 / density);
+        })
+        .unwrap();
+    assert!(velocities.iter().all(|&velocity| velocity == (1.0, 2.0)));
+}
+
+#[test]
+fn min_and_max_skip_nan() {
+    let mut grid = RowMajorGrid::new(3, 3, 1.0f32);
+    grid.set(0, 0, f32::NAN);
+    grid.set(1, 2, -4.0);
+    grid.set(2, 1, 7.5);
+    assert_eq!(grid.min(), Some(-4.0));
+    assert_eq!(grid.max(), Some(7.5));
+
+    let empty = RowMajorGrid::new(0, 4, 1.0f32);
+    assert_eq!(empty.min(), None);
+    assert_eq!(empty.max(), None);
+    assert_eq!(empty.sum(), 0.0);
+}
+
+#[test]
+fn for_each_mut_and_fill_touch_every_cell_of_a_chunked_grid() {
+    let mut grid = ChunkedGrid::new(20, 18, 0u32);
+    grid.for_each_mut(|cell| *cell += 3);
+    assert_eq!(grid.sum(), 20 * 18 * 3);
+    grid.fill(1);
+    assert_eq!(grid.fold(0, |count, &cell| count + cell), 20 * 18);
+}
+
+proptest! {
+    #[test]
+    fn sum_fold_min_and_max_agree_with_iterators(
+        width in 0usize..20,
+        height in 0usize..20,
+        x_offset in 0usize..5,
+        y_offset in 0usize..5,
+    ) {
+        let grid = numbered(width, height, x_offset, y_offset);
+        let cells: Vec<u32> = grid.iter().copied().collect();
+        prop_assert_eq!(grid.sum(), cells.iter().sum::<u32>());
+        prop_assert_eq!(grid.fold(0, |count, _| count + 1), cells.len());
+        prop_assert_eq!(grid.min(), cells.iter().copied().min());
+        prop_assert_eq!(grid.max(), cells.iter().copied().max());
+    }
+}
+
+#[test]
+fn passes_over_a_window_leave_the_rest_of_the_grid() {
+    let mut grid = numbered(6, 5, 1, 2);
+    let ones = RowMajorGrid::new(3, 2, 1u32);
+    let mut window = grid.view_mut().window(2, 3, 3, 2).unwrap();
+    window
+        .zip_mut_with(&ones, |cell, one| *cell += one)
+        .unwrap();
+    window.for_each_mut(|cell| *cell *= 10);
+    let cells: Vec<_> = window.map(|&cell| cell).iter().copied().collect();
+    assert_eq!(cells, [80, 90, 100, 140, 150, 160]);
+    assert_eq!(window.sum(), 720);
+    assert_eq!(grid.get(2, 3), Some(&80));
+    assert_eq!(grid.get(1, 3), Some(&6));
+    assert_eq!(grid.get(2, 5), Some(&19));
+}
+
+#[test]
+fn fill_sets_every_cell_of_each_layout() {
+    let mut row_major = numbered(5, 3, 2, 1);
+    row_major.fill(9);
+    assert!(row_major.iter().all(|&cell| cell == 9));
+
+    let mut morton = MortonGrid::new_sub_grid(11, 9, 3, 1, 0u32);
+    morton.fill(4);
+    assert_eq!(morton.sum(), 11 * 9 * 4);
+
+    let mut grid = numbered(6, 5, 1, 2);
+    grid.view_mut().window(2, 3, 3, 2).unwrap().fill(0);
+    assert_eq!(grid.get(2, 3), Some(&0));
+    assert_eq!(grid.get(4, 4), Some(&0));
+    assert_eq!(grid.get(5, 4), Some(&16));
+}
+
+/// A grid that wrongly has no cell at (1, 0).
+struct Holey;
+
+impl Grid<u32> for Holey {
+    fn get(&self, x: usize, y: usize) -> Option<&u32> {
+        match (x, y) {
+            (1, 0) => None,
+            _ => Some(&1),
+        }
+    }
+    fn get_mut(&mut self, _x: usize, _y: usize) -> Option<&mut u32> {
+        None
+    }
+    fn set(&mut self, _x: usize, _y: usize, _val: u32) {}
+    fn width(&self) -> usize {
+        2
+    }
+    fn height(&self) -> usize {
+        1
+    }
+    fn x_offset(&self) -> usize {
+        0
+    }
+    fn y_offset(&self) -> usize {
+        0
+    }
+}
+
+#[test]
+#[should_panic(expected = "no cell at a position within its bounds")]
+fn passes_panic_rather_than_skip_missing_cells() {
+    Holey.sum();
+}
@@ -0,0 +1,123 @@
+//! Tests that a `ChunkedGrid` behaves like a `RowMajorGrid` while allocating
+//! only the chunks that have been written.
+
+use proptest::prelude::*;
+use simulation::collections::grid::{ChunkedGrid, Grid, RowMajorGrid, CHUNK_SIZE};
+
+#[test]
+fn unwritten_cells_read_the_default_without_allocating() {
+    let grid = ChunkedGrid::new_sub_grid(1000, 800, 5, 7, 3u8);
+    assert_eq!(grid.get(5, 7), Some(&3));
+    assert_eq!(grid.get(1004, 806), Some(&3));
+    assert_eq!(grid.get(4, 7), None);
+    assert_eq!(grid.get(1005, 7), None);
+    assert_eq!(grid.allocated_chunks(), 0);
+}
+
+#[test]
+fn writes_allocate_only_their_chunk() {
+    let mut grid = ChunkedGrid::new(100, 100, 0u32);
+    grid.set(40, 50, 1);
+    grid.set(41, 51, 2);
+    grid.set(100, 0, 3);
+    assert_eq!(grid.allocated_chunks(), 1);
+    assert_eq!(grid.get(40, 50), Some(&1));
+    assert_eq!(grid.get(41, 51), Some(&2));
+    assert_eq!(grid.get(40, 51), Some(&0));
+    *grid.get_mut(0, 0).unwrap() = 4;
+    assert_eq!(grid.allocated_chunks(), 2);
+}
+
+#[test]
+fn fill_frees_every_chunk() {
+    let mut grid = ChunkedGrid::new(100, 100, 0u32);
+    grid.fill(0);
+    assert_eq!(grid.allocated_chunks(), 0);
+    grid.fill(5);
+    assert_eq!(grid.allocated_chunks(), 0);
+    assert_eq!(grid.get(99, 99), Some(&5));
+
+    grid.set(40, 50, 1);
+    grid.fill(7);
+    assert_eq!(grid.allocated_chunks(), 0);
+    assert_eq!(grid.get(40, 50), Some(&7));
+    assert_eq!(grid.default_value(), &7);
+}
+
+#[test]
+fn chunks_cover_the_part_of_each_allocated_chunk_in_the_grid() {
+    let mut grid = ChunkedGrid::new_sub_grid(CHUNK_SIZE + 3, CHUNK_SIZE * 2, 2, 1, 0u32);
+    grid.set(2 + CHUNK_SIZE, 1 + CHUNK_SIZE, 1);
+    grid.set(2, 1, 2);
+    let chunks: Vec<_> = grid.chunks().collect();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!((chunks[0].x_offset(), chunks[0].y_offset()), (2, 1));
+    assert_eq!(
+        (chunks[0].width(), chunks[0].height()),
+        (CHUNK_SIZE, CHUNK_SIZE)
+    );
+    assert_eq!(
+        (chunks[1].x_offset(), chunks[1].y_offset()),
+        (2 + CHUNK_SIZE, 1 + CHUNK_SIZE)
+    );
+    assert_eq!((chunks[1].width(), chunks[1].height()), (3, CHUNK_SIZE));
+    assert_eq!(chunks[1].get(2 + CHUNK_SIZE, 1 + CHUNK_SIZE), Some(&1));
+}
+
+#[test]
+fn chunks_mut_writes_the_cells_of_the_grid() {
+    let mut grid = ChunkedGrid::new(40, 40, 0u32);
+    grid.set(20, 20, 1);
+    for mut chunk in grid.chunks_mut() {
+        for row in chunk.rows_mut() {
+            row.fill(5);
+        }
+    }
+    assert_eq!(grid.get(16, 31), Some(&5));
+    assert_eq!(grid.get(15, 31), Some(&0));
+    assert_eq!(grid.allocated_chunks(), 1);
+}
+
+#[test]
+fn chunks_that_return_to_the_default_are_freed() {
+    let mut grid = ChunkedGrid::new(64, 64, 0.0f32);
+    grid.set(1, 1, 1.0);
+    grid.set(40, 40, 2.0);
+    grid.set(40, 40, 0.0);
+    assert_eq!(grid.allocated_chunks(), 2);
+    assert_eq!(grid.free_default_chunks(), 1);
+    assert_eq!(grid.allocated_chunks(), 1);
+    assert_eq!(grid.get(1, 1), Some(&1.0));
+    assert_eq!(grid.get(40, 40), Some(&0.0));
+}
+
+proptest! {
+    #[test]
+    fn behaves_like_a_row_major_grid(
+        width in 0usize..50,
+        height in 0usize..50,
+        x_offset in 0usize..5,
+        y_offset in 0usize..5,
+        writes in proptest::collection::vec((0usize..60, 0usize..60, 0u32..3), 0..40),
+    ) {
+        let mut chunked = ChunkedGrid::new_sub_grid(width, height, x_offset, y_offset, 0);
+        let mut dense = RowMajorGrid::new_sub_grid(width, height, x_offset, y_offset, 0);
+        for &(x, y, value) in &writes {
+            chunked.set(x, y, value);
+            dense.set(x, y, value);
+        }
+        chunked.free_default_chunks();
+        for y in 0..60 {
+            for x in 0..60 {
+                prop_assert_eq!(chunked.get(x, y), dense.get(x, y));
+            }
+        }
+        let written: usize = chunked
+            .chunks()
+            .flat_map(|chunk| chunk.rows().flatten().copied().collect::<Vec<_>>())
+            .filter(|&value| value != 0)
+            .count();
+        prop_assert_eq!(written, dense.iter().filter(|&&value| value != 0).count());
+        prop_assert!(chunked.chunks().all(|chunk| chunk.rows().flatten().any(|&value| value != 0)));
+    }
+}
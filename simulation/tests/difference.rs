@@ -0,0 +1,171 @@
+//! Tests of finite differences of grids against analytic functions.
+
+use proptest::prelude::*;
+use simulation::collections::grid::{Boundary, Difference, Grid, RowMajorGrid, Scheme};
+
+const X_OFFSET: usize = 4;
+const Y_OFFSET: usize = 3;
+const WIDTH: usize = 7;
+const HEIGHT: usize = 5;
+
+/// A sub grid whose cells hold the function evaluated at their centers.
+fn grid_of<T: Clone + Default>(f: impl Fn(f32, f32) -> T) -> RowMajorGrid<T> {
+    let mut grid = RowMajorGrid::new_sub_grid(WIDTH, HEIGHT, X_OFFSET, Y_OFFSET, T::default());
+    for (x, y) in grid.position_iter() {
+        grid.set(x, y, f(x as f32 + 0.5, y as f32 + 0.5));
+    }
+    grid
+}
+
+fn center(x: usize, y: usize) -> (f32, f32) {
+    (x as f32 + 0.5, y as f32 + 0.5)
+}
+
+fn is_interior(x: usize, y: usize) -> bool {
+    x > X_OFFSET && x < X_OFFSET + WIDTH - 1 && y > Y_OFFSET && y < Y_OFFSET + HEIGHT - 1
+}
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!(
+        (actual - expected).abs() < 1e-2,
+        "expected {}, got {}",
+        expected,
+        actual
+    );
+}
+
+/// A quadratic, whose central differences and second differences are exact.
+fn quadratic(x: f32, y: f32) -> f32 {
+    x * x + 3.0 * x * y - 2.0 * y * y
+}
+
+fn quadratic_gradient(x: f32, y: f32) -> (f32, f32) {
+    (2.0 * x + 3.0 * y, 3.0 * x - 4.0 * y)
+}
+
+#[test]
+fn central_gradient_of_a_quadratic_is_exact_in_the_interior() {
+    let grid = grid_of(quadratic);
+    let gradients = grid.gradient_field(Scheme::Central, Boundary::Clamp);
+    for (x, y) in grid.position_iter().filter(|&(x, y)| is_interior(x, y)) {
+        let (cx, cy) = center(x, y);
+        let (gx, gy) = *gradients.get(x, y).unwrap();
+        let (ex, ey) = quadratic_gradient(cx, cy);
+        assert_close(gx, ex);
+        assert_close(gy, ey);
+    }
+}
+
+#[test]
+fn forward_and_backward_differences_are_exact_halfway_to_the_neighbour() {
+    let grid = grid_of(quadratic);
+    for (x, y) in grid.position_iter().filter(|&(x, y)| is_interior(x, y)) {
+        let (cx, cy) = center(x, y);
+        let forward = grid.gradient(x, y, Scheme::Forward, Boundary::Zero);
+        let backward = grid.gradient(x, y, Scheme::Backward, Boundary::Zero);
+        assert_close(forward.0, quadratic_gradient(cx + 0.5, cy).0);
+        assert_close(forward.1, quadratic_gradient(cx, cy + 0.5).1);
+        assert_close(backward.0, quadratic_gradient(cx - 0.5, cy).0);
+        assert_close(backward.1, quadratic_gradient(cx, cy - 0.5).1);
+    }
+}
+
+#[test]
+fn one_sided_boundaries_fall_back_to_differences_into_the_grid() {
+    let grid = grid_of(quadratic);
+    let (x, y) = (X_OFFSET, Y_OFFSET + HEIGHT - 1);
+    let (cx, cy) = center(x, y);
+    let (gx, gy) = grid.gradient(x, y, Scheme::Central, Boundary::OneSided);
+    assert_close(gx, quadratic_gradient(cx + 0.5, cy).0);
+    assert_close(gy, quadratic_gradient(cx, cy - 0.5).1);
+}
+
+#[test]
+fn clamp_and_zero_boundaries_read_beyond_the_edge() {
+    let mut grid = RowMajorGrid::new(3, 1, 2.0f32);
+    grid.set(1, 0, 4.0);
+    assert_close(
+        grid.difference_x_by(0, 0, Scheme::Central, Boundary::Clamp, |&v| v),
+        1.0,
+    );
+    assert_close(
+        grid.difference_x_by(0, 0, Scheme::Central, Boundary::Zero, |&v| v),
+        2.0,
+    );
+    assert_close(
+        grid.difference_x_by(0, 0, Scheme::Backward, Boundary::Zero, |&v| v),
+        2.0,
+    );
+    assert_close(
+        grid.difference_y_by(1, 0, Scheme::Central, Boundary::Clamp, |&v| v),
+        0.0,
+    );
+    assert_close(
+        grid.difference_y_by(1, 0, Scheme::Central, Boundary::OneSided, |&v| v),
+        0.0,
+    );
+}
+
+#[test]
+fn laplacian_of_a_quadratic_is_constant() {
+    let grid = grid_of(quadratic);
+    let laplacians = grid.laplacian_field(Boundary::OneSided);
+    for (x, y) in grid.position_iter() {
+        assert_close(*laplacians.get(x, y).unwrap(), -2.0);
+    }
+    for (x, y) in grid.position_iter().filter(|&(x, y)| is_interior(x, y)) {
+        assert_close(grid.laplacian(x, y, Boundary::Zero), -2.0);
+    }
+}
+
+#[test]
+fn divergence_of_a_vector_field() {
+    // The divergence of (x², xy) is 3x.
+    let grid = grid_of(|x, y| (x * x, x * y));
+    let divergences = grid.divergence_field(Scheme::Central, Boundary::OneSided);
+    for (x, y) in grid.position_iter().filter(|&(x, y)| is_interior(x, y)) {
+        assert_close(*divergences.get(x, y).unwrap(), 3.0 * center(x, y).0);
+    }
+}
+
+#[test]
+fn fields_match_the_dimensions_and_offsets_of_the_grid() {
+    let grid = grid_of(quadratic);
+    let field = grid.gradient_field(Scheme::Central, Boundary::Clamp);
+    assert_eq!((field.width(), field.height()), (WIDTH, HEIGHT));
+    assert_eq!((field.x_offset(), field.y_offset()), (X_OFFSET, Y_OFFSET));
+}
+
+#[test]
+fn differences_of_projections_and_outside_the_grid() {
+    #[derive(Clone, Default)]
+    struct Cell {
+        potential: f32,
+    }
+    let grid = grid_of(|x, _| Cell { potential: 2.0 * x });
+    let (x, y) = (X_OFFSET + 2, Y_OFFSET + 2);
+    let gradient = grid.gradient_by(x, y, Scheme::Central, Boundary::Clamp, |c| c.potential);
+    assert_eq!(gradient, (2.0, 0.0));
+    let outside = grid.gradient_by(0, 0, Scheme::Central, Boundary::Clamp, |c| c.potential);
+    assert_eq!(outside, (0.0, 0.0));
+}
+
+proptest! {
+    /// Central differences of a slowly varying smooth function closely
+    /// approximate its derivative.
+    #[test]
+    fn central_gradient_of_a_smooth_function(
+        frequency in 0.01f32..0.1,
+        phase in 0.0f32..6.0,
+    ) {
+        let grid = grid_of(|x, y| (frequency * x + phase).sin() * (frequency * y).cos());
+        for (x, y) in grid.position_iter().filter(|&(x, y)| is_interior(x, y)) {
+            let (cx, cy) = center(x, y);
+            let (gx, gy) = grid.gradient(x, y, Scheme::Central, Boundary::Clamp);
+            let ex = frequency * (frequency * cx + phase).cos() * (frequency * cy).cos();
+            let ey = -frequency * (frequency * cx + phase).sin() * (frequency * cy).sin();
+            prop_assert!((gx - ex).abs() < 1e-3);
+            prop_assert!((gy - ey).abs() < 1e-3);
+        }
+    }
+}
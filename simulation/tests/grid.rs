@@ -0,0 +1,86 @@
+//! Tests of the bounds and neighbour queries provided by the `Grid` trait.
+
+use simulation::collections::grid::{Direction, Grid, RowMajorGrid};
+
+/// A 3 by 2 sub grid at (10, 20) whose cells hold their absolute positions.
+fn positioned_sub_grid() -> RowMajorGrid<(usize, usize)> {
+    let mut grid = RowMajorGrid::new_sub_grid(3, 2, 10, 20, (0, 0));
+    for y in 20..22 {
+        for x in 10..13 {
+            grid.set(x, y, (x, y));
+        }
+    }
+    grid
+}
+
+fn positions<'a>(
+    neighbours: impl Iterator<Item = (usize, usize, &'a (usize, usize))>,
+) -> Vec<(usize, usize)> {
+    neighbours
+        .map(|(x, y, &cell)| {
+            assert_eq!((x, y), cell);
+            (x, y)
+        })
+        .collect()
+}
+
+#[test]
+fn dimensions_and_offsets() {
+    let grid = positioned_sub_grid();
+    assert_eq!((grid.width(), grid.height()), (3, 2));
+    assert_eq!((grid.x_offset(), grid.y_offset()), (10, 20));
+    assert!(grid.in_bounds(10, 20));
+    assert!(grid.in_bounds(12, 21));
+    assert!(!grid.in_bounds(9, 20));
+    assert!(!grid.in_bounds(13, 21));
+    assert!(!grid.in_bounds(10, 22));
+}
+
+#[test]
+fn neighbour_in_each_direction() {
+    let grid = positioned_sub_grid();
+    assert_eq!(grid.neighbour(11, 20, Direction::East), Some(&(12, 20)));
+    assert_eq!(grid.neighbour(11, 20, Direction::North), Some(&(11, 21)));
+    assert_eq!(grid.neighbour(11, 20, Direction::West), Some(&(10, 20)));
+    assert_eq!(grid.neighbour(11, 20, Direction::South), None);
+    assert_eq!(grid.neighbour(12, 21, Direction::East), None);
+}
+
+#[test]
+fn direction_steps_and_opposites() {
+    for direction in Direction::ALL.iter().copied() {
+        let (x, y) = direction.step(5, 5).unwrap();
+        assert_eq!(direction.opposite().step(x, y), Some((5, 5)));
+    }
+    assert_eq!(Direction::West.step(0, 3), None);
+    assert_eq!(Direction::South.step(3, 0), None);
+}
+
+#[test]
+fn neighbours4_skip_cells_outside_the_grid() {
+    let grid = positioned_sub_grid();
+    assert_eq!(
+        positions(grid.neighbours4(11, 20)),
+        vec![(12, 20), (11, 21), (10, 20)]
+    );
+    assert_eq!(
+        positions(grid.neighbours4(10, 21)),
+        vec![(11, 21), (10, 20)]
+    );
+}
+
+#[test]
+fn neighbours8_include_diagonals() {
+    let grid = positioned_sub_grid();
+    assert_eq!(
+        positions(grid.neighbours8(11, 20)),
+        vec![(12, 20), (12, 21), (11, 21), (10, 21), (10, 20)]
+    );
+}
+
+#[test]
+fn neighbours_at_the_origin_of_the_world() {
+    let grid = RowMajorGrid::new(2, 2, 0u8);
+    assert_eq!(grid.neighbours8(0, 0).count(), 3);
+    assert_eq!(grid.neighbours4(0, 0).count(), 2);
+}
@@ -0,0 +1,190 @@
+//! Tests that views of a `RowMajorGrid` read and write the cells of the grid
+//! they borrow, and that split views cover every cell exactly once.
+
+use proptest::prelude::*;
+use simulation::collections::grid::{
+    Boundary, Difference, Grid, GridViewMut, RowMajorGrid, Scheme,
+};
+use std::thread;
+
+/// A sub grid whose cells hold their own positions.
+fn positions(
+    width: usize,
+    height: usize,
+    x_offset: usize,
+    y_offset: usize,
+) -> RowMajorGrid<(usize, usize)> {
+    let mut grid = RowMajorGrid::new_sub_grid(width, height, x_offset, y_offset, (0, 0));
+    for (x, y) in grid.position_iter() {
+        grid.set(x, y, (x, y));
+    }
+    grid
+}
+
+#[test]
+fn windows_read_the_cells_of_the_grid() {
+    let grid = positions(6, 5, 2, 3);
+    let window = grid.view().window(4, 4, 3, 2).unwrap();
+    assert_eq!((window.width(), window.height()), (3, 2));
+    assert_eq!((window.x_offset(), window.y_offset()), (4, 4));
+    assert_eq!(window.get(6, 5), Some(&(6, 5)));
+    assert_eq!(window.get(3, 4), None);
+    assert_eq!(window.get(7, 4), None);
+    let rows: Vec<_> = window.rows().collect();
+    assert_eq!(
+        rows,
+        vec![&[(4, 4), (5, 4), (6, 4)][..], &[(4, 5), (5, 5), (6, 5)][..]]
+    );
+}
+
+#[test]
+fn windows_must_lie_in_the_view() {
+    let grid = positions(6, 5, 2, 3);
+    let view = grid.view();
+    assert!(view.window(1, 3, 2, 2).is_none());
+    assert!(view.window(5, 3, 4, 2).is_none());
+    assert!(view.window(2, 7, 1, 2).is_none());
+    assert!(view.window(8, 8, 0, 0).is_some());
+}
+
+#[test]
+fn windows_that_would_overflow_are_out_of_bounds() {
+    let grid = positions(6, 5, 2, 3);
+    let view = grid.view();
+    assert!(view.window(usize::MAX, 3, 2, 1).is_none());
+    assert!(view.window(2, usize::MAX, 1, 2).is_none());
+    assert!(view.window(3, 3, usize::MAX, 1).is_none());
+    assert!(view.window(2, 4, 1, usize::MAX).is_none());
+
+    let mut grid = RowMajorGrid::new(4, 4, 0u8);
+    assert!(grid.view_mut().window(usize::MAX, 0, 2, 1).is_none());
+    assert!(grid.view_mut().window(1, 0, usize::MAX, 1).is_none());
+}
+
+#[test]
+fn mutable_windows_write_the_cells_of_the_grid() {
+    let mut grid = RowMajorGrid::new_sub_grid(4, 4, 1, 1, 0u32);
+    let mut window = grid.view_mut().window(2, 2, 2, 2).unwrap();
+    for row in window.rows_mut() {
+        row.fill(7);
+    }
+    window.set(1, 1, 9);
+    assert_eq!(grid.get(1, 1), Some(&0));
+    assert_eq!(grid.get(2, 3), Some(&7));
+    assert_eq!(grid.get(4, 2), Some(&0));
+    assert_eq!(grid.iter().filter(|&&cell| cell == 7).count(), 4);
+}
+
+#[test]
+fn split_at_row_and_column() {
+    let mut grid = positions(4, 3, 0, 0);
+    let (top, bottom) = grid.view().split_at_row(1);
+    assert_eq!((top.height(), bottom.height()), (1, 2));
+    assert_eq!(bottom.get(3, 1), Some(&(3, 1)));
+    assert_eq!(top.get(3, 1), None);
+
+    let (left, mut right) = grid.view_mut().split_at_column(4);
+    assert_eq!((left.width(), right.width()), (4, 0));
+    assert_eq!(right.get_mut(4, 0), None);
+}
+
+#[test]
+#[should_panic]
+fn split_outside_the_view_panics() {
+    let grid = positions(4, 3, 2, 2);
+    grid.view().split_at_row(1);
+}
+
+#[test]
+fn grid_traits_work_on_mutable_views() {
+    let mut grid = RowMajorGrid::new(5, 1, 0.0f32);
+    for x in 0..5 {
+        grid.set(x, 0, x as f32 * 2.0);
+    }
+    let mut view = grid.view_mut();
+    let window = view.reborrow().window(1, 0, 3, 1).unwrap();
+    assert_eq!(window.neighbours4(2, 0).count(), 2);
+    let gradient = window.gradient_field(Scheme::Central, Boundary::OneSided);
+    assert_eq!(gradient.get(2, 0), Some(&(2.0, 0.0)));
+    view.set(0, 0, 1.0);
+    assert_eq!(grid.get(0, 0), Some(&1.0));
+}
+
+#[test]
+fn tiles_can_be_updated_in_parallel() {
+    let mut grid = RowMajorGrid::new_sub_grid(10, 7, 3, 1, 0usize);
+    let tiles = grid.view_mut().tiles(4, 3);
+    assert_eq!(tiles.len(), 9);
+    thread::scope(|scope| {
+        for (i, mut tile) in tiles.into_iter().enumerate() {
+            scope.spawn(move || {
+                for row in tile.rows_mut() {
+                    row.fill(i + 1);
+                }
+            });
+        }
+    });
+    assert_eq!(grid.get(3, 1), Some(&1));
+    assert_eq!(grid.get(12, 1), Some(&3));
+    assert_eq!(grid.get(7, 4), Some(&5));
+    assert_eq!(grid.get(12, 7), Some(&9));
+}
+
+proptest! {
+    #[test]
+    fn tiles_cover_every_cell_once(
+        width in 0usize..12,
+        height in 0usize..12,
+        x_offset in 0usize..5,
+        y_offset in 0usize..5,
+        tile_width in 1usize..6,
+        tile_height in 1usize..6,
+    ) {
+        let mut grid = positions(width, height, x_offset, y_offset);
+
+        let mut seen = RowMajorGrid::new_sub_grid(width, height, x_offset, y_offset, 0);
+        for tile in grid.view().tiles(tile_width, tile_height) {
+            prop_assert!(tile.width() <= tile_width && tile.height() <= tile_height);
+            for y in tile.y_offset()..tile.y_offset() + tile.height() {
+                for x in tile.x_offset()..tile.x_offset() + tile.width() {
+                    prop_assert_eq!(tile.get(x, y), Some(&(x, y)));
+                    *seen.get_mut(x, y).unwrap() += 1;
+                }
+            }
+        }
+        prop_assert!(seen.iter().all(|&count| count == 1));
+
+        let tiles: Vec<GridViewMut<'_, _>> = grid.view_mut().tiles(tile_width, tile_height);
+        let cells: usize = tiles.iter().map(|tile| tile.width() * tile.height()).sum();
+        prop_assert_eq!(cells, width * height);
+        for tile in tiles {
+            for (row, y) in tile.rows().zip(tile.y_offset()..) {
+                let expected: Vec<_> = (tile.x_offset()..tile.x_offset() + tile.width())
+                    .map(|x| (x, y))
+                    .collect();
+                prop_assert_eq!(row, &expected[..]);
+            }
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn mutable_tiles_write_every_cell_once(
+        width in 0usize..12,
+        height in 0usize..12,
+        x_offset in 0usize..5,
+        y_offset in 0usize..5,
+        tile_width in 1usize..6,
+        tile_height in 1usize..6,
+    ) {
+        let mut grid = RowMajorGrid::new_sub_grid(width, height, x_offset, y_offset, 0u32);
+        for mut tile in grid.view_mut().tiles(tile_width, tile_height) {
+            let mut view = tile.reborrow();
+            for row in view.rows_mut() {
+                row.iter_mut().for_each(|cell| *cell += 1);
+            }
+        }
+        prop_assert!(grid.iter().all(|&count| count == 1));
+    }
+}
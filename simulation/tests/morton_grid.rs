@@ -0,0 +1,91 @@
+//! Tests that a `MortonGrid` behaves like a `RowMajorGrid`, and that the crowd
+//! passes give the same results on either layout.
+
+use proptest::prelude::*;
+use simulation::collections::grid::{Grid, MortonGrid, RowMajorGrid};
+use simulation::component::{Position, Velocity};
+use simulation::systems::continuum_crowds::splat_densities_and_velocities;
+
+#[test]
+fn padding_cells_are_out_of_bounds() {
+    let mut grid = MortonGrid::new_sub_grid(10, 3, 2, 4, 0u32);
+    assert_eq!(grid.get(11, 6), Some(&0));
+    assert_eq!(grid.get(12, 6), None);
+    assert_eq!(grid.get(11, 7), None);
+    assert_eq!(grid.get(1, 4), None);
+    grid.set(12, 4, 1);
+    assert_eq!(grid.iter().count(), 30);
+    assert!(grid.iter().all(|&cell| cell == 0));
+}
+
+#[test]
+fn iter_mut_visits_every_cell_in_the_grid() {
+    let mut grid = MortonGrid::new(20, 9, 0u32);
+    for cell in grid.iter_mut() {
+        *cell += 1;
+    }
+    assert!(grid
+        .position_iter()
+        .all(|(x, y)| grid.get(x, y) == Some(&1)));
+}
+
+#[test]
+fn splatting_agrees_on_both_layouts() {
+    let positions: Vec<_> = (0..200)
+        .map(|i| Position {
+            x: (i * 7 % 61) as f32 * 0.37,
+            y: (i * 13 % 53) as f32 * 0.29,
+        })
+        .collect();
+    let velocities: Vec<_> = (0..200)
+        .map(|i| Velocity {
+            x: (i % 5) as f32 - 2.0,
+            y: (i % 3) as f32,
+        })
+        .collect();
+    let agents = || positions.iter().zip(&velocities);
+
+    let mut row_major = (
+        RowMajorGrid::new(23, 17, 0.0),
+        RowMajorGrid::new(23, 17, (0.0, 0.0)),
+    );
+    splat_densities_and_velocities(&mut row_major.0, &mut row_major.1, agents());
+    let mut morton = (
+        MortonGrid::new(23, 17, 0.0),
+        MortonGrid::new(23, 17, (0.0, 0.0)),
+    );
+    splat_densities_and_velocities(&mut morton.0, &mut morton.1, agents());
+
+    for (x, y) in row_major.0.position_iter() {
+        assert_eq!(row_major.0.get(x, y), morton.0.get(x, y));
+        assert_eq!(row_major.1.get(x, y), morton.1.get(x, y));
+    }
+}
+
+proptest! {
+    #[test]
+    fn behaves_like_a_row_major_grid(
+        width in 0usize..30,
+        height in 0usize..30,
+        x_offset in 0usize..5,
+        y_offset in 0usize..5,
+        writes in proptest::collection::vec((0usize..40, 0usize..40, 1u32..100), 0..60),
+    ) {
+        let mut morton = MortonGrid::new_sub_grid(width, height, x_offset, y_offset, 0);
+        let mut row_major = RowMajorGrid::new_sub_grid(width, height, x_offset, y_offset, 0);
+        for &(x, y, value) in &writes {
+            morton.set(x, y, value);
+            row_major.set(x, y, value);
+        }
+        for y in 0..40 {
+            for x in 0..40 {
+                prop_assert_eq!(morton.get(x, y), row_major.get(x, y));
+            }
+        }
+        let mut stored: Vec<_> = morton.iter().copied().collect();
+        let mut expected: Vec<_> = row_major.iter().copied().collect();
+        stored.sort_unstable();
+        expected.sort_unstable();
+        prop_assert_eq!(stored, expected);
+    }
+}
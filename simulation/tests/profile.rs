@@ -0,0 +1,59 @@
+//! Tests of the rolling stats of `SystemTimings`.
+
+use simulation::profile::{SystemTimings, ROLLING_WINDOW};
+use std::time::Duration;
+
+fn millis(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+#[test]
+fn a_single_sample_is_every_stat() {
+    let timings = SystemTimings::default();
+    timings.record("update_pos", millis(3));
+    let stats = timings.stats();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].name, "update_pos");
+    assert_eq!(stats[0].samples, 1);
+    assert_eq!(stats[0].min, millis(3));
+    assert_eq!(stats[0].mean, millis(3));
+    assert_eq!(stats[0].p99, millis(3));
+}
+
+#[test]
+fn p99_of_a_hundred_samples_is_the_second_largest() {
+    let timings = SystemTimings::default();
+    // Recorded out of order to check that the stats sort them.
+    for i in (1..=100).rev() {
+        timings.record("update_pos", millis(i));
+    }
+    let stats = timings.stats()[0];
+    assert_eq!(stats.samples, 100);
+    assert_eq!(stats.min, millis(1));
+    assert_eq!(stats.mean, Duration::from_micros(50_500));
+    assert_eq!(stats.p99, millis(99));
+}
+
+#[test]
+fn only_the_last_rolling_window_of_samples_is_kept() {
+    let timings = SystemTimings::default();
+    for i in 0..ROLLING_WINDOW as u64 + 10 {
+        timings.record("update_pos", millis(i));
+    }
+    let stats = timings.stats()[0];
+    assert_eq!(stats.samples, ROLLING_WINDOW);
+    assert_eq!(stats.min, millis(10));
+    assert_eq!(
+        timings.durations(),
+        vec![("update_pos", millis(ROLLING_WINDOW as u64 + 9))]
+    );
+}
+
+#[test]
+fn stats_are_ordered_by_name() {
+    let timings = SystemTimings::default();
+    timings.record("update_pos", millis(1));
+    timings.record("reset_shared", millis(2));
+    let names: Vec<_> = timings.stats().iter().map(|stats| stats.name).collect();
+    assert_eq!(names, vec!["reset_shared", "update_pos"]);
+}
@@ -0,0 +1,147 @@
+//! Property tests that check `RowMajorGrid` iteration against naive indexing.
+
+use proptest::prelude::*;
+use simulation::collections::grid::{Grid, RowMajorGrid};
+
+/// Builds a sub grid whose cells each hold a distinct value.
+fn numbered_grid(
+    width: usize,
+    height: usize,
+    x_offset: usize,
+    y_offset: usize,
+) -> RowMajorGrid<u32> {
+    let mut grid = RowMajorGrid::new_sub_grid(width, height, x_offset, y_offset, 0);
+    for y in y_offset..y_offset + height {
+        for x in x_offset..x_offset + width {
+            grid.set(x, y, naive_value(x, y));
+        }
+    }
+    grid
+}
+
+fn naive_value(x: usize, y: usize) -> u32 {
+    (y * 1000 + x) as u32
+}
+
+/// Positions of a sub grid in row-major order, computed with nested loops.
+fn naive_positions(
+    width: usize,
+    height: usize,
+    x_offset: usize,
+    y_offset: usize,
+) -> Vec<(usize, usize)> {
+    let mut positions = vec![];
+    for y in y_offset..y_offset + height {
+        for x in x_offset..x_offset + width {
+            positions.push((x, y));
+        }
+    }
+    positions
+}
+
+proptest! {
+    #[test]
+    fn iter_yields_cells_in_row_major_order(
+        width in 0usize..12,
+        height in 0usize..12,
+        x_offset in 0usize..20,
+        y_offset in 0usize..20,
+    ) {
+        let grid = numbered_grid(width, height, x_offset, y_offset);
+        let expected: Vec<u32> = naive_positions(width, height, x_offset, y_offset)
+            .into_iter()
+            .map(|(x, y)| *grid.get(x, y).unwrap())
+            .collect();
+        let actual: Vec<u32> = grid.iter().copied().collect();
+        prop_assert_eq!(grid.iter().len(), width * height);
+        prop_assert_eq!(&actual, &expected);
+        let via_into_iter: Vec<u32> = (&grid).into_iter().copied().collect();
+        prop_assert_eq!(via_into_iter, expected);
+    }
+
+    #[test]
+    fn enumerate_yields_absolute_positions(
+        width in 0usize..12,
+        height in 0usize..12,
+        x_offset in 0usize..20,
+        y_offset in 0usize..20,
+    ) {
+        let grid = numbered_grid(width, height, x_offset, y_offset);
+        let expected: Vec<(usize, usize, u32)> = naive_positions(width, height, x_offset, y_offset)
+            .into_iter()
+            .map(|(x, y)| (x, y, naive_value(x, y)))
+            .collect();
+        let actual: Vec<(usize, usize, u32)> =
+            grid.enumerate().map(|(x, y, &cell)| (x, y, cell)).collect();
+        prop_assert_eq!(grid.enumerate().len(), width * height);
+        prop_assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn enumerate_agrees_with_position_iter(
+        width in 0usize..12,
+        height in 0usize..12,
+        x_offset in 0usize..20,
+        y_offset in 0usize..20,
+    ) {
+        let grid = numbered_grid(width, height, x_offset, y_offset);
+        let enumerated: Vec<(usize, usize)> = grid.enumerate().map(|(x, y, _)| (x, y)).collect();
+        let positions: Vec<(usize, usize)> = grid.position_iter().collect();
+        prop_assert_eq!(enumerated, positions);
+    }
+
+    #[test]
+    fn iter_mut_modifies_the_cell_at_each_position(
+        width in 0usize..12,
+        height in 0usize..12,
+        x_offset in 0usize..20,
+        y_offset in 0usize..20,
+    ) {
+        let mut grid = numbered_grid(width, height, x_offset, y_offset);
+        for cell in grid.iter_mut() {
+            *cell += 1;
+        }
+        for (x, y) in naive_positions(width, height, x_offset, y_offset) {
+            prop_assert_eq!(*grid.get(x, y).unwrap(), naive_value(x, y) + 1);
+        }
+    }
+
+    #[test]
+    fn rows_match_naive_indexing(
+        width in 0usize..12,
+        height in 0usize..12,
+        x_offset in 0usize..20,
+        y_offset in 0usize..20,
+    ) {
+        let grid = numbered_grid(width, height, x_offset, y_offset);
+        prop_assert_eq!(grid.rows().len(), height);
+        for (j, row) in grid.rows().enumerate() {
+            prop_assert_eq!(row.len(), width);
+            for (i, &cell) in row.iter().enumerate() {
+                prop_assert_eq!(cell, *grid.get(x_offset + i, y_offset + j).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn rows_mut_modifies_each_row(
+        width in 0usize..12,
+        height in 0usize..12,
+        x_offset in 0usize..20,
+        y_offset in 0usize..20,
+    ) {
+        let mut grid = numbered_grid(width, height, x_offset, y_offset);
+        let mut rows = 0;
+        for (j, row) in grid.rows_mut().enumerate() {
+            prop_assert_eq!(row.len(), width);
+            for cell in row.iter_mut() {
+                *cell = j as u32;
+            }
+            rows += 1;
+        }
+        prop_assert_eq!(rows, height);
+        for (x, y) in naive_positions(width, height, x_offset, y_offset) {
+            prop_assert_eq!(*grid.get(x, y).unwrap(), (y - y_offset) as u32);
+        }
+    }
+}
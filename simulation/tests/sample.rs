@@ -0,0 +1,149 @@
+//! Tests of nearest, bilinear and bicubic sampling of grids.
+
+use proptest::prelude::*;
+use simulation::collections::grid::{Edge, Grid, RowMajorGrid, Sample};
+
+/// A sub grid whose cells hold `a * x + b * y + c` for the position of their
+/// centers.
+fn linear_grid(a: f32, b: f32, c: f32) -> RowMajorGrid<f32> {
+    let mut grid = RowMajorGrid::new_sub_grid(8, 6, 3, 2, 0.0);
+    for y in 2..8 {
+        for x in 3..11 {
+            grid.set(x, y, linear(a, b, c, x as f32 + 0.5, y as f32 + 0.5));
+        }
+    }
+    grid
+}
+
+fn linear(a: f32, b: f32, c: f32, x: f32, y: f32) -> f32 {
+    a * x + b * y + c
+}
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!(
+        (actual - expected).abs() < 1e-3,
+        "expected {}, got {}",
+        expected,
+        actual
+    );
+}
+
+#[test]
+fn every_method_reads_cell_values_at_cell_centers() {
+    let grid = linear_grid(1.0, 10.0, 0.0);
+    for y in 2..8 {
+        for x in 3..11 {
+            let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+            let expected = *grid.get(x, y).unwrap();
+            assert_close(grid.sample_nearest(cx, cy, Edge::Clamp), expected);
+            assert_close(grid.sample_bilinear(cx, cy, Edge::Clamp), expected);
+            assert_close(grid.sample_bicubic(cx, cy, Edge::Clamp), expected);
+        }
+    }
+}
+
+#[test]
+fn nearest_reads_the_cell_that_contains_the_position() {
+    let grid = linear_grid(1.0, 10.0, 0.0);
+    assert_close(grid.sample_nearest(4.99, 2.0, Edge::Zero), 29.5);
+    assert_close(grid.sample_nearest(5.0, 2.0, Edge::Zero), 30.5);
+}
+
+#[test]
+fn bilinear_averages_the_surrounding_cells() {
+    let mut grid = RowMajorGrid::new(2, 2, 0.0);
+    grid.set(1, 0, 4.0);
+    grid.set(1, 1, 8.0);
+    assert_close(grid.sample_bilinear(1.0, 1.0, Edge::Clamp), 3.0);
+    assert_close(grid.sample_bilinear(1.0, 0.5, Edge::Clamp), 2.0);
+}
+
+#[test]
+fn clamp_repeats_the_edge_cells() {
+    let grid = linear_grid(1.0, 10.0, 0.0);
+    let corner = *grid.get(3, 2).unwrap();
+    assert_close(grid.sample_nearest(0.0, 0.0, Edge::Clamp), corner);
+    assert_close(grid.sample_bilinear(0.0, 0.0, Edge::Clamp), corner);
+    assert_close(grid.sample_bicubic(0.0, 0.0, Edge::Clamp), corner);
+}
+
+#[test]
+fn zero_fades_to_zero_beyond_the_edges() {
+    let grid = RowMajorGrid::new_sub_grid(2, 2, 1, 1, 1.0);
+    assert_close(grid.sample_nearest(0.5, 1.5, Edge::Zero), 0.0);
+    // Halfway between the center of an edge cell and the cell beyond it.
+    assert_close(grid.sample_bilinear(1.0, 2.0, Edge::Zero), 0.5);
+    assert_close(grid.sample_bilinear(100.0, 100.0, Edge::Zero), 0.0);
+}
+
+#[test]
+fn empty_grids_sample_to_zero() {
+    let grid = RowMajorGrid::new(0, 3, 1.0f32);
+    assert_close(grid.sample_nearest(0.5, 0.5, Edge::Clamp), 0.0);
+    assert_close(grid.sample_bicubic(0.5, 0.5, Edge::Clamp), 0.0);
+}
+
+#[test]
+fn vectors_and_projections() {
+    #[derive(Clone)]
+    struct Cell {
+        speed: f32,
+        velocity: (f32, f32),
+    }
+    let mut grid = RowMajorGrid::new(
+        2,
+        1,
+        Cell {
+            speed: 1.0,
+            velocity: (0.0, 2.0),
+        },
+    );
+    grid.set(
+        1,
+        0,
+        Cell {
+            speed: 3.0,
+            velocity: (4.0, 2.0),
+        },
+    );
+    let speed = grid.sample_bilinear_by(1.0, 0.5, Edge::Clamp, |cell| cell.speed);
+    assert_close(speed, 2.0);
+    let (vx, vy) = grid.sample_bilinear_by(1.0, 0.5, Edge::Clamp, |cell| cell.velocity);
+    assert_close(vx, 2.0);
+    assert_close(vy, 2.0);
+}
+
+proptest! {
+    /// Bilinear and Catmull-Rom interpolation both reproduce linear fields
+    /// exactly away from the edges.
+    #[test]
+    fn interpolation_reproduces_linear_fields(
+        a in -5.0f32..5.0,
+        b in -5.0f32..5.0,
+        c in -5.0f32..5.0,
+        x in 5.0f32..9.0,
+        y in 4.0f32..6.0,
+    ) {
+        let grid = linear_grid(a, b, c);
+        let expected = linear(a, b, c, x, y);
+        prop_assert!((grid.sample_bilinear(x, y, Edge::Zero) - expected).abs() < 1e-3);
+        prop_assert!((grid.sample_bicubic(x, y, Edge::Zero) - expected).abs() < 1e-3);
+    }
+
+    /// Bilinear samples never leave the range of the grid's values.
+    #[test]
+    fn bilinear_stays_within_the_range_of_cells(
+        values in proptest::collection::vec(-100.0f32..100.0, 12),
+        x in -2.0f32..6.0,
+        y in -2.0f32..5.0,
+    ) {
+        let mut grid = RowMajorGrid::new(4, 3, 0.0);
+        for (i, &value) in values.iter().enumerate() {
+            grid.set(i % 4, i / 4, value);
+        }
+        let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let sample = grid.sample_bilinear(x, y, Edge::Clamp);
+        prop_assert!(sample >= min - 1e-3 && sample <= max + 1e-3);
+    }
+}
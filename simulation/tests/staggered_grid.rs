@@ -0,0 +1,64 @@
+//! Tests that neighbouring cells of a `StaggeredGrid` share the face between
+//! them.
+
+use proptest::prelude::*;
+use simulation::collections::grid::{Direction, Grid, StaggeredGrid};
+
+#[test]
+fn face_grids_have_one_extra_column_or_row() {
+    let grid = StaggeredGrid::new_sub_grid(4, 3, 2, 5, 0u32);
+    let vertical = grid.vertical_faces();
+    let horizontal = grid.horizontal_faces();
+    assert_eq!((vertical.width(), vertical.height()), (5, 3));
+    assert_eq!((horizontal.width(), horizontal.height()), (4, 4));
+    assert_eq!((vertical.x_offset(), vertical.y_offset()), (2, 5));
+    assert_eq!((horizontal.x_offset(), horizontal.y_offset()), (2, 5));
+}
+
+#[test]
+fn faces_of_cells_outside_the_grid_are_missing() {
+    let mut grid = StaggeredGrid::new_sub_grid(2, 2, 1, 1, 0u32);
+    assert_eq!(grid.face(0, 1, Direction::East), None);
+    assert_eq!(grid.face(3, 1, Direction::West), None);
+    assert_eq!(grid.face(1, 3, Direction::South), None);
+    grid.set_face(0, 1, Direction::East, 7);
+    assert_eq!(grid.face(1, 1, Direction::West), Some(&0));
+}
+
+#[test]
+fn boundary_faces_belong_to_a_single_cell() {
+    let mut grid = StaggeredGrid::new(2, 2, 0u32);
+    grid.set_face(0, 0, Direction::West, 1);
+    grid.set_face(1, 1, Direction::North, 2);
+    assert_eq!(grid.vertical_faces().get(0, 0), Some(&1));
+    assert_eq!(grid.horizontal_faces().get(1, 2), Some(&2));
+}
+
+proptest! {
+    #[test]
+    fn neighbouring_cells_share_faces(
+        width in 1usize..8,
+        height in 1usize..8,
+        x_offset in 0usize..10,
+        y_offset in 0usize..10,
+    ) {
+        let mut grid = StaggeredGrid::new_sub_grid(width, height, x_offset, y_offset, 0u32);
+        let mut next_value = 1;
+        for y in y_offset..y_offset + height {
+            for x in x_offset..x_offset + width {
+                for direction in Direction::ALL.iter().copied() {
+                    grid.set_face(x, y, direction, next_value);
+                    let (nx, ny) = match direction.step(x, y) {
+                        Some(pos) if grid.in_bounds(pos.0, pos.1) => pos,
+                        _ => continue,
+                    };
+                    prop_assert_eq!(
+                        grid.face(nx, ny, direction.opposite()),
+                        Some(&next_value)
+                    );
+                    next_value += 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! An HTTP endpoint for operators that serves health checks at `/health` and
+//! metrics in the Prometheus text format at `/metrics`
+
+use crate::error::Result;
+use crate::metrics::Metrics;
+use crate::shard::Shard;
+use hyper::{
+    header::CONTENT_TYPE,
+    server::{conn::AddrIncoming, Builder},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// The content type of the Prometheus text format.
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Binds the admin endpoint to `address`.
+pub fn bind(address: &SocketAddr) -> Result<Builder<AddrIncoming>> {
+    Ok(Server::try_bind(address)?)
+}
+
+/// Serves requests until `shutdown` becomes true. `shard` is given if this
+/// process simulates one region of a split world.
+pub async fn serve(
+    server: Builder<AddrIncoming>,
+    metrics: Arc<Metrics>,
+    shard: Option<Arc<Shard>>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        let shard = shard.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = respond(&request, &metrics, shard.as_deref());
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    server
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.changed().await;
+        })
+        .await?;
+    Ok(())
+}
+
+fn respond(request: &Request<Body>, metrics: &Metrics, shard: Option<&Shard>) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/health") => Response::new(Body::from("ok\n")),
+        (&Method::GET, "/metrics") => {
+            let peer_queues = shard.map(Shard::queue_depths).unwrap_or_default();
+            Response::builder()
+                .header(CONTENT_TYPE, METRICS_CONTENT_TYPE)
+                .body(Body::from(metrics.render(&peer_queues)))
+                .unwrap()
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(path: &str, metrics: &Metrics) -> Response<Body> {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        respond(&request, metrics, None)
+    }
+
+    async fn body(response: Response<Body>) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn health_checks_succeed() {
+        let response = get("/health", &Metrics::new());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await, "ok\n");
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_in_the_prometheus_text_format() {
+        let metrics = Metrics::new();
+        let response = get("/metrics", &metrics);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "text/plain; version=0.0.4"
+        );
+        assert_eq!(body(response).await, metrics.render(&[]));
+    }
+
+    #[test]
+    fn other_paths_and_methods_are_not_found() {
+        let metrics = Metrics::new();
+        assert_eq!(get("/", &metrics).status(), StatusCode::NOT_FOUND);
+        let request = Request::post("/metrics").body(Body::empty()).unwrap();
+        assert_eq!(
+            respond(&request, &metrics, None).status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
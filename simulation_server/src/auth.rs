@@ -0,0 +1,207 @@
+//! Authentication of clients and the roles that determine what they can do
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+
+/// What an authenticated client is allowed to do. Each role can do everything
+/// that the roles before it can.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Can join rooms and receive snapshots.
+    Viewer,
+
+    /// Can also send commands that change the world.
+    Operator,
+
+    /// Can also pause and resume simulations.
+    Admin,
+}
+
+/// What a valid token grants its client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Grant {
+    pub role: Role,
+
+    /// When the token stops being valid, or `None` for tokens listed in the
+    /// config, which don't expire.
+    pub expires_at: Option<SystemTime>,
+}
+
+/// The tokens that clients can authenticate with. Authentication is disabled
+/// if neither an HMAC secret nor any tokens are configured.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Secret used to verify signed tokens of the form
+    /// `role:expires_at:signature`, where `expires_at` is a Unix timestamp in
+    /// seconds and `signature` is the hex encoded HMAC-SHA256 of
+    /// `role:expires_at`.
+    pub hmac_secret: Option<String>,
+
+    /// Tokens that are accepted as they are, each granting a role.
+    pub tokens: Vec<StaticToken>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StaticToken {
+    pub token: String,
+    pub role: Role,
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.hmac_secret.is_some() || !self.tokens.is_empty()
+    }
+
+    /// Returns what the token grants, or `None` if the token is invalid or
+    /// has expired. Tokens are compared in constant time.
+    pub fn authenticate(&self, token: &str) -> Option<Grant> {
+        let static_token = self
+            .tokens
+            .iter()
+            .find(|t| bool::from(t.token.as_bytes().ct_eq(token.as_bytes())));
+        if let Some(static_token) = static_token {
+            return Some(Grant {
+                role: static_token.role,
+                expires_at: None,
+            });
+        }
+        let secret = self.hmac_secret.as_ref()?;
+        verify_signed_token(secret, token, SystemTime::now())
+    }
+}
+
+fn verify_signed_token(secret: &str, token: &str, now: SystemTime) -> Option<Grant> {
+    let (payload, signature) = token.rsplit_once(':')?;
+    let (role, expires_at) = payload.split_once(':')?;
+
+    if !verify(secret, payload.as_bytes(), &hex::decode(signature).ok()?) {
+        return None;
+    }
+
+    let expires_at = UNIX_EPOCH.checked_add(Duration::from_secs(expires_at.parse().ok()?))?;
+    if now >= expires_at {
+        return None;
+    }
+    let role = match role {
+        "viewer" => Role::Viewer,
+        "operator" => Role::Operator,
+        "admin" => Role::Admin,
+        _ => return None,
+    };
+    Some(Grant {
+        role,
+        expires_at: Some(expires_at),
+    })
+}
+
+/// Returns the HMAC-SHA256 of the message, keyed with the secret.
+pub fn sign(secret: &str, message: &[u8]) -> Vec<u8> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Returns whether the signature is the HMAC-SHA256 of the message, keyed with
+/// the secret. The signature is compared in constant time.
+pub fn verify(secret: &str, message: &[u8], signature: &[u8]) -> bool {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.verify_slice(signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "secret";
+
+    fn signed_token(secret: &str, payload: &str) -> String {
+        format!(
+            "{}:{}",
+            payload,
+            hex::encode(sign(secret, payload.as_bytes()))
+        )
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn signed_tokens_grant_their_role_until_they_expire() {
+        let token = signed_token(SECRET, "operator:1000");
+        assert_eq!(
+            verify_signed_token(SECRET, &token, at(999)),
+            Some(Grant {
+                role: Role::Operator,
+                expires_at: Some(at(1000)),
+            })
+        );
+        assert_eq!(verify_signed_token(SECRET, &token, at(1000)), None);
+        assert_eq!(verify_signed_token(SECRET, &token, at(5000)), None);
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let token = signed_token(SECRET, "viewer:1000");
+        let signature = token.rsplit_once(':').unwrap().1;
+        let cases = [
+            // Another role or expiry with the original signature.
+            format!("admin:1000:{}", signature),
+            format!("viewer:9999:{}", signature),
+            // Signed with another secret.
+            signed_token("other", "viewer:1000"),
+            // A signature that isn't hex, or is cut short.
+            "viewer:1000:not-hex".to_string(),
+            token[..token.len() - 2].to_string(),
+            // Not a signed token at all.
+            "viewer".to_string(),
+        ];
+        for token in cases {
+            assert_eq!(
+                verify_signed_token(SECRET, &token, at(0)),
+                None,
+                "{}",
+                token
+            );
+        }
+    }
+
+    #[test]
+    fn correctly_signed_tokens_must_name_a_role_and_an_expiry() {
+        for payload in ["owner:1000", "admin:never"] {
+            let token = signed_token(SECRET, payload);
+            assert_eq!(verify_signed_token(SECRET, &token, at(0)), None);
+        }
+    }
+
+    #[test]
+    fn static_tokens_must_match_exactly() {
+        let config = AuthConfig {
+            hmac_secret: Some(SECRET.to_string()),
+            tokens: vec![StaticToken {
+                token: "letmein".to_string(),
+                role: Role::Admin,
+            }],
+        };
+        assert!(config.is_enabled());
+        assert_eq!(
+            config.authenticate("letmein"),
+            Some(Grant {
+                role: Role::Admin,
+                expires_at: None,
+            })
+        );
+        assert_eq!(config.authenticate("letmein!"), None);
+        assert_eq!(config.authenticate(""), None);
+        assert!(!AuthConfig::default().is_enabled());
+    }
+}
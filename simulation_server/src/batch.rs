@@ -0,0 +1,93 @@
+//! Runs a scenario for a fixed number of frames without serving clients and
+//! reports how long each system of the dispatcher took. Frames run back to
+//! back, but each one advances the simulation by the configured frame
+//! duration, so the world ends up as if it had run in real time.
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::scenario::Scenario;
+use crate::state::State;
+use log::info;
+use simulation::{
+    profile::{SystemTimings, ROLLING_WINDOW},
+    resources::DurationSinceLastFrame,
+};
+use specs::WorldExt;
+use std::time::{Duration, Instant};
+
+pub fn run(config: &Config, scenario: &Scenario, frames: u64) -> Result<()> {
+    let mut state = State::new(scenario);
+    state
+        .world
+        .insert(DurationSinceLastFrame(config.frame_duration));
+    let start = Instant::now();
+    for _ in 0..frames {
+        state.dispatcher.dispatch(&state.world);
+        state.world.maintain();
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "Ran {} frames in {:.3} s ({:.3} ms per frame)",
+        frames,
+        elapsed.as_secs_f64(),
+        millis(elapsed) / frames as f64
+    );
+    match state.world.try_fetch::<SystemTimings>() {
+        Some(timings) => {
+            println!(
+                "Each system's run times over its last {} runs:",
+                frames.min(ROLLING_WINDOW as u64)
+            );
+            println!(
+                "{:<32} {:>10} {:>10} {:>10}",
+                "system", "min (ms)", "mean (ms)", "p99 (ms)"
+            );
+            for stats in timings.stats() {
+                println!(
+                    "{:<32} {:>10.3} {:>10.3} {:>10.3}",
+                    stats.name,
+                    millis(stats.min),
+                    millis(stats.mean),
+                    millis(stats.p99)
+                );
+            }
+        }
+        None => println!("Build with the `profiling` feature to time each system."),
+    }
+
+    if let Some(path) = &config.save_path {
+        Scenario::capture(&state.world).save(path)?;
+        info!("Saved the world to {}", path.display());
+    }
+    Ok(())
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn runs_the_frames_and_saves_the_world() {
+        let path =
+            std::env::temp_dir().join(format!("simulation-batch-{}.json", std::process::id()));
+        let config = Config::from_toml(&format!("save_path = {:?}", path)).unwrap();
+        let scenario = Scenario::default();
+        run(&config, &scenario, 5).unwrap();
+
+        let saved = Scenario::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(saved.agents.len(), scenario.agents.len());
+        let moved = saved
+            .agents
+            .iter()
+            .zip(&scenario.agents)
+            .any(|(saved, agent)| (saved.x, saved.y) != (agent.x, agent.y));
+        assert!(moved);
+    }
+}
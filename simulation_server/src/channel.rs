@@ -1,64 +1,97 @@
 //! Infrastructure for communication between tasks on the server via channels
 
-use std::{collections::HashMap, net::SocketAddr};
-use tokio::sync::mpsc::UnboundedSender;
+use crate::peer::PeerMessage;
+use crate::protocol::{Command, MessageToClient, Snapshot};
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::{
+    mpsc::{Sender, UnboundedSender},
+    watch,
+};
 
-/// Contains the sender end of a channel that is consumed by the simulation task
-/// and the sender end of the channels that are consumed by connection handler
-/// tasks.
-pub struct Senders {
-    /// The sender end of a channel that is consumed by the simulation task.
-    sim_sender: Option<UnboundedSender<MessageToSimulation>>,
+/// The number of replies that can be queued for a connection handler task
+/// before further replies to its client are dropped.
+pub const CONN_HANDLER_QUEUE_CAPACITY: usize = 16;
 
-    /// Contains the sender end of a channel for each connection handler task.
-    conn_handler_senders: HashMap<SocketAddr, UnboundedSender<MessageToConnectionHandler>>,
-}
+/// The number of consecutive snapshots that a client can miss before it's
+/// considered to have fallen behind.
+pub const MAX_MISSED_SNAPSHOTS: u64 = 16;
 
-impl Senders {
-    pub fn new() -> Senders {
-        Senders {
-            sim_sender: None,
-            conn_handler_senders: HashMap::new(),
-        }
-    }
+/// What to do when a client falls behind.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowClientPolicy {
+    /// Skip stale snapshots. Snapshots are full copies of the simulation, so
+    /// the client catches up as soon as it receives the latest one.
+    DropStale,
 
-    pub fn insert_sim_sender(&mut self, sender: UnboundedSender<MessageToSimulation>) {
-        self.sim_sender = Some(sender);
-    }
+    /// Disconnect the client once it has missed `MAX_MISSED_SNAPSHOTS`
+    /// consecutive snapshots.
+    Disconnect,
+}
 
-    pub fn insert_conn_handler_sender(
-        &mut self,
-        addr: SocketAddr,
-        sender: UnboundedSender<MessageToConnectionHandler>,
-    ) {
-        self.conn_handler_senders.insert(addr, sender);
-    }
+/// The connection handler tasks' end of the channels to the simulation task.
+/// Commands funnel into the simulation through a single MPSC channel, and
+/// snapshots are published to every subscriber through a single watch channel,
+/// so no lock is shared between connections.
+#[derive(Clone)]
+pub struct SimHandle {
+    sim_sender: UnboundedSender<MessageToSimulation>,
+    snapshot_receiver: watch::Receiver<Option<PublishedSnapshot>>,
+}
 
-    pub fn remove_conn_handler_sender(&mut self, addr: &SocketAddr) {
-        self.conn_handler_senders.remove(&addr);
+impl SimHandle {
+    pub fn new(
+        sim_sender: UnboundedSender<MessageToSimulation>,
+        snapshot_receiver: watch::Receiver<Option<PublishedSnapshot>>,
+    ) -> SimHandle {
+        SimHandle {
+            sim_sender,
+            snapshot_receiver,
+        }
     }
 
     /// Attempts to send a message on the channel consumed by the simulation
     /// task.
     pub fn send_to_sim(&self, msg: MessageToSimulation) {
-        if let Some(sender) = &self.sim_sender {
-            let _ = sender.send(msg);
-        }
+        let _ = self.sim_sender.send(msg);
     }
 
-    /// Attempts to send a message on a channel consumed by a connection handler
-    /// task.
-    pub fn send_to_conn_handler(&self, addr: &SocketAddr, msg: MessageToConnectionHandler) {
-        if let Some(sender) = &self.conn_handler_senders.get(addr) {
-            let _ = sender.send(msg);
-        }
+    /// Returns a receiver that is notified each time the simulation publishes
+    /// a snapshot.
+    pub fn subscribe(&self) -> watch::Receiver<Option<PublishedSnapshot>> {
+        self.snapshot_receiver.clone()
     }
 }
 
+/// A snapshot published by the simulation task. Snapshots are numbered
+/// consecutively so that subscribers can tell how many they skipped.
+#[derive(Clone, Debug)]
+pub struct PublishedSnapshot {
+    pub sequence: u64,
+    pub snapshot: Arc<Snapshot>,
+}
+
 /// Message consumed by the simulation task.
 #[derive(Debug)]
-pub struct MessageToSimulation {}
+pub enum MessageToSimulation {
+    /// A command received from a client that should be applied at the start
+    /// of `frame`, or of the next frame if none is given. The outcome is sent
+    /// on `reply`.
+    Command {
+        id: u64,
+        command: Command,
+        frame: Option<u64>,
+        reply: Sender<MessageToConnectionHandler>,
+    },
+
+    /// A message from a peer that simulates a neighboring region of the world.
+    FromPeer(PeerMessage),
+}
 
 /// Message consumed by a connection handler task.
-#[derive(Debug)]
-pub struct MessageToConnectionHandler {}
+#[derive(Clone, Debug)]
+pub enum MessageToConnectionHandler {
+    /// A message that should be forwarded to the client.
+    Send(MessageToClient),
+}
@@ -0,0 +1,24 @@
+//! The server clock that clients synchronize with. Times sent to clients are
+//! measured in milliseconds since the server started, so a client can relate
+//! its own clock to the start times of frames.
+
+use std::sync::OnceLock;
+use std::time::Instant;
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Starts the server clock. Times before this call are reported as zero.
+pub fn start() {
+    EPOCH.get_or_init(Instant::now);
+}
+
+/// Returns the server time of an instant in milliseconds.
+pub fn millis(instant: Instant) -> f64 {
+    let epoch = *EPOCH.get_or_init(Instant::now);
+    instant.saturating_duration_since(epoch).as_secs_f64() * 1000.0
+}
+
+/// Returns the current server time in milliseconds.
+pub fn now() -> f64 {
+    millis(Instant::now())
+}
@@ -0,0 +1,376 @@
+//! Validation and application of client commands to the simulation world
+
+use crate::auth::Role;
+use crate::protocol::{Command, CommandError};
+use crate::state::Paused;
+use simulation::{
+    component::{Group, Position, Velocity},
+    resources::continuum_crowds::{GroupGoals, SharedCell, SharedGrid, GROUP_COUNT},
+};
+use specs::prelude::*;
+
+/// The largest number of agents that a single command can spawn.
+const MAX_SPAWN_COUNT: u32 = 1000;
+
+/// The largest radius, in cells, that a single command can affect.
+const MAX_RADIUS: f32 = 64.0;
+
+/// Distance, in cells, between neighboring agents spawned by a single command.
+const SPAWN_SPACING: f32 = 0.3;
+
+/// Returns the least privileged role that is allowed to send the command.
+pub fn required_role(command: &Command) -> Role {
+    match command {
+        Command::SetPaused { .. } => Role::Admin,
+        _ => Role::Operator,
+    }
+}
+
+/// Validates the command against the world and applies it if it's valid. The
+/// world is left untouched if the command is invalid.
+pub fn apply(world: &mut World, command: &Command) -> Result<(), CommandError> {
+    match *command {
+        Command::SpawnAgents { x, y, count, group } => {
+            validate_point(world, x, y)?;
+            validate_group(group)?;
+            if count == 0 || count > MAX_SPAWN_COUNT {
+                return Err(CommandError::InvalidCount {
+                    count,
+                    max: MAX_SPAWN_COUNT,
+                });
+            }
+            spawn_agents(world, x, y, count, group);
+        }
+        Command::SetGoal { group, x, y } => {
+            validate_group(group)?;
+            validate_point(world, x, y)?;
+            world.write_resource::<GroupGoals>().0[group] = Some((x, y));
+        }
+        Command::PaintDiscomfort {
+            x,
+            y,
+            radius,
+            discomfort,
+        } => {
+            validate_point(world, x, y)?;
+            validate_radius(radius)?;
+            validate_finite("discomfort", discomfort)?;
+            if discomfort < 0.0 {
+                return Err(CommandError::InvalidDiscomfort { discomfort });
+            }
+            paint(world, x, y, radius, |cell| cell.discomfort = discomfort);
+        }
+        Command::EraseDiscomfort { x, y, radius } => {
+            validate_point(world, x, y)?;
+            validate_radius(radius)?;
+            paint(world, x, y, radius, |cell| cell.discomfort = 0.0);
+        }
+        Command::PaintObstacle { x, y, radius } => {
+            validate_point(world, x, y)?;
+            validate_radius(radius)?;
+            paint(world, x, y, radius, |cell| cell.is_obstacle = true);
+        }
+        Command::EraseObstacle { x, y, radius } => {
+            validate_point(world, x, y)?;
+            validate_radius(radius)?;
+            paint(world, x, y, radius, |cell| cell.is_obstacle = false);
+        }
+        Command::DeleteAgents { x, y, radius } => {
+            validate_point(world, x, y)?;
+            validate_radius(radius)?;
+            delete_agents(world, x, y, radius);
+        }
+        Command::SetPaused { paused } => world.write_resource::<Paused>().0 = paused,
+    }
+    Ok(())
+}
+
+pub fn validate_finite(field: &'static str, value: f32) -> Result<(), CommandError> {
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err(CommandError::NotFinite { field })
+    }
+}
+
+pub fn validate_point(world: &World, x: f32, y: f32) -> Result<(), CommandError> {
+    validate_finite("x", x)?;
+    validate_finite("y", y)?;
+    let shared_grid = world.read_resource::<SharedGrid>();
+    if x >= 0.0 && y >= 0.0 && shared_grid.in_bounds(x as usize, y as usize) {
+        Ok(())
+    } else {
+        Err(CommandError::OutOfBounds { x, y })
+    }
+}
+
+pub fn validate_group(group: usize) -> Result<(), CommandError> {
+    if group < GROUP_COUNT {
+        Ok(())
+    } else {
+        Err(CommandError::InvalidGroup { group })
+    }
+}
+
+fn validate_radius(radius: f32) -> Result<(), CommandError> {
+    validate_finite("radius", radius)?;
+    if (0.0..=MAX_RADIUS).contains(&radius) {
+        Ok(())
+    } else {
+        Err(CommandError::InvalidRadius {
+            radius,
+            max: MAX_RADIUS,
+        })
+    }
+}
+
+/// Spawns agents on a sunflower spiral around the point so that they don't
+/// all share the same position. Agents whose place on the spiral lies beyond
+/// an edge of the grid are moved onto the edge.
+fn spawn_agents(world: &mut World, x: f32, y: f32, count: u32, group: usize) {
+    const GOLDEN_ANGLE: f32 = 2.399_963;
+    let (max_x, max_y) = {
+        let shared_grid = world.read_resource::<SharedGrid>();
+        (
+            (shared_grid.width() as f32).next_down(),
+            (shared_grid.height() as f32).next_down(),
+        )
+    };
+    for i in 0..count {
+        let r = SPAWN_SPACING * (i as f32).sqrt();
+        let theta = i as f32 * GOLDEN_ANGLE;
+        world
+            .create_entity()
+            .with(Position {
+                x: (x + r * theta.cos()).clamp(0.0, max_x),
+                y: (y + r * theta.sin()).clamp(0.0, max_y),
+            })
+            .with(Velocity { x: 0.0, y: 0.0 })
+            .with(Group(group))
+            .build();
+    }
+}
+
+/// Applies `f` to the cell containing the point and to every cell whose center
+/// lies within `radius` of the point.
+fn paint<F>(world: &mut World, x: f32, y: f32, radius: f32, mut f: F)
+where
+    F: FnMut(&mut SharedCell),
+{
+    let mut shared_grid = world.write_resource::<SharedGrid>();
+    let min_x = (x - radius).floor().max(0.0) as usize;
+    let min_y = (y - radius).floor().max(0.0) as usize;
+    let max_x = (x + radius).floor() as usize;
+    let max_y = (y + radius).floor() as usize;
+    for cell_y in min_y..=max_y {
+        for cell_x in min_x..=max_x {
+            let dx = cell_x as f32 + 0.5 - x;
+            let dy = cell_y as f32 + 0.5 - y;
+            let contains_point = cell_x == x as usize && cell_y == y as usize;
+            if !contains_point && dx * dx + dy * dy > radius * radius {
+                continue;
+            }
+            if let Some(mut cell) = shared_grid.cell(cell_x, cell_y) {
+                f(&mut cell);
+                shared_grid.set_cell(cell_x, cell_y, cell);
+            }
+        }
+    }
+}
+
+fn delete_agents(world: &mut World, x: f32, y: f32, radius: f32) {
+    let entities = world.entities();
+    let positions = world.read_storage::<Position>();
+    for (entity, pos) in (&entities, &positions).join() {
+        let dx = pos.x - x;
+        let dy = pos.y - y;
+        if dx * dx + dy * dy <= radius * radius {
+            let _ = entities.delete(entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::Scenario;
+    use crate::state::State;
+
+    /// A 16 by 16 cell world without agents.
+    fn world() -> World {
+        let scenario = Scenario {
+            agents: vec![],
+            ..Scenario::default()
+        };
+        State::new(&scenario).world
+    }
+
+    fn positions(world: &World) -> Vec<(f32, f32)> {
+        (&world.read_storage::<Position>())
+            .join()
+            .map(|pos| (pos.x, pos.y))
+            .collect()
+    }
+
+    #[test]
+    fn invalid_commands_are_rejected_without_touching_the_world() {
+        let mut world = world();
+        let cases = [
+            (
+                Command::SpawnAgents {
+                    x: f32::NAN,
+                    y: 1.0,
+                    count: 1,
+                    group: 0,
+                },
+                CommandError::NotFinite { field: "x" },
+            ),
+            (
+                Command::SpawnAgents {
+                    x: 16.0,
+                    y: 1.0,
+                    count: 1,
+                    group: 0,
+                },
+                CommandError::OutOfBounds { x: 16.0, y: 1.0 },
+            ),
+            (
+                Command::SpawnAgents {
+                    x: 1.0,
+                    y: -0.5,
+                    count: 1,
+                    group: 0,
+                },
+                CommandError::OutOfBounds { x: 1.0, y: -0.5 },
+            ),
+            (
+                Command::SpawnAgents {
+                    x: 1.0,
+                    y: 1.0,
+                    count: 1,
+                    group: GROUP_COUNT,
+                },
+                CommandError::InvalidGroup { group: GROUP_COUNT },
+            ),
+            (
+                Command::SpawnAgents {
+                    x: 1.0,
+                    y: 1.0,
+                    count: 0,
+                    group: 0,
+                },
+                CommandError::InvalidCount {
+                    count: 0,
+                    max: MAX_SPAWN_COUNT,
+                },
+            ),
+            (
+                Command::SpawnAgents {
+                    x: 1.0,
+                    y: 1.0,
+                    count: MAX_SPAWN_COUNT + 1,
+                    group: 0,
+                },
+                CommandError::InvalidCount {
+                    count: MAX_SPAWN_COUNT + 1,
+                    max: MAX_SPAWN_COUNT,
+                },
+            ),
+            (
+                Command::PaintObstacle {
+                    x: 1.0,
+                    y: 1.0,
+                    radius: -1.0,
+                },
+                CommandError::InvalidRadius {
+                    radius: -1.0,
+                    max: MAX_RADIUS,
+                },
+            ),
+            (
+                Command::PaintObstacle {
+                    x: 1.0,
+                    y: 1.0,
+                    radius: f32::INFINITY,
+                },
+                CommandError::NotFinite { field: "radius" },
+            ),
+            (
+                Command::PaintDiscomfort {
+                    x: 1.0,
+                    y: 1.0,
+                    radius: 1.0,
+                    discomfort: -2.0,
+                },
+                CommandError::InvalidDiscomfort { discomfort: -2.0 },
+            ),
+            (
+                Command::SetGoal {
+                    group: 0,
+                    x: 1.0,
+                    y: 20.0,
+                },
+                CommandError::OutOfBounds { x: 1.0, y: 20.0 },
+            ),
+        ];
+        for (command, error) in cases {
+            assert_eq!(apply(&mut world, &command), Err(error), "{:?}", command);
+        }
+        assert!(positions(&world).is_empty());
+        assert_eq!(world.read_resource::<GroupGoals>().0[0], None);
+        let shared_grid = world.read_resource::<SharedGrid>();
+        let cell = shared_grid.cell(1, 1).unwrap();
+        assert!(!cell.is_obstacle);
+        assert_eq!(cell.discomfort, 0.0);
+    }
+
+    #[test]
+    fn agents_spawned_near_an_edge_stay_inside_the_grid() {
+        let mut world = world();
+        let command = Command::SpawnAgents {
+            x: 15.9,
+            y: 0.1,
+            count: MAX_SPAWN_COUNT,
+            group: 1,
+        };
+        apply(&mut world, &command).unwrap();
+        let positions = positions(&world);
+        assert_eq!(positions.len(), MAX_SPAWN_COUNT as usize);
+        for (x, y) in positions {
+            assert!((0.0..16.0).contains(&x) && (0.0..16.0).contains(&y));
+        }
+    }
+
+    #[test]
+    fn painting_affects_the_cells_within_the_radius() {
+        let mut world = world();
+        let command = Command::PaintDiscomfort {
+            x: 3.5,
+            y: 3.5,
+            radius: 1.0,
+            discomfort: 2.0,
+        };
+        apply(&mut world, &command).unwrap();
+        let shared_grid = world.read_resource::<SharedGrid>();
+        let discomfort = |x, y| shared_grid.cell(x, y).unwrap().discomfort;
+        assert_eq!(discomfort(3, 3), 2.0);
+        assert_eq!(discomfort(4, 3), 2.0);
+        assert_eq!(discomfort(3, 2), 2.0);
+        assert_eq!(discomfort(4, 4), 0.0);
+        assert_eq!(discomfort(5, 3), 0.0);
+    }
+
+    #[test]
+    fn only_admins_can_pause() {
+        assert_eq!(
+            required_role(&Command::SetPaused { paused: true }),
+            Role::Admin
+        );
+        let command = Command::DeleteAgents {
+            x: 1.0,
+            y: 1.0,
+            radius: 1.0,
+        };
+        assert_eq!(required_role(&command), Role::Operator);
+    }
+}
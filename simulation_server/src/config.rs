@@ -0,0 +1,514 @@
+//! Server configuration, read from command line flags and an optional TOML
+//! file. Flags take precedence over values in the file.
+
+use crate::auth::AuthConfig;
+use crate::channel::SlowClientPolicy;
+use crate::error::{Error, Result};
+use crate::rate_limit::{RateLimit, RateLimitConfig};
+use crate::shard::Region;
+use clap::Parser;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
+
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8080";
+const DEFAULT_FRAME_RATE: f64 = 31.25;
+const DEFAULT_MAX_CONNECTIONS: usize = 64;
+const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_MAX_ROOMS: usize = 16;
+const DEFAULT_ROOM_IDLE_TIMEOUT_SECS: f64 = 60.0;
+const DEFAULT_SESSION_RETENTION_SECS: f64 = 30.0;
+
+/// The highest frame rate or snapshot rate that can be configured.
+const MAX_RATE: f64 = 1000.0;
+
+#[derive(Debug, Parser)]
+#[command(about = "Runs a crowd simulation and serves it to WebSocket clients")]
+struct Args {
+    /// Path to a TOML config file
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Address to listen for WebSocket connections on
+    #[arg(long)]
+    bind_address: Option<String>,
+
+    /// Number of simulation frames per second
+    #[arg(long)]
+    frame_rate: Option<f64>,
+
+    /// Path to a JSON scenario that the world is initialized from
+    #[arg(long)]
+    scenario: Option<PathBuf>,
+
+    /// Path that the world is written to when the server shuts down
+    #[arg(long)]
+    save_path: Option<PathBuf>,
+
+    /// Maximum number of simultaneous client connections
+    #[arg(long)]
+    max_connections: Option<usize>,
+
+    /// Number of snapshots sent to clients per second
+    #[arg(long)]
+    snapshot_rate: Option<f64>,
+
+    /// One of off, error, warn, info, debug or trace
+    #[arg(long)]
+    log_level: Option<String>,
+
+    /// Maximum number of rooms that can run at the same time
+    #[arg(long)]
+    max_rooms: Option<usize>,
+
+    /// Number of seconds that a room can be empty before it's shut down
+    #[arg(long)]
+    room_idle_timeout: Option<f64>,
+
+    /// Number of seconds that a disconnected session can be resumed for
+    #[arg(long)]
+    session_retention: Option<f64>,
+
+    /// Address to serve health checks and metrics over HTTP on
+    #[arg(long)]
+    admin_bind_address: Option<String>,
+
+    /// Run this many frames as fast as possible without serving clients, print
+    /// how long each system took, and exit
+    #[arg(long)]
+    batch_frames: Option<u64>,
+}
+
+/// The contents of a TOML config file. Every field is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    bind_address: Option<String>,
+    frame_rate: Option<f64>,
+    scenario: Option<PathBuf>,
+    save_path: Option<PathBuf>,
+    max_connections: Option<usize>,
+    snapshot_rate: Option<f64>,
+    log_level: Option<String>,
+    slow_client_policy: Option<SlowClientPolicy>,
+    max_rooms: Option<usize>,
+    room_idle_timeout: Option<f64>,
+    session_retention: Option<f64>,
+    admin_bind_address: Option<String>,
+    region: Option<Region>,
+    peer_bind_address: Option<String>,
+    peer_secret: Option<String>,
+    peers: Vec<PeerConfig>,
+    auth: AuthConfig,
+    rate_limits: RateLimitConfig,
+}
+
+/// Validated server configuration.
+#[derive(Debug)]
+pub struct Config {
+    pub bind_address: SocketAddr,
+    pub frame_duration: Duration,
+    pub scenario: Option<PathBuf>,
+    pub save_path: Option<PathBuf>,
+    pub max_connections: usize,
+    pub snapshot_interval: Duration,
+    pub log_level: LevelFilter,
+    pub slow_client_policy: SlowClientPolicy,
+    pub max_rooms: usize,
+    pub room_idle_timeout: Duration,
+    pub session_retention: Duration,
+
+    /// Set when health checks and metrics are served over HTTP.
+    pub admin_bind_address: Option<SocketAddr>,
+
+    /// Set when the server runs a fixed number of frames instead of serving
+    /// clients.
+    pub batch_frames: Option<u64>,
+
+    /// Set when this process simulates one region of a world that's split
+    /// between several processes.
+    pub shard: Option<ShardConfig>,
+    pub auth: AuthConfig,
+    pub rate_limits: RateLimitConfig,
+}
+
+#[derive(Debug)]
+pub struct ShardConfig {
+    /// The region of the world that this process simulates.
+    pub region: Region,
+
+    /// Address to listen for links from peers on.
+    pub peer_bind_address: SocketAddr,
+
+    /// Secret that every message between peers is signed with. It can only be
+    /// left out when peers listen on a loopback address.
+    pub peer_secret: Option<String>,
+    pub peers: Vec<PeerConfig>,
+}
+
+/// Another process that simulates a region of the same world.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerConfig {
+    /// The address that the peer listens for links from peers on.
+    pub address: SocketAddr,
+    pub region: Region,
+}
+
+impl Config {
+    /// Reads the configuration from the process's command line arguments and
+    /// the config file they name, if any.
+    pub fn load() -> Result<Config> {
+        let args = Args::parse();
+        let file = match &args.config {
+            Some(path) => toml::from_str(&fs::read_to_string(path)?)?,
+            None => FileConfig::default(),
+        };
+        Config::merge(args, file)
+    }
+
+    fn merge(args: Args, file: FileConfig) -> Result<Config> {
+        let bind_address = args
+            .bind_address
+            .or(file.bind_address)
+            .unwrap_or_else(|| DEFAULT_BIND_ADDRESS.to_string());
+        let bind_address = bind_address
+            .parse()
+            .map_err(|e| invalid("bind_address", format!("`{}`: {}", bind_address, e)))?;
+
+        let frame_rate = args
+            .frame_rate
+            .or(file.frame_rate)
+            .unwrap_or(DEFAULT_FRAME_RATE);
+        validate_rate("frame_rate", frame_rate)?;
+
+        let snapshot_rate = args
+            .snapshot_rate
+            .or(file.snapshot_rate)
+            .unwrap_or(frame_rate);
+        validate_rate("snapshot_rate", snapshot_rate)?;
+        if snapshot_rate > frame_rate {
+            return Err(invalid(
+                "snapshot_rate",
+                format!("{} exceeds frame_rate {}", snapshot_rate, frame_rate),
+            ));
+        }
+
+        let max_connections = args
+            .max_connections
+            .or(file.max_connections)
+            .unwrap_or(DEFAULT_MAX_CONNECTIONS);
+        if max_connections == 0 {
+            return Err(invalid("max_connections", "must be at least 1".to_string()));
+        }
+
+        let log_level = args
+            .log_level
+            .or(file.log_level)
+            .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string());
+        let log_level = log_level
+            .parse()
+            .map_err(|_| invalid("log_level", format!("`{}` is not a log level", log_level)))?;
+
+        let max_rooms = args
+            .max_rooms
+            .or(file.max_rooms)
+            .unwrap_or(DEFAULT_MAX_ROOMS);
+        if max_rooms == 0 {
+            return Err(invalid("max_rooms", "must be at least 1".to_string()));
+        }
+
+        let room_idle_timeout = args
+            .room_idle_timeout
+            .or(file.room_idle_timeout)
+            .unwrap_or(DEFAULT_ROOM_IDLE_TIMEOUT_SECS);
+        let room_idle_timeout = parse_duration("room_idle_timeout", room_idle_timeout)?;
+
+        let session_retention = args
+            .session_retention
+            .or(file.session_retention)
+            .unwrap_or(DEFAULT_SESSION_RETENTION_SECS);
+        let session_retention = parse_duration("session_retention", session_retention)?;
+
+        let admin_bind_address = match args.admin_bind_address.or(file.admin_bind_address) {
+            Some(address) => Some(
+                address
+                    .parse()
+                    .map_err(|e| invalid("admin_bind_address", format!("`{}`: {}", address, e)))?,
+            ),
+            None => None,
+        };
+
+        if args.batch_frames == Some(0) {
+            return Err(invalid("batch_frames", "must be at least 1".to_string()));
+        }
+
+        let shard = match (file.region, file.peer_bind_address) {
+            (None, None) if file.peers.is_empty() => None,
+            (Some(region), Some(peer_bind_address)) => Some(validate_shard(
+                region,
+                &peer_bind_address,
+                file.peer_secret,
+                file.peers,
+            )?),
+            (None, _) => {
+                return Err(invalid(
+                    "region",
+                    "must be set when peers are configured".to_string(),
+                ))
+            }
+            (Some(_), None) => {
+                return Err(invalid(
+                    "peer_bind_address",
+                    "must be set when a region is configured".to_string(),
+                ))
+            }
+        };
+
+        validate_auth(&file.auth)?;
+        validate_rate_limits(&file.rate_limits)?;
+
+        Ok(Config {
+            bind_address,
+            frame_duration: Duration::from_secs_f64(1.0 / frame_rate),
+            scenario: args.scenario.or(file.scenario),
+            save_path: args.save_path.or(file.save_path),
+            max_connections,
+            snapshot_interval: Duration::from_secs_f64(1.0 / snapshot_rate),
+            log_level,
+            slow_client_policy: file
+                .slow_client_policy
+                .unwrap_or(SlowClientPolicy::DropStale),
+            max_rooms,
+            room_idle_timeout,
+            session_retention,
+            admin_bind_address,
+            batch_frames: args.batch_frames,
+            shard,
+            auth: file.auth,
+            rate_limits: file.rate_limits,
+        })
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// Reads the configuration from the contents of a config file alone, as if
+    /// no flags were given.
+    pub fn from_toml(file: &str) -> Result<Config> {
+        Config::merge(
+            Args::parse_from(["simulation_server"]),
+            toml::from_str(file)?,
+        )
+    }
+}
+
+fn parse_duration(field: &'static str, secs: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(secs).map_err(|_| {
+        invalid(
+            field,
+            format!("{} must be a non-negative number of seconds", secs),
+        )
+    })
+}
+
+fn validate_auth(auth: &AuthConfig) -> Result<()> {
+    if auth
+        .hmac_secret
+        .as_ref()
+        .is_some_and(|secret| secret.is_empty())
+    {
+        return Err(invalid("auth.hmac_secret", "must not be empty".to_string()));
+    }
+    if auth.tokens.iter().any(|t| t.token.is_empty()) {
+        return Err(invalid("auth.tokens", "must not be empty".to_string()));
+    }
+    Ok(())
+}
+
+fn validate_rate_limits(rate_limits: &RateLimitConfig) -> Result<()> {
+    if rate_limits.max_strikes == 0 {
+        return Err(invalid(
+            "rate_limits.max_strikes",
+            "must be at least 1".to_string(),
+        ));
+    }
+    let roles = [
+        ("rate_limits.viewer", rate_limits.viewer),
+        ("rate_limits.operator", rate_limits.operator),
+        ("rate_limits.admin", rate_limits.admin),
+    ];
+    for (
+        field,
+        RateLimit {
+            commands_per_second,
+            burst,
+        },
+    ) in roles
+    {
+        if !commands_per_second.is_finite() || commands_per_second <= 0.0 || burst == 0 {
+            return Err(invalid(
+                field,
+                "commands_per_second must be greater than 0 and burst at least 1".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn validate_shard(
+    region: Region,
+    peer_bind_address: &str,
+    peer_secret: Option<String>,
+    peers: Vec<PeerConfig>,
+) -> Result<ShardConfig> {
+    if region.width == 0 || region.height == 0 {
+        return Err(invalid(
+            "region",
+            "must contain at least 1 cell".to_string(),
+        ));
+    }
+    let peer_bind_address: SocketAddr = peer_bind_address.parse().map_err(|e| {
+        invalid(
+            "peer_bind_address",
+            format!("`{}`: {}", peer_bind_address, e),
+        )
+    })?;
+    match &peer_secret {
+        Some(secret) if secret.is_empty() => {
+            return Err(invalid("peer_secret", "must not be empty".to_string()));
+        }
+        None if !peer_bind_address.ip().is_loopback() => {
+            return Err(invalid(
+                "peer_secret",
+                "must be set unless peer_bind_address is a loopback address".to_string(),
+            ));
+        }
+        _ => {}
+    }
+    for (i, peer) in peers.iter().enumerate() {
+        if peer.region.width == 0 || peer.region.height == 0 {
+            return Err(invalid(
+                "peers",
+                format!("the region of peer {} must contain at least 1 cell", i),
+            ));
+        }
+        if peer.region.overlaps(&region) {
+            return Err(invalid(
+                "peers",
+                format!("the region of peer {} overlaps this process's region", i),
+            ));
+        }
+    }
+    Ok(ShardConfig {
+        region,
+        peer_bind_address,
+        peer_secret,
+        peers,
+    })
+}
+
+fn invalid(field: &'static str, message: String) -> Error {
+    Error::InvalidConfig { field, message }
+}
+
+fn validate_rate(field: &'static str, rate: f64) -> Result<()> {
+    if rate.is_finite() && rate > 0.0 && rate <= MAX_RATE {
+        Ok(())
+    } else {
+        Err(invalid(
+            field,
+            format!("{} must be greater than 0 and at most {}", rate, MAX_RATE),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(args: &[&str], file: &str) -> Result<Config> {
+        let args = Args::try_parse_from(["simulation_server"].iter().chain(args)).unwrap();
+        Config::merge(args, toml::from_str(file).unwrap())
+    }
+
+    fn invalid_field(args: &[&str], file: &str) -> &'static str {
+        match merge(args, file) {
+            Err(Error::InvalidConfig { field, .. }) => field,
+            other => panic!("expected an invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn defaults_apply_when_nothing_is_set() {
+        let config = merge(&[], "").unwrap();
+        assert_eq!(config.bind_address, DEFAULT_BIND_ADDRESS.parse().unwrap());
+        assert_eq!(config.max_connections, DEFAULT_MAX_CONNECTIONS);
+        assert_eq!(config.snapshot_interval, config.frame_duration);
+        assert_eq!(config.slow_client_policy, SlowClientPolicy::DropStale);
+        assert!(config.shard.is_none());
+    }
+
+    #[test]
+    fn flags_take_precedence_over_the_file() {
+        let file = "frame_rate = 10.0\nmax_rooms = 3\nmax_connections = 5";
+        let config = merge(&["--frame-rate", "20", "--max-rooms", "4"], file).unwrap();
+        assert_eq!(config.frame_duration, Duration::from_millis(50));
+        assert_eq!(config.max_rooms, 4);
+        assert_eq!(config.max_connections, 5);
+    }
+
+    #[test]
+    fn invalid_values_name_their_field() {
+        let cases: [(&[&str], &str, &str); 12] = [
+            (&["--bind-address", "localhost"], "", "bind_address"),
+            (&["--frame-rate", "0"], "", "frame_rate"),
+            (&[], "frame_rate = 10.0\nsnapshot_rate = 20.0", "snapshot_rate"),
+            (&[], "max_connections = 0", "max_connections"),
+            (&["--log-level", "loud"], "", "log_level"),
+            (&[], "room_idle_timeout = -1.0", "room_idle_timeout"),
+            (&["--batch-frames", "0"], "", "batch_frames"),
+            (&[], "[[peers]]\naddress = \"127.0.0.1:9000\"\nregion = { x = 0, y = 0, width = 1, height = 1 }", "region"),
+            (
+                &[],
+                "region = { x = 0, y = 0, width = 8, height = 8 }\npeer_bind_address = \"0.0.0.0:9000\"",
+                "peer_secret",
+            ),
+            (&[], "[auth]\nhmac_secret = \"\"", "auth.hmac_secret"),
+            (&[], "[rate_limits]\nmax_strikes = 0", "rate_limits.max_strikes"),
+            (
+                &[],
+                "[rate_limits.operator]\ncommands_per_second = 0.0\nburst = 1",
+                "rate_limits.operator",
+            ),
+        ];
+        for (args, file, field) in cases {
+            assert_eq!(invalid_field(args, file), field, "{:?} {:?}", args, file);
+        }
+    }
+
+    #[test]
+    fn errors_mention_the_field() {
+        let error = merge(&[], "max_rooms = 0").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid config field `max_rooms`: must be at least 1"
+        );
+    }
+
+    #[test]
+    fn shards_bound_to_loopback_need_no_secret() {
+        let file = "region = { x = 0, y = 0, width = 8, height = 16 }
+peer_bind_address = \"127.0.0.1:9081\"
+
+[[peers]]
+address = \"127.0.0.1:9082\"
+region = { x = 8, y = 0, width = 8, height = 16 }";
+        let shard = merge(&[], file).unwrap().shard.unwrap();
+        assert_eq!(shard.peers.len(), 1);
+        assert_eq!(shard.peer_secret, None);
+
+        let overlapping = file.replace("x = 8", "x = 4");
+        assert_eq!(invalid_field(&[], &overlapping), "peers");
+    }
+}
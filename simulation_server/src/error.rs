@@ -5,15 +5,38 @@ use std::result;
 
 pub type Result<T> = result::Result<T, Error>;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Error {
     IoError(std::io::Error),
-    TungsteniteError(tokio_tungstenite::tungstenite::Error),
+    TungsteniteError(Box<tokio_tungstenite::tungstenite::Error>),
+    HyperError(hyper::Error),
+    JsonError(serde_json::Error),
+    MessagePackDecodeError(rmp_serde::decode::Error),
+    MessagePackEncodeError(rmp_serde::encode::Error),
+    TomlError(toml::de::Error),
+    InvalidConfig {
+        field: &'static str,
+        message: String,
+    },
+    InvalidScenario {
+        field: String,
+        message: String,
+    },
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Network Error: {:?}", self)
+        match self {
+            Error::TomlError(e) => write!(f, "Invalid config file: {}", e),
+            Error::InvalidConfig { field, message } => {
+                write!(f, "Invalid config field `{}`: {}", field, message)
+            }
+            Error::InvalidScenario { field, message } => {
+                write!(f, "Invalid scenario field `{}`: {}", field, message)
+            }
+            _ => write!(f, "Network Error: {:?}", self),
+        }
     }
 }
 
@@ -22,6 +45,12 @@ impl error::Error for Error {
         match self {
             Error::IoError(e) => Some(e),
             Error::TungsteniteError(e) => Some(e),
+            Error::HyperError(e) => Some(e),
+            Error::JsonError(e) => Some(e),
+            Error::MessagePackDecodeError(e) => Some(e),
+            Error::MessagePackEncodeError(e) => Some(e),
+            Error::TomlError(e) => Some(e),
+            Error::InvalidConfig { .. } | Error::InvalidScenario { .. } => None,
         }
     }
 }
@@ -34,6 +63,36 @@ impl From<std::io::Error> for Error {
 
 impl From<tokio_tungstenite::tungstenite::Error> for Error {
     fn from(error: tokio_tungstenite::tungstenite::Error) -> Self {
-        Error::TungsteniteError(error)
+        Error::TungsteniteError(Box::new(error))
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(error: hyper::Error) -> Self {
+        Error::HyperError(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::JsonError(error)
+    }
+}
+
+impl From<rmp_serde::decode::Error> for Error {
+    fn from(error: rmp_serde::decode::Error) -> Self {
+        Error::MessagePackDecodeError(error)
+    }
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(error: rmp_serde::encode::Error) -> Self {
+        Error::MessagePackEncodeError(error)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(error: toml::de::Error) -> Self {
+        Error::TomlError(error)
     }
 }
@@ -1,72 +1,149 @@
+mod admin;
+mod auth;
+mod batch;
 mod channel;
+mod clock;
+mod command;
+mod config;
 mod error;
+mod metrics;
 mod network;
+mod peer;
+mod protocol;
+mod rate_limit;
+mod room;
+mod scenario;
+mod session;
+mod shard;
+mod sim;
 mod state;
 
-use channel::Senders;
+use config::Config;
 use error::Result;
-use futures::future;
-use futures::pin_mut;
-use simulation::{
-    component::{Position, Velocity},
-    frame::Frame,
-    resources::DurationSinceLastFrame,
-    systems::{SayHello, UpdatePos},
-};
-use specs::{Builder, DispatcherBuilder, World, WorldExt};
-use state::State;
-use std::{
-    env,
-    sync::{Arc, Mutex},
-    time::{Duration, Instant},
-};
-use tokio::{net::TcpListener, time::sleep};
+use log::{error, info};
+use metrics::Metrics;
+use network::Context;
+use room::RoomManager;
+use scenario::Scenario;
+use session::SessionStore;
+use shard::Shard;
+use std::{process, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, sync::watch, time::timeout};
 
-const FRAME_DURATION: Duration = Duration::from_millis(32u64);
+/// How long to wait for clients to be sent a close frame during shutdown.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let addr = env::args()
-        .nth(1)
-        .unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    clock::start();
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    env_logger::Builder::new()
+        .filter_level(config.log_level)
+        .init();
+    let scenario = match &config.scenario {
+        Some(path) => Scenario::load(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load {}: {}", path.display(), e);
+            process::exit(2);
+        }),
+        None => Scenario::default(),
+    };
 
-    let senders = Arc::new(Mutex::new(Senders::new()));
-    let listener = TcpListener::bind(&addr).await?;
-    let listen = network::listen(listener, senders);
-    let run_sim = run();
-    pin_mut!(listen, run_sim);
-    future::select(listen, run_sim).await;
-
-    Ok(())
-}
+    if let Some(frames) = config.batch_frames {
+        return batch::run(&config, &scenario, frames);
+    }
 
-async fn step(state: &mut State<'_, '_>) -> Result<()> {
-    if let Some(frame) = state.frame {
-        let duration_since_ideal_start = Instant::now() - frame.ideal_start_time;
-        if duration_since_ideal_start < frame.ideal_duration {
-            // Wait until it's time for the next frame to start.
-            sleep(frame.ideal_duration - duration_since_ideal_start).await;
+    let config = Arc::new(config);
+    let metrics = Arc::new(Metrics::new());
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    let shard = match &config.shard {
+        Some(shard_config) => {
+            let shard = Shard::start(shard_config, &shutdown_receiver);
+            let listener = TcpListener::bind(shard_config.peer_bind_address).await?;
+            info!(
+                "Simulating {:?}; listening for peers on {}",
+                shard_config.region, shard_config.peer_bind_address
+            );
+            Some((shard, listener))
         }
-        let next_frame = frame.next(Instant::now());
-        state.frame = Some(next_frame);
-        state.world.insert(DurationSinceLastFrame(
-            next_frame.start_time - frame.start_time,
-        ))
-    } else {
-        state.frame = Some(Frame::new(FRAME_DURATION, Instant::now()));
-        state.world.insert(DurationSinceLastFrame::default());
+        None => None,
+    };
+    let rooms = RoomManager::new(
+        config.clone(),
+        scenario,
+        shard.as_ref().map(|(shard, _)| shard.clone()),
+        metrics.clone(),
+    );
+    if let Some(address) = config.admin_bind_address {
+        let server = admin::bind(&address)?;
+        info!("Serving health checks and metrics on http://{}", address);
+        let metrics = metrics.clone();
+        let shard = shard.as_ref().map(|(shard, _)| shard.clone());
+        let shutdown = shutdown_receiver.clone();
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(server, metrics, shard, shutdown).await {
+                error!("The admin endpoint failed: {}", e);
+            }
+        });
+    }
+
+    if let Some((_, listener)) = shard {
+        let secret = config
+            .shard
+            .as_ref()
+            .and_then(|shard| shard.peer_secret.clone());
+        tokio::spawn(peer::listen(
+            listener,
+            secret,
+            rooms.clone(),
+            shutdown_receiver.clone(),
+        ));
+    }
+
+    let listener = TcpListener::bind(config.bind_address).await?;
+    info!("Listening on {}", config.bind_address);
+    let context = Context {
+        config: config.clone(),
+        rooms: rooms.clone(),
+        sessions: SessionStore::new(config.session_retention),
+        metrics,
+    };
+    let listen = network::listen(listener, context, shutdown_receiver);
+    tokio::pin!(listen);
+
+    tokio::select! {
+        result = shutdown_signal() => result?,
+        _ = &mut listen => {},
     }
 
-    // Executate a frame of the simulation.
-    state.dispatcher.dispatch(&state.world);
-    state.world.maintain();
+    // Stop accepting connections, close every client connection, and let each
+    // room's simulation finish its current frame.
+    info!("Shutting down");
+    let _ = shutdown_sender.send(true);
+    let _ = timeout(SHUTDOWN_TIMEOUT, listen).await;
+    rooms.shutdown().await;
 
     Ok(())
 }
 
-pub async fn run() -> Result<()> {
-    let mut state = State::new();
-    let sim_loop = async { while let Ok(()) = step(&mut state).await {} };
-    sim_loop.await;
+/// Resolves when the process receives SIGINT or SIGTERM.
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {},
+    }
+    Ok(())
+}
+
+/// Resolves when the process receives Ctrl-C.
+#[cfg(not(unix))]
+async fn shutdown_signal() -> Result<()> {
+    tokio::signal::ctrl_c().await?;
     Ok(())
 }
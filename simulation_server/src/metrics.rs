@@ -0,0 +1,434 @@
+//! Counters that describe the health of the running server, rendered in the
+//! Prometheus text format
+
+use simulation::profile::SystemTimings;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::net::SocketAddr;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, OnceLock,
+};
+use std::time::Duration;
+
+/// Upper bounds of the buckets of duration histograms, in seconds.
+const DURATION_BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// The number of messages to clients that were dropped because the client
+    /// fell behind.
+    dropped_messages: AtomicU64,
+
+    /// The number of client connections that are currently being served.
+    connections: AtomicU64,
+
+    /// The metrics of each running room, keyed by name.
+    rooms: Mutex<BTreeMap<String, Arc<RoomMetrics>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn record_dropped_messages(&self, count: u64) {
+        self.dropped_messages.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn dropped_messages(&self) -> u64 {
+        self.dropped_messages.load(Ordering::Relaxed)
+    }
+
+    pub fn record_connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_connection_closed(&self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Starts tracking the metrics of a room's simulation.
+    pub fn add_room(&self, name: &str) -> Arc<RoomMetrics> {
+        let room = Arc::new(RoomMetrics::default());
+        self.rooms
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), room.clone());
+        room
+    }
+
+    /// Stops tracking the metrics of a room's simulation, unless a new room
+    /// with the same name has already replaced them.
+    pub fn remove_room(&self, name: &str, room: &Arc<RoomMetrics>) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms.get(name).is_some_and(|r| Arc::ptr_eq(r, room)) {
+            rooms.remove(name);
+        }
+    }
+
+    /// Renders every metric in the Prometheus text format. `peer_queues` is
+    /// the number of messages queued for each peer.
+    pub fn render(&self, peer_queues: &[(SocketAddr, usize)]) -> String {
+        let mut out = String::new();
+        // Writing to a string can't fail.
+        let _ = self.write(&mut out, peer_queues);
+        out
+    }
+
+    fn write(&self, out: &mut String, peer_queues: &[(SocketAddr, usize)]) -> fmt::Result {
+        header(
+            out,
+            "simulation_connections",
+            "gauge",
+            "Client connections being served.",
+        )?;
+        writeln!(
+            out,
+            "simulation_connections {}",
+            self.connections.load(Ordering::Relaxed)
+        )?;
+        header(
+            out,
+            "simulation_dropped_messages_total",
+            "counter",
+            "Messages to clients dropped because the client fell behind.",
+        )?;
+        writeln!(
+            out,
+            "simulation_dropped_messages_total {}",
+            self.dropped_messages()
+        )?;
+        header(
+            out,
+            "simulation_peer_queue_depth",
+            "gauge",
+            "Messages waiting to be sent to each peer.",
+        )?;
+        for (address, depth) in peer_queues {
+            writeln!(
+                out,
+                "simulation_peer_queue_depth{{peer=\"{}\"}} {}",
+                address, depth
+            )?;
+        }
+
+        let rooms = self.rooms.lock().unwrap();
+        header(out, "simulation_rooms", "gauge", "Rooms that are running.")?;
+        writeln!(out, "simulation_rooms {}", rooms.len())?;
+        header(
+            out,
+            "simulation_agents",
+            "gauge",
+            "Agents in each room at the end of the last frame.",
+        )?;
+        for (name, room) in rooms.iter() {
+            writeln!(
+                out,
+                "simulation_agents{{room=\"{}\"}} {}",
+                name,
+                room.agents.load(Ordering::Relaxed)
+            )?;
+        }
+        header(
+            out,
+            "simulation_skipped_frames_total",
+            "counter",
+            "Frames skipped because the previous frame overran.",
+        )?;
+        for (name, room) in rooms.iter() {
+            writeln!(
+                out,
+                "simulation_skipped_frames_total{{room=\"{}\"}} {}",
+                name,
+                room.skipped_frames.load(Ordering::Relaxed)
+            )?;
+        }
+        header(
+            out,
+            "simulation_inbox_depth",
+            "gauge",
+            "Messages from clients and peers waiting at the start of the last frame.",
+        )?;
+        for (name, room) in rooms.iter() {
+            writeln!(
+                out,
+                "simulation_inbox_depth{{room=\"{}\"}} {}",
+                name,
+                room.inbox_depth.load(Ordering::Relaxed)
+            )?;
+        }
+        header(
+            out,
+            "simulation_scheduled_commands",
+            "gauge",
+            "Commands waiting for the frame they target.",
+        )?;
+        for (name, room) in rooms.iter() {
+            writeln!(
+                out,
+                "simulation_scheduled_commands{{room=\"{}\"}} {}",
+                name,
+                room.scheduled_commands.load(Ordering::Relaxed)
+            )?;
+        }
+        header(
+            out,
+            "simulation_frame_duration_seconds",
+            "histogram",
+            "Time spent computing each frame, excluding the wait for it to start.",
+        )?;
+        for (name, room) in rooms.iter() {
+            let labels = format!("room=\"{}\"", name);
+            room.frame_durations
+                .write(out, "simulation_frame_duration_seconds", &labels)?;
+        }
+        header(
+            out,
+            "simulation_system_duration_seconds",
+            "histogram",
+            "Time spent running each system of the dispatcher.",
+        )?;
+        for (name, room) in rooms.iter() {
+            for (system, histogram) in room.systems.lock().unwrap().iter() {
+                let labels = format!("room=\"{}\",system=\"{}\"", name, system);
+                histogram.write(out, "simulation_system_duration_seconds", &labels)?;
+            }
+        }
+        header(
+            out,
+            "simulation_system_duration_rolling_seconds",
+            "gauge",
+            "Min, mean and 99th percentile of each system's recent run times.",
+        )?;
+        for (name, room) in rooms.iter() {
+            let stats = room
+                .system_timings
+                .get()
+                .map(SystemTimings::stats)
+                .unwrap_or_default();
+            for stats in stats {
+                for (stat, duration) in
+                    [("min", stats.min), ("mean", stats.mean), ("p99", stats.p99)]
+                {
+                    writeln!(
+                        out,
+                        "simulation_system_duration_rolling_seconds{{room=\"{}\",system=\"{}\",stat=\"{}\"}} {}",
+                        name,
+                        stats.name,
+                        stat,
+                        duration.as_secs_f64()
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Metrics of a single room's simulation, updated by its thread at the end of
+/// each frame.
+#[derive(Debug, Default)]
+pub struct RoomMetrics {
+    frame_durations: Histogram,
+    skipped_frames: AtomicU64,
+    agents: AtomicU64,
+    inbox_depth: AtomicU64,
+    scheduled_commands: AtomicU64,
+    systems: Mutex<BTreeMap<&'static str, Histogram>>,
+
+    /// Set if the simulation is built with profiling.
+    system_timings: OnceLock<SystemTimings>,
+}
+
+impl RoomMetrics {
+    /// Records how long a frame took to compute and how many frames were
+    /// skipped before it.
+    pub fn record_frame(&self, duration: Duration, skipped: u64) {
+        self.frame_durations.observe(duration);
+        self.skipped_frames.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    pub fn record_systems(&self, durations: &[(&'static str, Duration)]) {
+        let mut systems = self.systems.lock().unwrap();
+        for &(name, duration) in durations {
+            systems.entry(name).or_default().observe(duration);
+        }
+    }
+
+    pub fn set_system_timings(&self, timings: SystemTimings) {
+        let _ = self.system_timings.set(timings);
+    }
+
+    pub fn set_agents(&self, count: usize) {
+        self.agents.store(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_inbox_depth(&self, depth: usize) {
+        self.inbox_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_scheduled_commands(&self, count: usize) {
+        self.scheduled_commands
+            .store(count as u64, Ordering::Relaxed);
+    }
+}
+
+/// A histogram of durations with the buckets in `DURATION_BUCKETS`.
+#[derive(Debug, Default)]
+struct Histogram {
+    /// The number of observations that fell in each bucket but not in any
+    /// smaller one. Observations larger than every bucket are only counted in
+    /// `count`.
+    buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(i) = DURATION_BUCKETS.iter().position(|&bound| secs <= bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) -> fmt::Result {
+        let mut cumulative = 0;
+        for (bound, bucket) in DURATION_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, cumulative
+            )?;
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, count)?;
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        writeln!(out, "{}_sum{{{}}} {}", name, labels, sum)?;
+        writeln!(out, "{}_count{{{}}} {}", name, labels, count)
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observations_are_counted_in_the_smallest_bucket_that_holds_them() {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_micros(501));
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_secs(3));
+
+        let buckets: Vec<u64> = histogram
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        assert_eq!(buckets, vec![1, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(histogram.count.load(Ordering::Relaxed), 4);
+        assert_eq!(histogram.sum_nanos.load(Ordering::Relaxed), 3_004_001_000);
+    }
+
+    #[test]
+    fn histograms_are_rendered_with_cumulative_buckets() {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_millis(2));
+        histogram.observe(Duration::from_secs(3));
+        let mut out = String::new();
+        histogram.write(&mut out, "duration", "room=\"a\"").unwrap();
+        assert_eq!(
+            out,
+            "duration_bucket{room=\"a\",le=\"0.0005\"} 0\n\
+             duration_bucket{room=\"a\",le=\"0.001\"} 0\n\
+             duration_bucket{room=\"a\",le=\"0.0025\"} 1\n\
+             duration_bucket{room=\"a\",le=\"0.005\"} 1\n\
+             duration_bucket{room=\"a\",le=\"0.01\"} 1\n\
+             duration_bucket{room=\"a\",le=\"0.025\"} 1\n\
+             duration_bucket{room=\"a\",le=\"0.05\"} 1\n\
+             duration_bucket{room=\"a\",le=\"0.1\"} 1\n\
+             duration_bucket{room=\"a\",le=\"0.25\"} 1\n\
+             duration_bucket{room=\"a\",le=\"0.5\"} 1\n\
+             duration_bucket{room=\"a\",le=\"1\"} 1\n\
+             duration_bucket{room=\"a\",le=\"2.5\"} 1\n\
+             duration_bucket{room=\"a\",le=\"+Inf\"} 2\n\
+             duration_sum{room=\"a\"} 3.002\n\
+             duration_count{room=\"a\"} 2\n"
+        );
+    }
+
+    #[test]
+    fn metrics_are_rendered_in_the_prometheus_text_format() {
+        let metrics = Metrics::new();
+        metrics.record_connection_opened();
+        metrics.record_dropped_messages(3);
+        let room = metrics.add_room("lobby");
+        room.record_frame(Duration::from_millis(20), 1);
+        room.set_agents(5);
+        room.set_inbox_depth(2);
+        room.set_scheduled_commands(1);
+        let peer: SocketAddr = "127.0.0.1:9082".parse().unwrap();
+
+        assert_eq!(
+            metrics.render(&[(peer, 4)]),
+            "# HELP simulation_connections Client connections being served.\n\
+             # TYPE simulation_connections gauge\n\
+             simulation_connections 1\n\
+             # HELP simulation_dropped_messages_total Messages to clients dropped because the client fell behind.\n\
+             # TYPE simulation_dropped_messages_total counter\n\
+             simulation_dropped_messages_total 3\n\
+             # HELP simulation_peer_queue_depth Messages waiting to be sent to each peer.\n\
+             # TYPE simulation_peer_queue_depth gauge\n\
+             simulation_peer_queue_depth{peer=\"127.0.0.1:9082\"} 4\n\
+             # HELP simulation_rooms Rooms that are running.\n\
+             # TYPE simulation_rooms gauge\n\
+             simulation_rooms 1\n\
+             # HELP simulation_agents Agents in each room at the end of the last frame.\n\
+             # TYPE simulation_agents gauge\n\
+             simulation_agents{room=\"lobby\"} 5\n\
+             # HELP simulation_skipped_frames_total Frames skipped because the previous frame overran.\n\
+             # TYPE simulation_skipped_frames_total counter\n\
+             simulation_skipped_frames_total{room=\"lobby\"} 1\n\
+             # HELP simulation_inbox_depth Messages from clients and peers waiting at the start of the last frame.\n\
+             # TYPE simulation_inbox_depth gauge\n\
+             simulation_inbox_depth{room=\"lobby\"} 2\n\
+             # HELP simulation_scheduled_commands Commands waiting for the frame they target.\n\
+             # TYPE simulation_scheduled_commands gauge\n\
+             simulation_scheduled_commands{room=\"lobby\"} 1\n\
+             # HELP simulation_frame_duration_seconds Time spent computing each frame, excluding the wait for it to start.\n\
+             # TYPE simulation_frame_duration_seconds histogram\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"0.0005\"} 0\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"0.001\"} 0\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"0.0025\"} 0\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"0.005\"} 0\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"0.01\"} 0\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"0.025\"} 1\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"0.05\"} 1\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"0.1\"} 1\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"0.25\"} 1\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"0.5\"} 1\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"1\"} 1\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"2.5\"} 1\n\
+             simulation_frame_duration_seconds_bucket{room=\"lobby\",le=\"+Inf\"} 1\n\
+             simulation_frame_duration_seconds_sum{room=\"lobby\"} 0.02\n\
+             simulation_frame_duration_seconds_count{room=\"lobby\"} 1\n\
+             # HELP simulation_system_duration_seconds Time spent running each system of the dispatcher.\n\
+             # TYPE simulation_system_duration_seconds histogram\n\
+             # HELP simulation_system_duration_rolling_seconds Min, mean and 99th percentile of each system's recent run times.\n\
+             # TYPE simulation_system_duration_rolling_seconds gauge\n"
+        );
+    }
+}
@@ -1,56 +1,562 @@
 //! Infrastructure for communication between the server and the network
 
-use crate::channel::{MessageToSimulation, Senders};
+use crate::auth::Grant;
+use crate::channel::{
+    MessageToConnectionHandler, MessageToSimulation, PublishedSnapshot, SlowClientPolicy,
+    CONN_HANDLER_QUEUE_CAPACITY, MAX_MISSED_SNAPSHOTS,
+};
+use crate::clock;
+use crate::command;
+use crate::config::Config;
 use crate::error::Result;
-use futures::future;
-use futures::stream::{StreamExt, TryStreamExt};
+use crate::metrics::Metrics;
+use crate::protocol::{CommandError, Encoding, MessageFromClient, MessageToClient, RoomError};
+use crate::room::{RoomManager, RoomMembership};
+use crate::session::{Session, SessionState, SessionStore, Takeover};
+use futures::{
+    future,
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use log::{info, warn};
+use std::error::Error;
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::{
+    mpsc::{channel, Receiver, Sender},
+    watch::{self, error::RecvError},
+    OwnedSemaphorePermit, Semaphore,
+};
+use tokio_tungstenite::WebSocketStream;
+use tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+use tungstenite::Message;
 
-/// Handles a TCP connection initiated by a client.
+/// Server-wide state shared by every connection handler task.
+#[derive(Clone)]
+pub struct Context {
+    pub config: Arc<Config>,
+    pub rooms: Arc<RoomManager>,
+    pub sessions: Arc<SessionStore>,
+    pub metrics: Arc<Metrics>,
+}
+
+/// How long a client has to authenticate after connecting, if authentication
+/// is enabled.
+const AUTHENTICATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait before accepting connections again after accepting one
+/// failed, for example because the process ran out of file descriptors.
+pub const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The room that a connection handler task's client is in.
+struct JoinedRoom {
+    membership: RoomMembership,
+    snapshots: watch::Receiver<Option<PublishedSnapshot>>,
+    last_sequence: Option<u64>,
+    missed_in_a_row: u64,
+}
+
+impl JoinedRoom {
+    fn new(membership: RoomMembership) -> JoinedRoom {
+        // Subscribe to the snapshots published by the room's simulation task.
+        let snapshots = membership.sim().subscribe();
+        JoinedRoom {
+            membership,
+            snapshots,
+            last_sequence: None,
+            missed_in_a_row: 0,
+        }
+    }
+
+    /// Rejoins a room when a session is resumed. The latest snapshot is sent
+    /// as a keyframe unless the client already received it.
+    fn resume(membership: RoomMembership, last_frame: Option<u64>) -> JoinedRoom {
+        let mut room = JoinedRoom::new(membership);
+        let latest_frame = room
+            .snapshots
+            .borrow()
+            .as_ref()
+            .map(|published| published.snapshot.frame);
+        if latest_frame.is_some() && latest_frame <= last_frame {
+            room.snapshots.borrow_and_update();
+        }
+        room
+    }
+}
+
+/// Resolves when the room's simulation publishes a snapshot, or never if the
+/// client isn't in a room.
+async fn snapshot_changed(room: &mut Option<JoinedRoom>) -> std::result::Result<(), RecvError> {
+    match room {
+        Some(room) => room.snapshots.changed().await,
+        None => future::pending().await,
+    }
+}
+
+/// A client's connection and the session it serves.
+struct Connection {
+    session: Session,
+
+    /// Requests from other connections to resume this connection's session.
+    takeovers: Receiver<Takeover>,
+
+    /// Clients receive snapshots and can send commands once they join a room.
+    room: Option<JoinedRoom>,
+
+    /// Replies are encoded in the same format as the last message received
+    /// from the client.
+    encoding: Encoding,
+
+    /// Attached to each command so that the simulation task can reply to this
+    /// client.
+    reply_sender: Sender<MessageToConnectionHandler>,
+}
+
+/// How a connection ended, which determines what happens to its session.
+enum Disconnect {
+    /// The connection dropped without a close handshake. The session is kept
+    /// so that the client can resume it.
+    Dropped,
+
+    /// The client or the server closed the connection, which ends the session.
+    Closed,
+
+    /// Another connection resumed the session.
+    TakenOver,
+}
+
+fn close_frame(code: CloseCode, reason: &'static str) -> Message {
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: reason.into(),
+    }))
+}
+
+/// Handles a TCP connection initiated by a client. The connection is closed
+/// immediately if no `permit` is available because the server is full.
 async fn handle_connection(
     socket: TcpStream,
     addr: SocketAddr,
-    senders: Arc<Mutex<Senders>>,
+    permit: Option<OwnedSemaphorePermit>,
+    context: Context,
+    shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
     let web_socket = tokio_tungstenite::accept_async(socket).await?;
-    let (_outgoing, incoming) = web_socket.split();
-
-    // Create a MPSC channel that the connection handler task will consume, and
-    // save the sender end of the channel into a data structure that is shared
-    // across tasks.
-    let (sender, _receiver) = unbounded_channel();
-    senders
-        .lock()
-        .unwrap()
-        .insert_conn_handler_sender(addr, sender);
-
-    // Handle each incoming WS message by sending a message to the simulation
-    // task.
-    let handle_connection_messages = incoming.try_for_each(|msg| {
-        println!(
-            "Received a message from {}: {}",
-            addr,
-            msg.to_text().unwrap()
-        );
-        senders.lock().unwrap().send_to_sim(MessageToSimulation {});
-        future::ok(())
-    });
-
-    // let handle_channel_messages = receiver.map(|m| -> {""});
-
-    let _ = handle_connection_messages.await;
-    senders.lock().unwrap().remove_conn_handler_sender(&addr);
-    Ok(())
+    let (mut outgoing, incoming) = web_socket.split();
+
+    if permit.is_none() {
+        warn!("Rejecting {} because the server is full", addr);
+        outgoing
+            .send(close_frame(CloseCode::Again, "server is full"))
+            .await?;
+        return Ok(());
+    }
+
+    context.metrics.record_connection_opened();
+    let session = Session::new(&context.config);
+    info!(
+        "Accepted a connection from {} as session {}",
+        addr, session.id
+    );
+
+    // Create a bounded MPSC channel that the connection handler task will
+    // consume.
+    let (reply_sender, replies) = channel(CONN_HANDLER_QUEUE_CAPACITY);
+    let mut connection = Connection {
+        takeovers: context.sessions.attach(&session.id),
+        session,
+        room: None,
+        encoding: Encoding::default(),
+        reply_sender,
+    };
+
+    let result = serve(
+        &mut connection,
+        outgoing,
+        incoming,
+        replies,
+        &context,
+        shutdown,
+    )
+    .await;
+    context.metrics.record_connection_closed();
+    let Connection { session, room, .. } = connection;
+    match result {
+        Ok(Disconnect::Dropped) | Err(_) if session.role.is_some() => {
+            info!("Session {} disconnected", session.id);
+            let state = SessionState {
+                role: session.role,
+                expires_at: session.expires_at,
+                commands: session.commands,
+                room: room.map(|room| room.membership),
+            };
+            context.sessions.detach(session.id, state);
+        }
+        Ok(Disconnect::TakenOver) => {}
+        _ => context.sessions.remove(&session.id),
+    }
+    result.map(|_| ())
 }
 
-/// Accept and handle each new TCP connection.
-pub async fn listen(listener: TcpListener, senders: Arc<Mutex<Senders>>) -> Result<()> {
-    while let Ok((socket, addr)) = listener.accept().await {
-        let senders = senders.clone();
-        tokio::spawn(async move { handle_connection(socket, addr, senders).await });
+/// Handles each incoming WS message by sending a message to the room's
+/// simulation task, and forwards each reply and snapshot to the client.
+async fn serve(
+    connection: &mut Connection,
+    mut outgoing: SplitSink<WebSocketStream<TcpStream>, Message>,
+    mut incoming: SplitStream<WebSocketStream<TcpStream>>,
+    mut replies: Receiver<MessageToConnectionHandler>,
+    context: &Context,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<Disconnect> {
+    let authentication_deadline = tokio::time::sleep(AUTHENTICATION_TIMEOUT);
+    tokio::pin!(authentication_deadline);
+    let mut closing = false;
+
+    loop {
+        let reply = tokio::select! {
+            // Check for shutdown first so that clients are sent a close frame
+            // rather than being dropped when the simulation task stops.
+            biased;
+
+            _ = shutdown.changed() => {
+                outgoing
+                    .send(close_frame(CloseCode::Away, "server is shutting down"))
+                    .await?;
+                return Ok(Disconnect::Closed);
+            },
+            Some(takeover) = connection.takeovers.recv() => {
+                info!("Session {} was resumed by another connection", connection.session.id);
+                let _ = takeover.send(SessionState {
+                    role: connection.session.role,
+                    expires_at: connection.session.expires_at,
+                    commands: connection.session.commands.clone(),
+                    room: connection.room.take().map(|room| room.membership),
+                });
+                outgoing
+                    .send(close_frame(CloseCode::Policy, "session resumed elsewhere"))
+                    .await?;
+                return Ok(Disconnect::TakenOver);
+            },
+            _ = &mut authentication_deadline, if connection.session.role.is_none() => {
+                warn!(
+                    "Disconnecting session {} because it didn't authenticate",
+                    connection.session.id
+                );
+                outgoing
+                    .send(close_frame(CloseCode::Policy, "authentication timed out"))
+                    .await?;
+                return Ok(Disconnect::Closed);
+            },
+            msg = incoming.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    connection.encoding = Encoding::Json;
+                    connection.handle_message(text.as_bytes(), context).await
+                }
+                Some(Ok(Message::Binary(bytes))) => {
+                    connection.encoding = Encoding::MessagePack;
+                    connection.handle_message(&bytes, context).await
+                }
+                // Tungstenite queues a pong in response to each ping and
+                // flushes it on the next read or write, so there is nothing
+                // left to do for ping and pong frames.
+                Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => None,
+                // Tungstenite echoes the close frame; the stream ends once the
+                // close handshake completes.
+                Some(Ok(Message::Close(_))) => {
+                    closing = true;
+                    None
+                }
+                Some(Err(e)) => return Err(e.into()),
+                None if closing => return Ok(Disconnect::Closed),
+                None => return Ok(Disconnect::Dropped),
+            },
+            Some(MessageToConnectionHandler::Send(msg)) = replies.recv() => Some(msg),
+            changed = snapshot_changed(&mut connection.room) => {
+                match (changed, &mut connection.room) {
+                    (Ok(()), Some(room)) => {
+                        // Only the latest snapshot is kept by the channel, so
+                        // any snapshots published since the last one that was
+                        // sent to this client have been skipped.
+                        let published = room.snapshots.borrow().clone();
+                        published.map(|PublishedSnapshot { sequence, snapshot }| {
+                            let missed = room.last_sequence.map_or(0, |last| sequence - last - 1);
+                            context.metrics.record_dropped_messages(missed);
+                            room.missed_in_a_row = if missed == 0 {
+                                0
+                            } else {
+                                room.missed_in_a_row + missed
+                            };
+                            room.last_sequence = Some(sequence);
+                            MessageToClient::Snapshot(snapshot)
+                        })
+                    }
+                    // The room's simulation task has stopped.
+                    (_, room) => room.take().map(|room| MessageToClient::LeftRoom {
+                        name: room.membership.name().to_string(),
+                    }),
+                }
+            },
+        };
+
+        let fell_behind = connection
+            .room
+            .as_ref()
+            .is_some_and(|room| room.missed_in_a_row >= MAX_MISSED_SNAPSHOTS);
+        if context.config.slow_client_policy == SlowClientPolicy::Disconnect && fell_behind {
+            warn!(
+                "Disconnecting session {} because it fell behind ({} messages dropped in total)",
+                connection.session.id,
+                context.metrics.dropped_messages()
+            );
+            outgoing
+                .send(close_frame(CloseCode::Policy, "client fell behind"))
+                .await?;
+            return Ok(Disconnect::Closed);
+        }
+
+        if let Some(reply) = reply {
+            let send = outgoing.send(connection.encoding.encode(&reply)?);
+            if context.config.slow_client_policy == SlowClientPolicy::Disconnect {
+                // A client that stops reading blocks the send once the socket's
+                // buffers fill, so the snapshots it misses meanwhile are never
+                // counted. Give up on it once it has been blocked for as long
+                // as it takes to publish that many.
+                let limit = context.config.snapshot_interval * MAX_MISSED_SNAPSHOTS as u32;
+                match tokio::time::timeout(limit, send).await {
+                    Ok(sent) => sent?,
+                    Err(_) => {
+                        warn!(
+                            "Disconnecting session {} because it stopped reading",
+                            connection.session.id
+                        );
+                        return Ok(Disconnect::Closed);
+                    }
+                }
+            } else {
+                send.await?;
+            }
+            if connection.session.strikes >= context.config.rate_limits.max_strikes {
+                warn!(
+                    "Disconnecting session {} because it kept exceeding its rate limit",
+                    connection.session.id
+                );
+                outgoing
+                    .send(close_frame(CloseCode::Policy, "rate limit exceeded"))
+                    .await?;
+                return Ok(Disconnect::Closed);
+            }
+            if let MessageToClient::AuthenticationFailed = reply {
+                outgoing
+                    .send(close_frame(CloseCode::Policy, "authentication failed"))
+                    .await?;
+                return Ok(Disconnect::Closed);
+            }
+        }
+    }
+}
+
+impl Connection {
+    /// Decodes a WS message from the client and either authenticates or
+    /// resumes the client's session, forwards the command it contains to the
+    /// simulation task of the client's room, or moves the client between
+    /// rooms. Returns a message that should be sent back to the client
+    /// immediately, if any.
+    async fn handle_message(&mut self, bytes: &[u8], context: &Context) -> Option<MessageToClient> {
+        let session = &mut self.session;
+        match self.encoding.decode(bytes) {
+            Ok(MessageFromClient::Authenticate { token }) => {
+                let grant = if context.config.auth.is_enabled() {
+                    context.config.auth.authenticate(&token)
+                } else {
+                    session.role.map(|role| Grant {
+                        role,
+                        expires_at: None,
+                    })
+                };
+                match grant {
+                    Some(grant) => {
+                        info!("Session {} authenticated as {:?}", session.id, grant.role);
+                        session.grant(grant, &context.config.rate_limits);
+                        Some(MessageToClient::Authenticated {
+                            session_id: session.id.clone(),
+                            role: grant.role,
+                        })
+                    }
+                    None => {
+                        warn!("Session {} failed to authenticate", session.id);
+                        Some(MessageToClient::AuthenticationFailed)
+                    }
+                }
+            }
+            Ok(MessageFromClient::Resume { session_id, .. }) if session_id == session.id => {
+                Some(MessageToClient::ResumeFailed { session_id })
+            }
+            Ok(MessageFromClient::Resume {
+                session_id,
+                last_frame,
+            }) => match context.sessions.resume(&session_id).await {
+                // The session ends rather than outliving the token that
+                // granted its role.
+                Some(state) if state.expires_at.is_some_and(|at| SystemTime::now() >= at) => {
+                    info!(
+                        "Session {} can't resume session {} because its token has expired",
+                        session.id, session_id
+                    );
+                    Some(MessageToClient::ResumeFailed { session_id })
+                }
+                Some(state) => {
+                    info!("Session {} resumed session {}", session.id, session_id);
+                    context.sessions.remove(&session.id);
+                    self.takeovers = context.sessions.attach(&session_id);
+                    self.session = Session {
+                        id: session_id.clone(),
+                        role: state.role,
+                        expires_at: state.expires_at,
+                        commands: state.commands,
+                        strikes: 0,
+                    };
+                    // Replacing the current room's membership leaves that room.
+                    self.room = state
+                        .room
+                        .map(|membership| JoinedRoom::resume(membership, last_frame));
+                    Some(MessageToClient::Resumed {
+                        session_id,
+                        role: state.role,
+                        room: self
+                            .room
+                            .as_ref()
+                            .map(|room| room.membership.name().to_string()),
+                    })
+                }
+                None => Some(MessageToClient::ResumeFailed { session_id }),
+            },
+            Ok(MessageFromClient::Command { id, command, frame }) => {
+                if session.has_expired(SystemTime::now()) {
+                    return Some(MessageToClient::CommandRejected {
+                        id,
+                        error: CommandError::TokenExpired,
+                    });
+                }
+                if let Err(retry_after) = session.take_command_token() {
+                    return Some(MessageToClient::CommandRejected {
+                        id,
+                        error: CommandError::RateLimited {
+                            retry_after_ms: retry_after.as_millis() as u64,
+                        },
+                    });
+                }
+                let required = command::required_role(&command);
+                match &self.room {
+                    Some(_) if !session.has_role(required) => {
+                        Some(MessageToClient::CommandRejected {
+                            id,
+                            error: CommandError::Forbidden { required },
+                        })
+                    }
+                    Some(room) => {
+                        room.membership
+                            .sim()
+                            .send_to_sim(MessageToSimulation::Command {
+                                id,
+                                command,
+                                frame,
+                                reply: self.reply_sender.clone(),
+                            });
+                        None
+                    }
+                    None => Some(MessageToClient::CommandRejected {
+                        id,
+                        error: CommandError::NotInRoom,
+                    }),
+                }
+            }
+            Ok(MessageFromClient::JoinRoom { name }) if session.role.is_none() => {
+                Some(MessageToClient::JoinRoomRejected {
+                    name,
+                    error: RoomError::NotAuthenticated,
+                })
+            }
+            Ok(MessageFromClient::JoinRoom { name }) => match context.rooms.join(&name) {
+                Ok(membership) => {
+                    // Replacing the previous room's membership leaves that room.
+                    self.room = Some(JoinedRoom::new(membership));
+                    Some(MessageToClient::JoinedRoom { name })
+                }
+                Err(error) => {
+                    info!("Rejected a request to join room `{}`: {}", name, error);
+                    Some(MessageToClient::JoinRoomRejected { name, error })
+                }
+            },
+            Ok(MessageFromClient::LeaveRoom) => {
+                self.room.take().map(|room| MessageToClient::LeftRoom {
+                    name: room.membership.name().to_string(),
+                })
+            }
+            Ok(MessageFromClient::Ping { client_time }) => {
+                let latest = self.room.as_ref().and_then(|room| {
+                    let published = room.snapshots.borrow();
+                    published
+                        .as_ref()
+                        .map(|published| (published.snapshot.frame, published.snapshot.time))
+                });
+                Some(MessageToClient::Pong {
+                    client_time,
+                    server_time: clock::now(),
+                    frame_duration_ms: context.config.frame_duration.as_secs_f64() * 1000.0,
+                    frame: latest.map(|(frame, _)| frame),
+                    frame_start_time: latest.map(|(_, time)| time),
+                })
+            }
+            Err(e) => Some(MessageToClient::ProtocolError {
+                message: e.source().map_or_else(|| e.to_string(), |e| e.to_string()),
+            }),
+        }
     }
+}
+
+/// Accept and handle each new TCP connection until `shutdown` becomes true,
+/// then wait for every connection handler task to close its connection.
+pub async fn listen(
+    listener: TcpListener,
+    context: Context,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let connection_permits = Arc::new(Semaphore::new(context.config.max_connections));
+
+    // Each connection handler task holds a clone of this sender. The receiver
+    // yields `None` once every clone has been dropped.
+    let (done_sender, mut done_receiver) = channel::<()>(1);
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.changed() => break,
+        };
+        let (socket, addr) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept a connection: {}", e);
+                tokio::select! {
+                    _ = tokio::time::sleep(ACCEPT_RETRY_DELAY) => continue,
+                    _ = shutdown.changed() => break,
+                }
+            }
+        };
+        let permit = connection_permits.clone().try_acquire_owned().ok();
+        let context = context.clone();
+        let shutdown = shutdown.clone();
+        let done_sender = done_sender.clone();
+        tokio::spawn(async move {
+            let result = handle_connection(socket, addr, permit, context, shutdown).await;
+            drop(done_sender);
+            result
+        });
+    }
+
+    drop(listener);
+    drop(done_sender);
+    let _ = done_receiver.recv().await;
     Ok(())
 }
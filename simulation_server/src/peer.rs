@@ -0,0 +1,353 @@
+//! Links between server processes that simulate neighboring regions of the
+//! world. Peer messages are encoded as MessagePack in binary WS messages. When
+//! a peer secret is configured, each message is preceded by a timestamp and an
+//! HMAC-SHA256 signature of both. A link that sends a message with a bad
+//! signature, or with a timestamp that isn't later than the last one on the
+//! link or is too old, is closed so that recorded messages can't be replayed.
+
+use crate::auth;
+use crate::error::Result;
+use crate::network::ACCEPT_RETRY_DELAY;
+use crate::room::RoomManager;
+use crate::scenario::ScenarioAgent;
+use futures::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc::Receiver, watch};
+use tungstenite::Message;
+
+/// The number of messages that can be queued for a peer before further
+/// messages to it are dropped.
+pub const PEER_QUEUE_CAPACITY: usize = 256;
+
+/// How long to wait before trying to reconnect to a peer.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The length of the signature that precedes each message when a peer secret
+/// is configured.
+const SIGNATURE_LEN: usize = 32;
+
+/// The length of the timestamp, in microseconds since the UNIX epoch, that
+/// follows the signature when a peer secret is configured.
+const STAMP_LEN: usize = 8;
+
+/// How old a signed message can be when it arrives. This bounds how long a
+/// recorded message can be replayed over a new link, and has to allow for the
+/// clocks of the processes differing.
+const MAX_MESSAGE_AGE: Duration = Duration::from_secs(10);
+
+/// Message sent from one server process to another.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PeerMessage {
+    /// Cells along the border of the sender's region at the end of a frame.
+    /// `occupied` is whether the sender has clients in the room, which keeps
+    /// the receiver's copy of the room running while it has none of its own.
+    BorderCells {
+        room: String,
+        occupied: bool,
+        cells: Vec<BorderCell>,
+    },
+
+    /// Agents that crossed from the sender's region into the receiver's region.
+    Handoff {
+        room: String,
+        agents: Vec<ScenarioAgent>,
+    },
+}
+
+impl PeerMessage {
+    pub fn room(&self) -> &str {
+        match self {
+            PeerMessage::BorderCells { room, .. } | PeerMessage::Handoff { room, .. } => room,
+        }
+    }
+
+    /// Returns whether the message shows that the room is in use elsewhere.
+    pub fn is_from_occupied_room(&self) -> bool {
+        match self {
+            PeerMessage::BorderCells { occupied, .. } => *occupied,
+            PeerMessage::Handoff { .. } => false,
+        }
+    }
+}
+
+/// The parts of a `SharedCell` that a neighboring region needs.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BorderCell {
+    pub x: usize,
+    pub y: usize,
+    pub density: f32,
+    pub height: f32,
+    pub discomfort: f32,
+    pub is_obstacle: bool,
+    pub avg_velocity: (f32, f32),
+}
+
+/// Sends each message from `receiver` to the peer at `address`, reconnecting
+/// whenever the link fails, until `shutdown` becomes true. Messages queue up
+/// while the peer is unreachable. A handoff that fails to send is sent again
+/// first once the link is back, since its agents have already left this
+/// process's world; border cells are superseded by the next frame's instead.
+pub async fn connect(
+    address: SocketAddr,
+    secret: Option<String>,
+    mut receiver: Receiver<PeerMessage>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let url = format!("ws://{}", address);
+    let mut unsent = None;
+    let mut stamp = 0;
+    loop {
+        let connected = tokio::select! {
+            _ = shutdown.changed() => return,
+            connected = tokio_tungstenite::connect_async(&url) => connected,
+        };
+        let mut socket = match connected {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                debug!("Failed to connect to peer {}: {}", address, e);
+                tokio::select! {
+                    _ = shutdown.changed() => return,
+                    _ = tokio::time::sleep(RECONNECT_INTERVAL) => continue,
+                }
+            }
+        };
+        info!("Connected to peer {}", address);
+
+        loop {
+            let msg = match unsent.take() {
+                Some(msg) => msg,
+                None => tokio::select! {
+                    _ = shutdown.changed() => {
+                        let _ = socket.close(None).await;
+                        return;
+                    },
+                    msg = receiver.recv() => match msg {
+                        Some(msg) => msg,
+                        None => return,
+                    },
+                },
+            };
+            // Stamps must increase even if the clock doesn't.
+            stamp = micros_since_epoch(SystemTime::now()).max(stamp + 1);
+            let bytes = match encode(secret.as_deref(), stamp, &msg) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    warn!("Failed to encode a message to peer {}: {}", address, e);
+                    continue;
+                }
+            };
+            if let Err(e) = socket.send(Message::Binary(bytes)).await {
+                warn!("Lost the link to peer {}: {}", address, e);
+                if let PeerMessage::Handoff { .. } = msg {
+                    unsent = Some(msg);
+                }
+                break;
+            }
+        }
+    }
+}
+
+fn micros_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_micros() as u64)
+}
+
+/// Encodes the message, preceded by its signature and `stamp` if there's a
+/// secret.
+fn encode(
+    secret: Option<&str>,
+    stamp: u64,
+    msg: &PeerMessage,
+) -> std::result::Result<Vec<u8>, rmp_serde::encode::Error> {
+    let bytes = rmp_serde::to_vec_named(msg)?;
+    Ok(match secret {
+        Some(secret) => {
+            let mut stamped = stamp.to_be_bytes().to_vec();
+            stamped.extend(bytes);
+            let mut signed = auth::sign(secret, &stamped);
+            signed.extend(stamped);
+            signed
+        }
+        None => bytes,
+    })
+}
+
+/// Returns the encoded message in the bytes, or `None` if there's a secret and
+/// the bytes don't start with a valid signature of the rest, or their stamp
+/// isn't later than `last_stamp` or is more than `MAX_MESSAGE_AGE` before
+/// `now`. Sets `last_stamp` to the stamp of a message that's accepted.
+fn verify<'a>(
+    secret: Option<&str>,
+    bytes: &'a [u8],
+    last_stamp: &mut u64,
+    now: SystemTime,
+) -> Option<&'a [u8]> {
+    match secret {
+        Some(secret) => {
+            if bytes.len() < SIGNATURE_LEN + STAMP_LEN {
+                return None;
+            }
+            let (signature, stamped) = bytes.split_at(SIGNATURE_LEN);
+            if !auth::verify(secret, stamped, signature) {
+                return None;
+            }
+            let (stamp, msg) = stamped.split_at(STAMP_LEN);
+            let stamp = u64::from_be_bytes(stamp.try_into().unwrap());
+            let oldest = micros_since_epoch(now).saturating_sub(MAX_MESSAGE_AGE.as_micros() as u64);
+            if stamp <= *last_stamp || stamp < oldest {
+                return None;
+            }
+            *last_stamp = stamp;
+            Some(msg)
+        }
+        None => Some(bytes),
+    }
+}
+
+/// Handles a link initiated by a peer by delivering each of its messages to
+/// the room it's tagged with.
+async fn handle_peer(
+    socket: TcpStream,
+    addr: SocketAddr,
+    secret: Option<Arc<str>>,
+    rooms: Arc<RoomManager>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut web_socket = tokio_tungstenite::accept_async(socket).await?;
+    info!("Accepted a link from peer {}", addr);
+    let mut last_stamp = 0;
+    loop {
+        let msg = tokio::select! {
+            _ = shutdown.changed() => return Ok(()),
+            msg = web_socket.next() => msg,
+        };
+        match msg {
+            Some(Ok(Message::Binary(bytes))) => {
+                let bytes = match verify(
+                    secret.as_deref(),
+                    &bytes,
+                    &mut last_stamp,
+                    SystemTime::now(),
+                ) {
+                    Some(bytes) => bytes,
+                    None => {
+                        warn!(
+                            "Closing the link from {}: bad signature or replayed message",
+                            addr
+                        );
+                        return Ok(());
+                    }
+                };
+                match rmp_serde::from_slice(bytes) {
+                    Ok(msg) => rooms.deliver(msg),
+                    Err(e) => warn!("Failed to decode a message from peer {}: {}", addr, e),
+                }
+            }
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e.into()),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Accepts and handles links from peers until `shutdown` becomes true. Only
+/// messages signed with `secret` are accepted if it's set.
+pub async fn listen(
+    listener: TcpListener,
+    secret: Option<String>,
+    rooms: Arc<RoomManager>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let secret: Option<Arc<str>> = secret.map(Into::into);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.changed() => break,
+        };
+        let (socket, addr) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept a link from a peer: {}", e);
+                tokio::select! {
+                    _ = tokio::time::sleep(ACCEPT_RETRY_DELAY) => continue,
+                    _ = shutdown.changed() => break,
+                }
+            }
+        };
+        tokio::spawn(handle_peer(
+            socket,
+            addr,
+            secret.clone(),
+            rooms.clone(),
+            shutdown.clone(),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handoff() -> PeerMessage {
+        PeerMessage::Handoff {
+            room: "lobby".to_string(),
+            agents: vec![],
+        }
+    }
+
+    /// A stamp one second after `now()`.
+    const STAMP: u64 = 1_000_000_001_000_000;
+
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_000_000_000)
+    }
+
+    #[test]
+    fn signed_messages_verify_with_the_same_secret_only() {
+        let bytes = encode(Some("secret"), STAMP, &handoff()).unwrap();
+        let msg = verify(Some("secret"), &bytes, &mut 0, now()).unwrap();
+        assert_eq!(msg, &rmp_serde::to_vec_named(&handoff()).unwrap()[..]);
+        assert_eq!(verify(Some("other"), &bytes, &mut 0, now()), None);
+    }
+
+    #[test]
+    fn tampered_and_unsigned_messages_are_rejected() {
+        let mut bytes = encode(Some("secret"), STAMP, &handoff()).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        assert_eq!(verify(Some("secret"), &bytes, &mut 0, now()), None);
+
+        let mut bytes = encode(Some("secret"), STAMP, &handoff()).unwrap();
+        bytes[SIGNATURE_LEN] ^= 1;
+        assert_eq!(verify(Some("secret"), &bytes, &mut 0, now()), None);
+
+        let unsigned = encode(None, STAMP, &handoff()).unwrap();
+        assert_eq!(verify(Some("secret"), &unsigned, &mut 0, now()), None);
+        assert_eq!(verify(None, &unsigned, &mut 0, now()), Some(&unsigned[..]));
+    }
+
+    #[test]
+    fn replayed_and_stale_messages_are_rejected() {
+        let mut last_stamp = 0;
+        let first = encode(Some("secret"), STAMP, &handoff()).unwrap();
+        let second = encode(Some("secret"), STAMP + 1, &handoff()).unwrap();
+        assert!(verify(Some("secret"), &first, &mut last_stamp, now()).is_some());
+        assert_eq!(last_stamp, STAMP);
+        assert_eq!(verify(Some("secret"), &first, &mut last_stamp, now()), None);
+        assert!(verify(Some("secret"), &second, &mut last_stamp, now()).is_some());
+        assert_eq!(verify(Some("secret"), &first, &mut last_stamp, now()), None);
+
+        // A new link starts without a last stamp, but only accepts messages
+        // that are recent.
+        let later = now() + MAX_MESSAGE_AGE + Duration::from_secs(2);
+        assert_eq!(verify(Some("secret"), &second, &mut 0, later), None);
+        let oldest = now() + MAX_MESSAGE_AGE + Duration::from_secs(1);
+        assert!(verify(Some("secret"), &second, &mut 0, oldest).is_some());
+    }
+}
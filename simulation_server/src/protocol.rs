@@ -0,0 +1,358 @@
+//! Messages exchanged between the server and clients over the network
+
+use crate::auth::Role;
+use crate::error::Result;
+use crate::session::SessionId;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use tungstenite::Message;
+
+/// Format in which messages are encoded on the wire. Binary WS messages are
+/// encoded as MessagePack and text WS messages are encoded as JSON.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    #[default]
+    MessagePack,
+}
+
+impl Encoding {
+    pub fn decode(self, bytes: &[u8]) -> Result<MessageFromClient> {
+        match self {
+            Encoding::Json => Ok(serde_json::from_slice(bytes)?),
+            Encoding::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+        }
+    }
+
+    pub fn encode(self, msg: &MessageToClient) -> Result<Message> {
+        match self {
+            Encoding::Json => Ok(Message::Text(serde_json::to_string(msg)?)),
+            Encoding::MessagePack => Ok(Message::Binary(rmp_serde::to_vec_named(msg)?)),
+        }
+    }
+}
+
+/// Message sent by a client to the server.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageFromClient {
+    /// Authenticates the client with a token. If authentication is enabled,
+    /// this must be the first message that the client sends.
+    Authenticate { token: String },
+
+    /// Resumes a session whose connection dropped, restoring its role and
+    /// room. `last_frame` is the frame of the last snapshot that the client
+    /// received; the latest snapshot is sent right away unless it's that one.
+    Resume {
+        session_id: SessionId,
+        last_frame: Option<u64>,
+    },
+
+    /// Requests that a command be applied to the simulation. The `id` is chosen
+    /// by the client and is echoed back in the reply. If `frame` is given the
+    /// command is held until that frame starts; otherwise, or if that frame
+    /// has already started, it's applied at the start of the next frame.
+    Command {
+        id: u64,
+        command: Command,
+        #[serde(default)]
+        frame: Option<u64>,
+    },
+
+    /// Joins the named room, creating it if it doesn't exist. A client is in at
+    /// most one room at a time, so on success this leaves any room the client
+    /// was in before.
+    JoinRoom { name: String },
+
+    /// Leaves the room the client is in, if any.
+    LeaveRoom,
+
+    /// Measures the round trip time to the server and the offset of the
+    /// server clock. `client_time` is read from the client's clock and is
+    /// echoed back in the reply.
+    Ping { client_time: f64 },
+}
+
+/// Message sent by the server to a client.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageToClient {
+    /// The client authenticated and was granted `role`.
+    Authenticated { session_id: SessionId, role: Role },
+
+    /// The client's token was rejected. The connection is closed after this
+    /// message.
+    AuthenticationFailed,
+
+    /// The session was resumed. If the session is in a room, the latest
+    /// snapshot follows.
+    Resumed {
+        session_id: SessionId,
+        role: Option<Role>,
+        room: Option<String>,
+    },
+
+    /// The session doesn't exist, has expired, or is the one the client
+    /// already has. The client keeps its current session.
+    ResumeFailed { session_id: SessionId },
+
+    /// The command with the given id was applied to the simulation at the
+    /// start of `frame`.
+    CommandApplied { id: u64, frame: u64 },
+
+    /// The command with the given id failed validation and was not applied.
+    CommandRejected { id: u64, error: CommandError },
+
+    /// A message from the client could not be decoded.
+    ProtocolError { message: String },
+
+    /// The client joined the named room and will be sent its snapshots.
+    JoinedRoom { name: String },
+
+    /// The client could not join the named room.
+    JoinRoomRejected { name: String, error: RoomError },
+
+    /// The client is no longer in the named room, either because it asked to
+    /// leave or because the room's simulation stopped.
+    LeftRoom { name: String },
+
+    /// The state of the simulation at the end of a frame.
+    Snapshot(Arc<Snapshot>),
+
+    /// The reply to a ping. `server_time` is the server clock when the ping
+    /// was handled. If the client is in a room, `frame` is the latest frame
+    /// of the room's simulation that was sent as a snapshot and
+    /// `frame_start_time` is the server time at which that frame started.
+    /// Times are in milliseconds.
+    Pong {
+        client_time: f64,
+        server_time: f64,
+        frame_duration_ms: f64,
+        frame: Option<u64>,
+        frame_start_time: Option<f64>,
+    },
+}
+
+/// The state of the simulation at the end of a frame.
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub frame: u64,
+
+    /// The server time at which the frame started, in milliseconds.
+    pub time: f64,
+    pub agents: Vec<AgentSnapshot>,
+}
+
+/// The state of a single agent at the end of a frame.
+#[derive(Debug, Serialize)]
+pub struct AgentSnapshot {
+    pub id: u32,
+    pub x: f32,
+    pub y: f32,
+    pub group: usize,
+}
+
+/// A change to the simulation requested by a client. Positions and radii are
+/// measured in cells.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Command {
+    /// Spawn `count` agents belonging to `group` around the point.
+    SpawnAgents {
+        x: f32,
+        y: f32,
+        count: u32,
+        group: usize,
+    },
+
+    /// Move the goal of `group` to the point.
+    SetGoal { group: usize, x: f32, y: f32 },
+
+    /// Set the discomfort of every cell within `radius` of the point.
+    PaintDiscomfort {
+        x: f32,
+        y: f32,
+        radius: f32,
+        discomfort: f32,
+    },
+
+    /// Reset the discomfort of every cell within `radius` of the point.
+    EraseDiscomfort { x: f32, y: f32, radius: f32 },
+
+    /// Mark every cell within `radius` of the point as an obstacle.
+    PaintObstacle { x: f32, y: f32, radius: f32 },
+
+    /// Clear the obstacle flag of every cell within `radius` of the point.
+    EraseObstacle { x: f32, y: f32, radius: f32 },
+
+    /// Delete every agent within `radius` of the point.
+    DeleteAgents { x: f32, y: f32, radius: f32 },
+
+    /// Pause or resume the simulation. Snapshots are still sent while the
+    /// simulation is paused.
+    SetPaused { paused: bool },
+}
+
+/// Reason that a command was rejected.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CommandError {
+    /// A numeric field was NaN or infinite.
+    NotFinite { field: &'static str },
+
+    /// The point does not lie inside the simulation grid.
+    OutOfBounds { x: f32, y: f32 },
+
+    /// The group index does not refer to an existing group.
+    InvalidGroup { group: usize },
+
+    /// The number of agents to spawn was zero or too large.
+    InvalidCount { count: u32, max: u32 },
+
+    /// The radius was negative or too large.
+    InvalidRadius { radius: f32, max: f32 },
+
+    /// The discomfort was negative.
+    InvalidDiscomfort { discomfort: f32 },
+
+    /// The client must join a room before sending commands.
+    NotInRoom,
+
+    /// The command targeted a frame further ahead of the simulation than the
+    /// server holds commands for.
+    FrameTooFarAhead { frame: u64, max: u64 },
+
+    /// The client's role doesn't allow the command.
+    Forbidden { required: Role },
+
+    /// The token the client authenticated with has expired. The client must
+    /// authenticate again before sending more commands.
+    TokenExpired,
+
+    /// The client sent commands faster than its rate limit allows. Clients
+    /// that keep exceeding their limit are disconnected.
+    RateLimited { retry_after_ms: u64 },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NotFinite { field } => write!(f, "`{}` must be finite", field),
+            CommandError::OutOfBounds { x, y } => write!(f, "({}, {}) is out of bounds", x, y),
+            CommandError::InvalidGroup { group } => write!(f, "group {} does not exist", group),
+            CommandError::InvalidCount { count, max } => {
+                write!(f, "count {} must be between 1 and {}", count, max)
+            }
+            CommandError::InvalidRadius { radius, max } => {
+                write!(f, "radius {} must be between 0 and {}", radius, max)
+            }
+            CommandError::InvalidDiscomfort { discomfort } => {
+                write!(f, "discomfort {} must not be negative", discomfort)
+            }
+            CommandError::FrameTooFarAhead { frame, max } => write!(
+                f,
+                "frame {} is more than {} frames ahead of the simulation",
+                frame, max
+            ),
+            CommandError::NotInRoom => write!(f, "the client is not in a room"),
+            CommandError::Forbidden { required } => {
+                write!(f, "the command requires the {:?} role", required)
+            }
+            CommandError::TokenExpired => write!(f, "the client's token has expired"),
+            CommandError::RateLimited { retry_after_ms } => {
+                write!(f, "rate limit exceeded, retry in {} ms", retry_after_ms)
+            }
+        }
+    }
+}
+
+/// Reason that a client could not join a room.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoomError {
+    /// The name was empty, too long, or contained characters other than ASCII
+    /// letters, digits, `-` and `_`.
+    InvalidName { max_len: usize },
+
+    /// The room doesn't exist and the server already runs as many rooms as it
+    /// allows.
+    TooManyRooms { max: usize },
+
+    /// The client must authenticate before joining a room.
+    NotAuthenticated,
+
+    /// The server is shutting down.
+    ShuttingDown,
+
+    /// The room's simulation could not be started.
+    Unavailable,
+}
+
+impl fmt::Display for RoomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoomError::InvalidName { max_len } => write!(
+                f,
+                "room names must be 1 to {} ASCII letters, digits, `-` or `_`",
+                max_len
+            ),
+            RoomError::TooManyRooms { max } => write!(f, "the server already runs {} rooms", max),
+            RoomError::NotAuthenticated => write!(f, "the client is not authenticated"),
+            RoomError::ShuttingDown => write!(f, "the server is shutting down"),
+            RoomError::Unavailable => write!(f, "the room could not be started"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use serde_json::json;
+
+    #[test]
+    fn messages_decode_from_json_and_message_pack() {
+        let ping = json!({ "type": "ping", "client_time": 1.5 });
+        let encodings = [
+            (Encoding::Json, serde_json::to_vec(&ping).unwrap()),
+            (
+                Encoding::MessagePack,
+                rmp_serde::to_vec_named(&ping).unwrap(),
+            ),
+        ];
+        for (encoding, bytes) in encodings {
+            match encoding.decode(&bytes) {
+                Ok(MessageFromClient::Ping { client_time }) => assert_eq!(client_time, 1.5),
+                other => panic!("{:?} decoded to {:?}", encoding, other),
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_json_is_a_json_error() {
+        let cases: [&[u8]; 3] = [
+            b"{\"type\": \"ping\"",
+            b"{\"type\": \"teleport\"}",
+            b"{\"type\": \"ping\", \"client_time\": \"soon\"}",
+        ];
+        for bytes in cases {
+            assert!(matches!(
+                Encoding::Json.decode(bytes),
+                Err(Error::JsonError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn malformed_message_pack_is_a_decode_error() {
+        let unknown_type = rmp_serde::to_vec_named(&json!({ "type": "teleport" })).unwrap();
+        let cases: [&[u8]; 3] = [&[0xc1], &[0x82, 0xa4], &unknown_type];
+        for bytes in cases {
+            assert!(matches!(
+                Encoding::MessagePack.decode(bytes),
+                Err(Error::MessagePackDecodeError(_))
+            ));
+        }
+    }
+}
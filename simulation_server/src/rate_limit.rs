@@ -0,0 +1,155 @@
+//! Token bucket rate limiting of the commands that clients send
+
+use crate::auth::Role;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// How many commands a client can send.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// The sustained rate at which commands are allowed.
+    pub commands_per_second: f64,
+
+    /// The number of commands that can be sent at once after a quiet period.
+    pub burst: u32,
+}
+
+/// The rate limit of each role, and how many commands in a row a client can
+/// send over its limit before it's disconnected.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub max_strikes: u32,
+    pub viewer: RateLimit,
+    pub operator: RateLimit,
+    pub admin: RateLimit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            max_strikes: 20,
+            viewer: RateLimit {
+                commands_per_second: 5.0,
+                burst: 10,
+            },
+            operator: RateLimit {
+                commands_per_second: 20.0,
+                burst: 40,
+            },
+            admin: RateLimit {
+                commands_per_second: 50.0,
+                burst: 100,
+            },
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Returns the rate limit of a role. Clients that haven't authenticated
+    /// are limited like viewers.
+    pub fn for_role(&self, role: Option<Role>) -> RateLimit {
+        match role {
+            None | Some(Role::Viewer) => self.viewer,
+            Some(Role::Operator) => self.operator,
+            Some(Role::Admin) => self.admin,
+        }
+    }
+}
+
+/// A bucket that holds up to `burst` tokens and is refilled at a constant
+/// rate. Each command takes one token.
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    pub fn new(limit: RateLimit) -> TokenBucket {
+        TokenBucket {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token if one is available. Otherwise returns how long it will
+    /// take for the next token to become available.
+    pub fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.commands_per_second).min(self.limit.burst as f64);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - self.tokens;
+            Err(Duration::from_secs_f64(
+                missing / self.limit.commands_per_second,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(commands_per_second: f64, burst: u32) -> TokenBucket {
+        TokenBucket::new(RateLimit {
+            commands_per_second,
+            burst,
+        })
+    }
+
+    #[test]
+    fn full_buckets_allow_a_burst() {
+        let mut bucket = bucket(2.0, 3);
+        let now = bucket.last_refill;
+        for _ in 0..3 {
+            assert_eq!(bucket.try_take(now), Ok(()));
+        }
+        assert_eq!(bucket.try_take(now), Err(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn tokens_refill_at_the_sustained_rate() {
+        let mut bucket = bucket(4.0, 1);
+        let start = bucket.last_refill;
+        assert_eq!(bucket.try_take(start), Ok(()));
+        let later = start + Duration::from_millis(100);
+        assert_eq!(bucket.try_take(later), Err(Duration::from_millis(150)));
+        assert_eq!(bucket.try_take(start + Duration::from_millis(250)), Ok(()));
+    }
+
+    #[test]
+    fn buckets_never_hold_more_than_the_burst() {
+        let mut bucket = bucket(10.0, 2);
+        let later = bucket.last_refill + Duration::from_secs(60);
+        assert_eq!(bucket.try_take(later), Ok(()));
+        assert_eq!(bucket.try_take(later), Ok(()));
+        assert!(bucket.try_take(later).is_err());
+    }
+
+    #[test]
+    fn times_before_the_last_refill_add_no_tokens() {
+        let mut bucket = bucket(1.0, 1);
+        let start = bucket.last_refill;
+        assert_eq!(bucket.try_take(start + Duration::from_secs(1)), Ok(()));
+        assert!(bucket.try_take(start).is_err());
+    }
+
+    #[test]
+    fn unauthenticated_clients_are_limited_like_viewers() {
+        let config = RateLimitConfig::default();
+        assert_eq!(config.for_role(None).burst, config.viewer.burst);
+        assert_eq!(config.for_role(Some(Role::Admin)).burst, config.admin.burst);
+    }
+}
@@ -0,0 +1,348 @@
+//! Named rooms, each of which runs an independent simulation. A room is created
+//! when the first client joins it, or when a peer first sends a message tagged
+//! with it, and is shut down once it has been empty for the configured idle
+//! timeout.
+
+use crate::channel::{MessageToSimulation, SimHandle};
+use crate::config::Config;
+use crate::error::Result;
+use crate::metrics::Metrics;
+use crate::peer::PeerMessage;
+use crate::protocol::RoomError;
+use crate::scenario::Scenario;
+use crate::shard::Shard;
+use crate::sim::{self, RoomLink};
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::Instant;
+use tokio::sync::{mpsc::unbounded_channel, oneshot, watch};
+
+/// The longest name that a room can have.
+pub const MAX_ROOM_NAME_LEN: usize = 64;
+
+/// Creates rooms on demand and tracks how many clients are in each. The lock is
+/// only taken when a client joins or leaves a room or a peer message arrives,
+/// never while a room runs.
+pub struct RoomManager {
+    config: Arc<Config>,
+    scenario: Scenario,
+    shard: Option<Arc<Shard>>,
+    metrics: Arc<Metrics>,
+    rooms: Mutex<Rooms>,
+}
+
+#[derive(Default)]
+struct Rooms {
+    by_name: HashMap<String, Room>,
+
+    /// Set once the server starts shutting down, after which no room can be
+    /// created or joined.
+    closed: bool,
+}
+
+struct Room {
+    sim: SimHandle,
+    members: usize,
+
+    /// Shared with the room's simulation so that it can tell peers whether the
+    /// room is in use here.
+    occupied: Arc<AtomicBool>,
+
+    /// When the room was last left by a client or reported as in use by a
+    /// peer. The room is shut down once it has had no members for the idle
+    /// timeout since then.
+    last_active: Instant,
+
+    /// Whether a task is waiting to shut the room down. At most one runs.
+    idle_timer_running: bool,
+    shutdown_sender: watch::Sender<bool>,
+    done: oneshot::Receiver<()>,
+}
+
+/// A client's membership of a room. The client leaves the room when this is
+/// dropped.
+pub struct RoomMembership {
+    manager: Arc<RoomManager>,
+    name: String,
+    sim: SimHandle,
+}
+
+impl RoomMembership {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn sim(&self) -> &SimHandle {
+        &self.sim
+    }
+}
+
+impl Drop for RoomMembership {
+    fn drop(&mut self) {
+        self.manager.leave(&self.name);
+    }
+}
+
+impl RoomManager {
+    /// Creates a manager whose rooms all start from `scenario`. If `shard` is
+    /// given, each room only simulates the shard's region of the world.
+    pub fn new(
+        config: Arc<Config>,
+        scenario: Scenario,
+        shard: Option<Arc<Shard>>,
+        metrics: Arc<Metrics>,
+    ) -> Arc<RoomManager> {
+        Arc::new(RoomManager {
+            config,
+            scenario,
+            shard,
+            metrics,
+            rooms: Mutex::new(Rooms::default()),
+        })
+    }
+
+    /// Joins the room with the given name, creating it if it doesn't exist.
+    pub fn join(self: &Arc<Self>, name: &str) -> std::result::Result<RoomMembership, RoomError> {
+        validate_name(name)?;
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms.closed {
+            return Err(RoomError::ShuttingDown);
+        }
+        let room = self.get_or_create(&mut rooms, name)?;
+        room.members += 1;
+        room.occupied.store(true, Ordering::Relaxed);
+        Ok(RoomMembership {
+            manager: self.clone(),
+            name: name.to_string(),
+            sim: room.sim.clone(),
+        })
+    }
+
+    /// Delivers a message from a peer to the room it's tagged with, creating
+    /// the room if it doesn't exist.
+    pub fn deliver(self: &Arc<Self>, msg: PeerMessage) {
+        let name = msg.room().to_string();
+        if validate_name(&name).is_err() {
+            warn!("Dropping a peer message for the invalid room `{}`", name);
+            return;
+        }
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms.closed {
+            return;
+        }
+        let room = match self.get_or_create(&mut rooms, &name) {
+            Ok(room) => room,
+            Err(e) => {
+                warn!("Dropping a peer message for room `{}`: {}", name, e);
+                return;
+            }
+        };
+        if msg.is_from_occupied_room() {
+            room.last_active = Instant::now();
+        }
+        room.sim.send_to_sim(MessageToSimulation::FromPeer(msg));
+        if room.members == 0 {
+            self.start_idle_timer(&name, room);
+        }
+    }
+
+    fn get_or_create<'a>(
+        &self,
+        rooms: &'a mut Rooms,
+        name: &str,
+    ) -> std::result::Result<&'a mut Room, RoomError> {
+        if !rooms.by_name.contains_key(name) {
+            if rooms.by_name.len() >= self.config.max_rooms {
+                return Err(RoomError::TooManyRooms {
+                    max: self.config.max_rooms,
+                });
+            }
+            let room = self.create(name).map_err(|e| {
+                error!("Failed to create room `{}`: {}", name, e);
+                RoomError::Unavailable
+            })?;
+            info!("Created room `{}`", name);
+            rooms.by_name.insert(name.to_string(), room);
+        }
+        Ok(rooms.by_name.get_mut(name).unwrap())
+    }
+
+    /// Spawns the simulation thread for a new room.
+    fn create(&self, name: &str) -> Result<Room> {
+        let (sim_sender, sim_receiver) = unbounded_channel();
+        let (snapshot_sender, snapshot_receiver) = watch::channel(None);
+        let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+        let occupied = Arc::new(AtomicBool::new(false));
+        let link = RoomLink {
+            name: name.to_string(),
+            receiver: sim_receiver,
+            snapshot_sender,
+            shutdown: shutdown_receiver,
+            occupied: occupied.clone(),
+        };
+        let done = sim::spawn(
+            &self.config,
+            self.scenario.clone(),
+            link,
+            self.shard.clone(),
+            self.metrics.clone(),
+        )?;
+        Ok(Room {
+            sim: SimHandle::new(sim_sender, snapshot_receiver),
+            members: 0,
+            occupied,
+            last_active: Instant::now(),
+            idle_timer_running: false,
+            shutdown_sender,
+            done,
+        })
+    }
+
+    /// Removes a client from a room. If the room becomes empty it's shut down
+    /// after the idle timeout unless it's used again first.
+    fn leave(self: &Arc<Self>, name: &str) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = match rooms.by_name.get_mut(name) {
+            Some(room) => room,
+            None => return,
+        };
+        room.members -= 1;
+        if room.members > 0 {
+            return;
+        }
+        room.occupied.store(false, Ordering::Relaxed);
+        room.last_active = Instant::now();
+        self.start_idle_timer(name, room);
+    }
+
+    fn start_idle_timer(self: &Arc<Self>, name: &str, room: &mut Room) {
+        if room.idle_timer_running {
+            return;
+        }
+        room.idle_timer_running = true;
+        let deadline = room.last_active + self.config.room_idle_timeout;
+        let manager = self.clone();
+        let name = name.to_string();
+        tokio::spawn(async move { manager.close_when_idle(&name, deadline).await });
+    }
+
+    /// Waits until the room has been idle for the idle timeout and then shuts
+    /// it down, or stops waiting if a client joins the room first.
+    async fn close_when_idle(&self, name: &str, mut deadline: Instant) {
+        loop {
+            tokio::time::sleep_until(deadline.into()).await;
+            let mut rooms = self.rooms.lock().unwrap();
+            let room = match rooms.by_name.get_mut(name) {
+                Some(room) => room,
+                None => return,
+            };
+            if room.members > 0 {
+                room.idle_timer_running = false;
+                return;
+            }
+            deadline = room.last_active + self.config.room_idle_timeout;
+            if Instant::now() >= deadline {
+                let room = rooms.by_name.remove(name).unwrap();
+                info!("Closing room `{}` because it's empty", name);
+                let _ = room.shutdown_sender.send(true);
+                return;
+            }
+        }
+    }
+
+    /// Stops every room and waits for their simulations to finish.
+    pub async fn shutdown(&self) {
+        let rooms: Vec<Room> = {
+            let mut rooms = self.rooms.lock().unwrap();
+            rooms.closed = true;
+            rooms.by_name.drain().map(|(_, room)| room).collect()
+        };
+        for room in &rooms {
+            let _ = room.shutdown_sender.send(true);
+        }
+        for room in rooms {
+            let _ = room.done.await;
+        }
+    }
+}
+
+/// Room names are used in file names, so only ASCII letters, digits, `-` and
+/// `_` are allowed.
+fn validate_name(name: &str) -> std::result::Result<(), RoomError> {
+    let is_valid = !name.is_empty()
+        && name.len() <= MAX_ROOM_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if is_valid {
+        Ok(())
+    } else {
+        Err(RoomError::InvalidName {
+            max_len: MAX_ROOM_NAME_LEN,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn manager(room_idle_timeout: &str) -> Arc<RoomManager> {
+        let file = format!("room_idle_timeout = {}", room_idle_timeout);
+        let config = Config::from_toml(&file).unwrap();
+        RoomManager::new(
+            Arc::new(config),
+            Scenario::default(),
+            None,
+            Arc::new(Metrics::new()),
+        )
+    }
+
+    fn has_room(manager: &RoomManager, name: &str) -> bool {
+        manager.rooms.lock().unwrap().by_name.contains_key(name)
+    }
+
+    #[test]
+    fn names_are_short_and_safe_in_file_names() {
+        for name in ["lobby", "room-2", "A_b", &"x".repeat(MAX_ROOM_NAME_LEN)] {
+            assert!(validate_name(name).is_ok(), "{:?}", name);
+        }
+        let long = "x".repeat(MAX_ROOM_NAME_LEN + 1);
+        for name in ["", "../lobby", "a b", "lobby.json", "café", &long] {
+            assert_eq!(
+                validate_name(name),
+                Err(RoomError::InvalidName {
+                    max_len: MAX_ROOM_NAME_LEN
+                }),
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_rooms_close_after_the_idle_timeout() {
+        let manager = manager("0.05");
+        drop(manager.join("lobby").unwrap());
+        assert!(has_room(&manager, "lobby"));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!has_room(&manager, "lobby"));
+        manager.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn rooms_with_members_stay_open() {
+        let manager = manager("0.05");
+        drop(manager.join("lobby").unwrap());
+        let _membership = manager.join("lobby").unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(has_room(&manager, "lobby"));
+        manager.shutdown().await;
+        assert_eq!(manager.join("lobby").err(), Some(RoomError::ShuttingDown));
+    }
+}
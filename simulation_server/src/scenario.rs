@@ -0,0 +1,240 @@
+//! Serializable description of a simulation world that can be loaded from or
+//! written to disk. A world written at shutdown can be loaded as a scenario.
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use simulation::{
+    collections::grid::Grid,
+    component::{Group, Position, Velocity},
+    resources::continuum_crowds::{GroupGoals, SharedGrid, GROUP_COUNT},
+};
+use specs::prelude::*;
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// The agents, goals and painted cells of a simulation world.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Scenario {
+    pub width: usize,
+    pub height: usize,
+    pub goals: [Option<(f32, f32)>; GROUP_COUNT],
+    pub agents: Vec<ScenarioAgent>,
+
+    /// Only cells that have discomfort or are obstacles are listed.
+    pub cells: Vec<ScenarioCell>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScenarioAgent {
+    pub x: f32,
+    pub y: f32,
+    pub velocity_x: f32,
+    pub velocity_y: f32,
+    pub group: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScenarioCell {
+    pub x: usize,
+    pub y: usize,
+    pub discomfort: f32,
+    pub is_obstacle: bool,
+}
+
+impl Default for Scenario {
+    /// A 16 by 16 cell world containing a 10 by 10 block of agents.
+    fn default() -> Self {
+        let mut agents = vec![];
+        for x in 1..=10 {
+            for y in 1..=10 {
+                agents.push(ScenarioAgent {
+                    x: x as f32 * 0.3,
+                    y: y as f32 * 0.3,
+                    velocity_x: 0.2,
+                    velocity_y: 0.2,
+                    group: 0,
+                });
+            }
+        }
+        Scenario {
+            width: 16,
+            height: 16,
+            goals: [None; GROUP_COUNT],
+            agents,
+            cells: vec![],
+        }
+    }
+}
+
+impl Scenario {
+    /// Reads and validates a scenario from a JSON file.
+    pub fn load(path: &Path) -> Result<Scenario> {
+        let reader = BufReader::new(File::open(path)?);
+        let scenario: Scenario = serde_json::from_reader(reader)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.width == 0 {
+            return Err(invalid("width".to_string(), "must be at least 1"));
+        }
+        if self.height == 0 {
+            return Err(invalid("height".to_string(), "must be at least 1"));
+        }
+        for (i, goal) in self.goals.iter().enumerate() {
+            if let Some((x, y)) = goal {
+                if !x.is_finite() || !y.is_finite() {
+                    return Err(invalid(format!("goals[{}]", i), "must be finite"));
+                }
+            }
+        }
+        for (i, agent) in self.agents.iter().enumerate() {
+            let values = [agent.x, agent.y, agent.velocity_x, agent.velocity_y];
+            if !values.iter().all(|v| v.is_finite()) {
+                return Err(invalid(format!("agents[{}]", i), "must be finite"));
+            }
+            if agent.group >= GROUP_COUNT {
+                return Err(invalid(
+                    format!("agents[{}].group", i),
+                    "does not refer to an existing group",
+                ));
+            }
+        }
+        for (i, cell) in self.cells.iter().enumerate() {
+            if cell.x >= self.width || cell.y >= self.height {
+                return Err(invalid(format!("cells[{}]", i), "is out of bounds"));
+            }
+            if !cell.discomfort.is_finite() || cell.discomfort < 0.0 {
+                return Err(invalid(
+                    format!("cells[{}].discomfort", i),
+                    "must be finite and not negative",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Captures the current state of the world.
+    pub fn capture(world: &World) -> Scenario {
+        let shared_grid = world.read_resource::<SharedGrid>();
+        let goals = world.read_resource::<GroupGoals>();
+        let positions = world.read_storage::<Position>();
+        let velocities = world.read_storage::<Velocity>();
+        let groups = world.read_storage::<Group>();
+
+        let agents = (&positions, &velocities, &groups)
+            .join()
+            .map(|(pos, vel, group)| ScenarioAgent {
+                x: pos.x,
+                y: pos.y,
+                velocity_x: vel.x,
+                velocity_y: vel.y,
+                group: group.0,
+            })
+            .collect();
+
+        let cells = shared_grid
+            .discomfort
+            .position_iter()
+            .filter_map(|(x, y)| {
+                let discomfort = *shared_grid.discomfort.get(x, y)?;
+                let is_obstacle = *shared_grid.is_obstacle.get(x, y)?;
+                if discomfort != 0.0 || is_obstacle {
+                    Some(ScenarioCell {
+                        x,
+                        y,
+                        discomfort,
+                        is_obstacle,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        Scenario {
+            width: shared_grid.width(),
+            height: shared_grid.height(),
+            goals: goals.0,
+            agents,
+            cells,
+        }
+    }
+
+    /// Writes the scenario to a JSON file. The JSON is written to a temporary
+    /// file in the same directory, which is then renamed over the path, so the
+    /// file at the path is never left partly written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+        let result = self
+            .write(&temp_path)
+            .and_then(|()| Ok(fs::rename(&temp_path, path)?));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// Writes the scenario to a new JSON file and waits for it to reach the
+    /// disk.
+    fn write(&self, path: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+fn invalid(field: String, message: &str) -> Error {
+    Error::InvalidScenario {
+        field,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saving_replaces_the_file_without_leaving_a_temporary_file() {
+        let dir = std::env::temp_dir().join(format!("simulation-scenario-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("world.json");
+        fs::write(&path, "not a scenario").unwrap();
+
+        let scenario = Scenario::default();
+        scenario.save(&path).unwrap();
+        let saved = Scenario::load(&path).unwrap();
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(saved.agents.len(), scenario.agents.len());
+        assert_eq!(files, vec!["world.json"]);
+    }
+
+    #[test]
+    fn a_failed_save_leaves_no_temporary_file() {
+        let dir =
+            std::env::temp_dir().join(format!("simulation-scenario-failed-{}", std::process::id()));
+        fs::create_dir_all(dir.join("world.json")).unwrap();
+
+        // The rename fails because the path is a directory.
+        assert!(Scenario::default().save(&dir.join("world.json")).is_err());
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(files, vec!["world.json"]);
+    }
+}
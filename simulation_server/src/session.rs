@@ -0,0 +1,319 @@
+//! Identity of a connected client, independent of its network address, and
+//! the store that keeps sessions alive across brief disconnects
+
+use crate::auth::{Grant, Role};
+use crate::config::Config;
+use crate::rate_limit::{RateLimitConfig, TokenBucket};
+use crate::room::RoomMembership;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{
+    mpsc::{channel, error::SendTimeoutError, Receiver, Sender},
+    oneshot,
+};
+
+/// How long to wait for the connection that currently serves a session to hand
+/// it over to a connection that resumes it.
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A random identifier that distinguishes clients even when they share an
+/// address, for example behind the same NAT.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct SessionId(String);
+
+impl SessionId {
+    pub fn generate() -> SessionId {
+        SessionId(format!("{:032x}", rand::random::<u128>()))
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A client's session.
+#[derive(Debug)]
+pub struct Session {
+    pub id: SessionId,
+
+    /// `None` until the client authenticates.
+    pub role: Option<Role>,
+
+    /// When the token that granted the role expires, or `None` if it doesn't.
+    pub expires_at: Option<SystemTime>,
+
+    /// Limits the rate at which the client can send commands, according to its
+    /// role.
+    pub commands: TokenBucket,
+
+    /// The number of commands in a row that were rejected because the client
+    /// exceeded its rate limit.
+    pub strikes: u32,
+}
+
+impl Session {
+    /// Starts a session. Clients only need to authenticate if authentication
+    /// is enabled; otherwise they're granted every role.
+    pub fn new(config: &Config) -> Session {
+        let role = if config.auth.is_enabled() {
+            None
+        } else {
+            Some(Role::Admin)
+        };
+        Session {
+            id: SessionId::generate(),
+            role,
+            expires_at: None,
+            commands: TokenBucket::new(config.rate_limits.for_role(role)),
+            strikes: 0,
+        }
+    }
+
+    /// Grants the session a token's role, along with the role's rate limit,
+    /// until the token expires.
+    pub fn grant(&mut self, grant: Grant, rate_limits: &RateLimitConfig) {
+        self.expires_at = grant.expires_at;
+        if self.role != Some(grant.role) {
+            self.role = Some(grant.role);
+            self.commands = TokenBucket::new(rate_limits.for_role(Some(grant.role)));
+        }
+    }
+
+    /// Returns whether the token the session authenticated with has expired,
+    /// after which the client must authenticate again to send commands.
+    pub fn has_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Takes a token from the session's bucket for a command. Returns how long
+    /// the client should wait before sending another command if the bucket is
+    /// empty.
+    pub fn take_command_token(&mut self) -> Result<(), Duration> {
+        match self.commands.try_take(Instant::now()) {
+            Ok(()) => {
+                self.strikes = 0;
+                Ok(())
+            }
+            Err(retry_after) => {
+                self.strikes += 1;
+                Err(retry_after)
+            }
+        }
+    }
+
+    pub fn has_role(&self, required: Role) -> bool {
+        self.role.is_some_and(|role| role >= required)
+    }
+}
+
+/// The parts of a session that are kept while its client is disconnected.
+pub struct SessionState {
+    pub role: Option<Role>,
+    pub expires_at: Option<SystemTime>,
+    pub commands: TokenBucket,
+    pub room: Option<RoomMembership>,
+}
+
+/// A request for a connection handler task to hand its session over to
+/// another connection. The task replies with the session's state and closes
+/// its connection.
+pub type Takeover = oneshot::Sender<SessionState>;
+
+enum Entry {
+    /// A connection handler task is serving the session.
+    Attached(Sender<Takeover>),
+
+    /// The session's connection dropped. The session is kept, including its
+    /// room membership, until it's resumed or the retention period ends.
+    Detached {
+        state: SessionState,
+        detached_at: Instant,
+    },
+}
+
+/// Every session that is connected or was recently disconnected.
+pub struct SessionStore {
+    retention: Duration,
+    sessions: Mutex<HashMap<SessionId, Entry>>,
+}
+
+impl SessionStore {
+    pub fn new(retention: Duration) -> Arc<SessionStore> {
+        Arc::new(SessionStore {
+            retention,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Records that the calling connection handler task serves the session.
+    /// The returned receiver yields a request each time another connection
+    /// resumes the session.
+    pub fn attach(&self, id: &SessionId) -> Receiver<Takeover> {
+        let (sender, receiver) = channel(1);
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(id.clone(), Entry::Attached(sender));
+        receiver
+    }
+
+    /// Keeps the state of a session whose connection dropped so that a new
+    /// connection can resume it within the retention period.
+    pub fn detach(self: &Arc<Self>, id: SessionId, state: SessionState) {
+        let detached_at = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(id.clone(), Entry::Detached { state, detached_at });
+        let store = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(store.retention).await;
+            store.expire(&id, detached_at);
+        });
+    }
+
+    fn expire(&self, id: &SessionId, detached_at: Instant) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let is_expired = matches!(
+            sessions.get(id),
+            Some(Entry::Detached { detached_at: t, .. }) if *t == detached_at
+        );
+        if is_expired {
+            info!("Session {} expired", id);
+            sessions.remove(id);
+        }
+    }
+
+    /// Forgets a session, leaving its room if it was detached in one.
+    pub fn remove(&self, id: &SessionId) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+
+    /// Takes the state of a session so that the calling connection can serve
+    /// it. If another connection still serves the session, for example because
+    /// it hasn't noticed that its client is gone, that connection is closed.
+    /// The calling connection must not serve the session itself, since it
+    /// can't hand the session over while it waits here.
+    pub async fn resume(&self, id: &SessionId) -> Option<SessionState> {
+        // The connection serving the session may detach it instead of handing
+        // it over if it drops at the same time, so look the session up again.
+        for _ in 0..2 {
+            let sender = {
+                let mut sessions = self.sessions.lock().unwrap();
+                match sessions.remove(id)? {
+                    Entry::Detached { state, .. } => return Some(state),
+                    Entry::Attached(sender) => {
+                        sessions.insert(id.clone(), Entry::Attached(sender.clone()));
+                        sender
+                    }
+                }
+            };
+            // The queue only has room for one request, so if another resume
+            // is already waiting for the same connection, give up rather than
+            // wait behind it.
+            let (takeover, state) = oneshot::channel();
+            match sender.send_timeout(takeover, TAKEOVER_TIMEOUT).await {
+                Ok(()) => {}
+                Err(SendTimeoutError::Closed(_)) => continue,
+                Err(SendTimeoutError::Timeout(_)) => return None,
+            }
+            if let Ok(Ok(state)) = tokio::time::timeout(TAKEOVER_TIMEOUT, state).await {
+                return Some(state);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::RateLimit;
+
+    fn state(role: Role) -> SessionState {
+        SessionState {
+            role: Some(role),
+            expires_at: None,
+            commands: TokenBucket::new(RateLimit {
+                commands_per_second: 1.0,
+                burst: 1,
+            }),
+            room: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn detached_sessions_can_be_resumed_once() {
+        let store = SessionStore::new(Duration::from_secs(60));
+        let id = SessionId::generate();
+        store.detach(id.clone(), state(Role::Operator));
+        assert_eq!(store.resume(&id).await.unwrap().role, Some(Role::Operator));
+        assert!(store.resume(&id).await.is_none());
+        assert!(store.resume(&SessionId::generate()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn detached_sessions_expire_after_the_retention_period() {
+        let store = SessionStore::new(Duration::from_millis(20));
+        let id = SessionId::generate();
+        store.detach(id.clone(), state(Role::Viewer));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(store.resume(&id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn attached_sessions_are_handed_over_by_their_connection() {
+        let store = SessionStore::new(Duration::from_secs(60));
+        let id = SessionId::generate();
+        let mut takeovers = store.attach(&id);
+        tokio::spawn(async move {
+            let takeover = takeovers.recv().await.unwrap();
+            let _ = takeover.send(state(Role::Admin));
+        });
+        assert_eq!(store.resume(&id).await.unwrap().role, Some(Role::Admin));
+    }
+
+    #[tokio::test]
+    async fn resuming_a_session_whose_connection_never_answers_gives_up() {
+        let store = SessionStore::new(Duration::from_secs(60));
+        let id = SessionId::generate();
+        let _takeovers = store.attach(&id);
+        // The first request fills the queue, so the second can't be queued.
+        let resume = async {
+            assert!(store.resume(&id).await.is_none());
+            assert!(store.resume(&id).await.is_none());
+        };
+        tokio::time::timeout(TAKEOVER_TIMEOUT * 6, resume)
+            .await
+            .expect("resume waited for the connection forever");
+    }
+
+    #[test]
+    fn sessions_expire_with_the_token_that_granted_their_role() {
+        let config = Config::from_toml("[auth]\nhmac_secret = \"secret\"").unwrap();
+        let mut session = Session::new(&config);
+        let now = SystemTime::now();
+        assert!(!session.has_expired(now));
+
+        let expires_at = now + Duration::from_secs(60);
+        let grant = Grant {
+            role: Role::Operator,
+            expires_at: Some(expires_at),
+        };
+        session.grant(grant, &config.rate_limits);
+        assert!(session.has_role(Role::Operator));
+        assert!(!session.has_expired(now));
+        assert!(session.has_expired(expires_at));
+
+        // Tokens listed in the config never expire.
+        let grant = Grant {
+            role: Role::Operator,
+            expires_at: None,
+        };
+        session.grant(grant, &config.rate_limits);
+        assert!(!session.has_expired(expires_at));
+    }
+}
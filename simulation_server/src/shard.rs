@@ -0,0 +1,493 @@
+//! Partitioning of the world into regions that are simulated by separate server
+//! processes. Each process owns the agents in its region, sends the cells along
+//! its border to the peers that own neighboring regions, and hands agents off
+//! to a peer when they cross into the peer's region.
+
+use crate::command;
+use crate::config::ShardConfig;
+use crate::peer::{self, BorderCell, PeerMessage, PEER_QUEUE_CAPACITY};
+use crate::scenario::ScenarioAgent;
+use log::warn;
+use serde::Deserialize;
+use simulation::{
+    component::{Group, Position, Velocity},
+    resources::continuum_crowds::{GhostCells, SharedCell, SharedGrid},
+};
+use specs::prelude::*;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{
+    mpsc::{channel, Sender},
+    watch,
+};
+
+/// The number of cells beyond the edge of a region that a process needs from
+/// its neighbors.
+pub const HALO_WIDTH: usize = 1;
+
+/// How long a peer's ghost cells are kept after its last border cells
+/// arrived. Peers send their border every frame, so a peer that's silent for
+/// this long has lost its link or stopped simulating the room.
+pub const GHOST_CELL_LIFETIME: Duration = Duration::from_secs(1);
+
+/// A rectangle of cells. Coordinates are measured in cells from the origin of
+/// the whole world.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    pub fn contains_cell(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// Returns whether the point lies in one of the region's cells.
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= 0.0 && y >= 0.0 && self.contains_cell(x as usize, y as usize)
+    }
+
+    pub fn overlaps(&self, other: &Region) -> bool {
+        self.x < other.x + other.width
+            && other.x < self.x + self.width
+            && self.y < other.y + other.height
+            && other.y < self.y + self.height
+    }
+
+    /// Returns whether the cell lies in the region or within `HALO_WIDTH`
+    /// cells of it.
+    fn halo_contains_cell(&self, x: usize, y: usize) -> bool {
+        x + HALO_WIDTH >= self.x
+            && x < self.x + self.width + HALO_WIDTH
+            && y + HALO_WIDTH >= self.y
+            && y < self.y + self.height + HALO_WIDTH
+    }
+}
+
+/// Another process that owns a neighboring region.
+struct Peer {
+    address: SocketAddr,
+    region: Region,
+    sender: Sender<PeerMessage>,
+
+    /// The cells of this process's region that lie in the peer's halo.
+    border: Vec<(usize, usize)>,
+}
+
+/// When border cells last arrived from each peer, by index in `Shard::peers`.
+/// A resource of each room's world.
+#[derive(Default)]
+struct BorderUpdates(HashMap<usize, Instant>);
+
+/// This process's region of the world and the peers that own the rest of it.
+/// A shard is shared by every room; messages to peers are tagged with the name
+/// of the room they belong to.
+pub struct Shard {
+    region: Region,
+    peers: Vec<Peer>,
+}
+
+impl Shard {
+    /// Creates the shard and spawns a task that maintains the link to each
+    /// peer until `shutdown` becomes true.
+    pub fn start(config: &ShardConfig, shutdown: &watch::Receiver<bool>) -> Arc<Shard> {
+        let region = config.region;
+        let peers = config
+            .peers
+            .iter()
+            .map(|peer| {
+                let (sender, receiver) = channel(PEER_QUEUE_CAPACITY);
+                tokio::spawn(peer::connect(
+                    peer.address,
+                    config.peer_secret.clone(),
+                    receiver,
+                    shutdown.clone(),
+                ));
+                let mut border = vec![];
+                for y in region.y..region.y + region.height {
+                    for x in region.x..region.x + region.width {
+                        if peer.region.halo_contains_cell(x, y) {
+                            border.push((x, y));
+                        }
+                    }
+                }
+                Peer {
+                    address: peer.address,
+                    region: peer.region,
+                    sender,
+                    border,
+                }
+            })
+            .collect();
+        Arc::new(Shard { region, peers })
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Returns the number of messages waiting to be sent to each peer.
+    pub fn queue_depths(&self) -> Vec<(SocketAddr, usize)> {
+        self.peers
+            .iter()
+            .map(|peer| (peer.address, PEER_QUEUE_CAPACITY - peer.sender.capacity()))
+            .collect()
+    }
+
+    /// Removes every agent that has moved into a peer's region from the world
+    /// and hands it off to that peer. Agents that aren't in any peer's region,
+    /// or whose peer can't currently accept them, stay in the world.
+    pub fn hand_off_agents(&self, room: &str, world: &mut World) {
+        let mut departures = vec![vec![]; self.peers.len()];
+        {
+            let entities = world.entities();
+            let positions = world.read_storage::<Position>();
+            let velocities = world.read_storage::<Velocity>();
+            let groups = world.read_storage::<Group>();
+            for (entity, pos, vel, group) in (&entities, &positions, &velocities, &groups).join() {
+                if self.region.contains(pos.x, pos.y) {
+                    continue;
+                }
+                let peer = self
+                    .peers
+                    .iter()
+                    .position(|peer| peer.region.contains(pos.x, pos.y));
+                if let Some(i) = peer {
+                    departures[i].push((
+                        entity,
+                        ScenarioAgent {
+                            x: pos.x,
+                            y: pos.y,
+                            velocity_x: vel.x,
+                            velocity_y: vel.y,
+                            group: group.0,
+                        },
+                    ));
+                }
+            }
+        }
+
+        for (peer, departures) in self.peers.iter().zip(departures) {
+            if departures.is_empty() {
+                continue;
+            }
+            let (departed, agents): (Vec<Entity>, Vec<ScenarioAgent>) =
+                departures.into_iter().unzip();
+            let msg = PeerMessage::Handoff {
+                room: room.to_string(),
+                agents,
+            };
+            if peer.sender.try_send(msg).is_ok() {
+                let _ = world.delete_entities(&departed);
+            } else {
+                warn!(
+                    "Keeping {} agents that left the region because peer {} is unavailable",
+                    departed.len(),
+                    peer.address
+                );
+            }
+        }
+        world.maintain();
+    }
+
+    /// Sends each peer the cells of this process's region that lie in the
+    /// peer's halo. `occupied` is whether any clients of this process are in
+    /// the room.
+    pub fn send_border_cells(&self, room: &str, world: &World, occupied: bool) {
+        let shared_grid = world.read_resource::<SharedGrid>();
+        for peer in &self.peers {
+            let cells = peer
+                .border
+                .iter()
+                .filter_map(|&(x, y)| {
+                    let cell = shared_grid.cell(x, y)?;
+                    Some(BorderCell {
+                        x,
+                        y,
+                        density: cell.density,
+                        height: cell.height,
+                        discomfort: cell.discomfort,
+                        is_obstacle: cell.is_obstacle,
+                        avg_velocity: cell.avg_velocity,
+                    })
+                })
+                .collect();
+            // Border cells are sent every frame, so a message that doesn't fit
+            // in the queue is superseded by the next one.
+            let _ = peer.sender.try_send(PeerMessage::BorderCells {
+                room: room.to_string(),
+                occupied,
+                cells,
+            });
+        }
+    }
+
+    /// Applies a message from a peer to the world of the room it's tagged
+    /// with. Border cells replace the ghost cells last received from the peer
+    /// whose region they lie in. Handed off agents are dropped unless they
+    /// belong to a group that exists and lie in this process's region, as a
+    /// command would be rejected.
+    pub fn apply_peer_message(&self, world: &mut World, msg: PeerMessage, now: Instant) {
+        match msg {
+            PeerMessage::BorderCells { room, cells, .. } => {
+                let sender = cells.first().and_then(|cell| {
+                    self.peers
+                        .iter()
+                        .position(|peer| peer.region.contains_cell(cell.x, cell.y))
+                });
+                let i = match sender {
+                    Some(i) => i,
+                    None => return,
+                };
+                let region = self.peers[i].region;
+                world
+                    .entry::<BorderUpdates>()
+                    .or_insert_with(Default::default)
+                    .0
+                    .insert(i, now);
+                let mut ghost_cells = world.write_resource::<GhostCells>();
+                ghost_cells
+                    .0
+                    .retain(|&(x, y), _| !region.contains_cell(x, y));
+                let count = cells.len();
+                let cells: Vec<_> = cells
+                    .into_iter()
+                    .filter(|cell| region.contains_cell(cell.x, cell.y))
+                    .collect();
+                if cells.len() < count {
+                    warn!(
+                        "Dropping {} border cells outside the sender's region in room `{}`",
+                        count - cells.len(),
+                        room
+                    );
+                }
+                for cell in cells {
+                    ghost_cells.0.insert(
+                        (cell.x, cell.y),
+                        SharedCell {
+                            density: cell.density,
+                            height: cell.height,
+                            discomfort: cell.discomfort,
+                            is_obstacle: cell.is_obstacle,
+                            avg_velocity: cell.avg_velocity,
+                        },
+                    );
+                }
+            }
+            PeerMessage::Handoff { room, agents } => {
+                let count = agents.len();
+                let agents: Vec<_> = agents
+                    .into_iter()
+                    .filter(|agent| self.accepts(world, agent))
+                    .collect();
+                if agents.len() < count {
+                    warn!(
+                        "Dropping {} invalid agents handed off in room `{}`",
+                        count - agents.len(),
+                        room
+                    );
+                }
+                for agent in agents {
+                    world
+                        .create_entity()
+                        .with(Position {
+                            x: agent.x,
+                            y: agent.y,
+                        })
+                        .with(Velocity {
+                            x: agent.velocity_x,
+                            y: agent.velocity_y,
+                        })
+                        .with(Group(agent.group))
+                        .build();
+                }
+            }
+        }
+    }
+
+    /// Removes the ghost cells of every peer that hasn't sent border cells
+    /// for `GHOST_CELL_LIFETIME`, so that the world stops seeing the border
+    /// as it was when the peer went away.
+    pub fn expire_ghost_cells(&self, world: &mut World, now: Instant) {
+        let mut updates = world
+            .entry::<BorderUpdates>()
+            .or_insert_with(Default::default);
+        let expired: Vec<usize> = updates
+            .0
+            .iter()
+            .filter(|(_, &updated)| now.duration_since(updated) >= GHOST_CELL_LIFETIME)
+            .map(|(&i, _)| i)
+            .collect();
+        for i in &expired {
+            updates.0.remove(i);
+        }
+        drop(updates);
+        if expired.is_empty() {
+            return;
+        }
+        let mut ghost_cells = world.write_resource::<GhostCells>();
+        for i in expired {
+            let region = self.peers[i].region;
+            warn!(
+                "Forgetting the ghost cells of peer {}, which stopped sending them",
+                self.peers[i].address
+            );
+            ghost_cells
+                .0
+                .retain(|&(x, y), _| !region.contains_cell(x, y));
+        }
+    }
+
+    /// Returns whether an agent handed off by a peer can be added to the
+    /// world.
+    fn accepts(&self, world: &World, agent: &ScenarioAgent) -> bool {
+        command::validate_group(agent.group).is_ok()
+            && command::validate_point(world, agent.x, agent.y).is_ok()
+            && command::validate_finite("velocity_x", agent.velocity_x).is_ok()
+            && command::validate_finite("velocity_y", agent.velocity_y).is_ok()
+            && self.region.contains(agent.x, agent.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::Scenario;
+    use crate::state::State;
+    use simulation::resources::continuum_crowds::GROUP_COUNT;
+
+    /// A 16 by 16 cell world without agents.
+    fn world() -> World {
+        let scenario = Scenario {
+            agents: vec![],
+            ..Scenario::default()
+        };
+        State::new(&scenario).world
+    }
+
+    /// A shard that owns the left half of the world and has a peer that owns
+    /// the right half.
+    fn shard() -> Shard {
+        let (sender, _) = channel(1);
+        Shard {
+            region: Region {
+                x: 0,
+                y: 0,
+                width: 8,
+                height: 16,
+            },
+            peers: vec![Peer {
+                address: "127.0.0.1:9000".parse().unwrap(),
+                region: Region {
+                    x: 8,
+                    y: 0,
+                    width: 8,
+                    height: 16,
+                },
+                sender,
+                border: vec![],
+            }],
+        }
+    }
+
+    fn border_cells(cells: &[(usize, usize)]) -> PeerMessage {
+        PeerMessage::BorderCells {
+            room: "default".to_string(),
+            occupied: false,
+            cells: cells
+                .iter()
+                .map(|&(x, y)| BorderCell {
+                    x,
+                    y,
+                    density: 1.0,
+                    height: 0.0,
+                    discomfort: 0.0,
+                    is_obstacle: false,
+                    avg_velocity: (0.0, 0.0),
+                })
+                .collect(),
+        }
+    }
+
+    fn ghost_cells(world: &World) -> Vec<(usize, usize)> {
+        let mut cells: Vec<_> = world
+            .read_resource::<GhostCells>()
+            .0
+            .keys()
+            .copied()
+            .collect();
+        cells.sort_unstable();
+        cells
+    }
+
+    fn agent(x: f32, y: f32, group: usize) -> ScenarioAgent {
+        ScenarioAgent {
+            x,
+            y,
+            velocity_x: 0.0,
+            velocity_y: 0.0,
+            group,
+        }
+    }
+
+    #[test]
+    fn handed_off_agents_are_dropped_if_invalid() {
+        let mut world = world();
+        let mut nan_velocity = agent(3.0, 3.0, 0);
+        nan_velocity.velocity_x = f32::NAN;
+        let agents = vec![
+            agent(1.0, 2.0, 0),
+            agent(1.0, 2.0, GROUP_COUNT),
+            agent(f32::NAN, 2.0, 0),
+            agent(1.0, 20.0, 0),
+            agent(12.0, 2.0, 0),
+            nan_velocity,
+        ];
+        shard().apply_peer_message(
+            &mut world,
+            PeerMessage::Handoff {
+                room: "default".to_string(),
+                agents,
+            },
+            Instant::now(),
+        );
+
+        let positions: Vec<_> = (&world.read_storage::<Position>())
+            .join()
+            .map(|pos| (pos.x, pos.y))
+            .collect();
+        assert_eq!(positions, vec![(1.0, 2.0)]);
+    }
+
+    #[test]
+    fn border_cells_replace_the_senders_ghost_cells() {
+        let mut world = world();
+        let shard = shard();
+        let now = Instant::now();
+        shard.apply_peer_message(&mut world, border_cells(&[(8, 1), (8, 2)]), now);
+        assert_eq!(ghost_cells(&world), vec![(8, 1), (8, 2)]);
+
+        // Cells outside the sender's region are ignored.
+        shard.apply_peer_message(&mut world, border_cells(&[(8, 3), (2, 3)]), now);
+        assert_eq!(ghost_cells(&world), vec![(8, 3)]);
+    }
+
+    #[test]
+    fn ghost_cells_expire_once_the_peer_stops_sending_them() {
+        let mut world = world();
+        let shard = shard();
+        let now = Instant::now();
+        shard.apply_peer_message(&mut world, border_cells(&[(8, 1)]), now);
+
+        shard.expire_ghost_cells(&mut world, now + GHOST_CELL_LIFETIME / 2);
+        assert_eq!(ghost_cells(&world), vec![(8, 1)]);
+        shard.expire_ghost_cells(&mut world, now + GHOST_CELL_LIFETIME);
+        assert_eq!(ghost_cells(&world), vec![]);
+    }
+}
@@ -0,0 +1,583 @@
+//! The simulation loop, which runs on a dedicated OS thread so that slow
+//! frames never block the async executor that handles network traffic
+
+use crate::channel::{MessageToConnectionHandler, MessageToSimulation, PublishedSnapshot};
+use crate::clock;
+use crate::command;
+use crate::config::Config;
+use crate::error::Result;
+use crate::metrics::{Metrics, RoomMetrics};
+use crate::protocol::{AgentSnapshot, Command, CommandError, MessageToClient, Snapshot};
+use crate::scenario::Scenario;
+use crate::shard::Shard;
+use crate::state::{Paused, State};
+use log::{error, info};
+use simulation::{
+    component::{Group, Position},
+    frame::Frame,
+    profile::SystemTimings,
+    resources::DurationSinceLastFrame,
+};
+use specs::{Join, WorldExt};
+use std::{
+    collections::BTreeMap,
+    mem,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::sync::{
+    mpsc::{error::TrySendError, Sender, UnboundedReceiver},
+    oneshot, watch,
+};
+
+/// How many frames ahead of the simulation a command can target.
+const MAX_FRAMES_AHEAD: u64 = 1000;
+
+/// Timing settings for the simulation loop.
+#[derive(Clone, Copy, Debug)]
+struct Timing {
+    frame_duration: Duration,
+
+    /// The minimum amount of time between published snapshots. This is
+    /// independent of the frame duration so that clients can be sent fewer
+    /// snapshots than the simulation computes frames.
+    snapshot_interval: Duration,
+}
+
+/// A command from a client that is waiting for the frame it targets.
+struct PendingCommand {
+    id: u64,
+    command: Command,
+    reply: Sender<MessageToConnectionHandler>,
+}
+
+/// Commands waiting to be applied, keyed by the frame they target. Commands
+/// that target the same frame are applied in the order they were received.
+type CommandQueue = BTreeMap<u64, Vec<PendingCommand>>;
+
+/// The simulation thread's ends of the connections to the room that owns it.
+pub struct RoomLink {
+    pub name: String,
+
+    /// Messages from clients and peers, applied before each frame.
+    pub receiver: UnboundedReceiver<MessageToSimulation>,
+    pub snapshot_sender: watch::Sender<Option<PublishedSnapshot>>,
+    pub shutdown: watch::Receiver<bool>,
+
+    /// Whether any clients of this process are in the room.
+    pub occupied: Arc<AtomicBool>,
+}
+
+/// Spawns a thread that runs the simulation loop for a room, starting from
+/// `scenario`. If `shard` is given, only agents in the shard's region are
+/// simulated. Once the room's shutdown signal becomes true the simulation
+/// finishes its current frame and, if a save path is configured, writes the
+/// world to disk. The returned receiver resolves when the thread exits.
+pub fn spawn(
+    config: &Config,
+    mut scenario: Scenario,
+    link: RoomLink,
+    shard: Option<Arc<Shard>>,
+    metrics: Arc<Metrics>,
+) -> Result<oneshot::Receiver<()>> {
+    let timing = Timing {
+        frame_duration: config.frame_duration,
+        snapshot_interval: config.snapshot_interval,
+    };
+    let save_path = config
+        .save_path
+        .as_deref()
+        .map(|path| room_save_path(path, &link.name));
+    if let Some(shard) = &shard {
+        let region = shard.region();
+        scenario
+            .agents
+            .retain(|agent| region.contains(agent.x, agent.y));
+    }
+    let (done_sender, done_receiver) = oneshot::channel();
+    let room_metrics = metrics.add_room(&link.name);
+    thread::Builder::new()
+        .name(format!("simulation-{}", link.name))
+        .spawn(move || {
+            let name = link.name.clone();
+            let state = run(
+                &scenario,
+                timing,
+                link,
+                shard.as_deref(),
+                &metrics,
+                &room_metrics,
+            );
+            metrics.remove_room(&name, &room_metrics);
+            if let Some(path) = save_path {
+                match Scenario::capture(&state.world).save(&path) {
+                    Ok(()) => info!("Saved the world to {}", path.display()),
+                    Err(e) => error!("Failed to save the world to {}: {}", path.display(), e),
+                }
+            }
+            // If the thread panics this sender is dropped instead, which also
+            // resolves the receiver.
+            let _ = done_sender.send(());
+        })?;
+    Ok(done_receiver)
+}
+
+/// Inserts the room name before the extension of the configured save path, so
+/// that `world.json` becomes `world.lobby.json` for the room `lobby`.
+fn room_save_path(path: &Path, name: &str) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(name);
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+/// Runs frames until shutdown is requested and returns the final state.
+fn run<'a, 'b>(
+    scenario: &Scenario,
+    timing: Timing,
+    mut link: RoomLink,
+    shard: Option<&Shard>,
+    metrics: &Metrics,
+    room_metrics: &RoomMetrics,
+) -> State<'a, 'b> {
+    // The state is created on the simulation thread because the dispatcher
+    // isn't required to be `Send`.
+    let mut state = State::new(scenario);
+    // Systems are only timed if the simulation is built with profiling.
+    let system_timings = state
+        .world
+        .try_fetch::<SystemTimings>()
+        .map(|timings| SystemTimings::clone(&timings));
+    if let Some(timings) = &system_timings {
+        room_metrics.set_system_timings(timings.clone());
+    }
+    let mut commands = CommandQueue::new();
+    let mut sequence = 0;
+    let mut next_publish_time: Option<Instant> = None;
+    while !*link.shutdown.borrow() {
+        let inbox_depth = apply_messages(
+            &mut state,
+            &mut link.receiver,
+            &mut commands,
+            shard,
+            metrics,
+        );
+        if let Some(shard) = shard {
+            shard.expire_ghost_cells(&mut state.world, Instant::now());
+        }
+        let previous_frame = state.frame;
+        let dispatched = step(&mut state, timing.frame_duration, &mut commands, metrics);
+        if let Some(shard) = shard {
+            shard.hand_off_agents(&link.name, &mut state.world);
+            let occupied = link.occupied.load(Ordering::Relaxed);
+            shard.send_border_cells(&link.name, &state.world, occupied);
+        }
+
+        // Compare against the frame's ideal start time rather than the clock
+        // so that jitter doesn't cause snapshots to be skipped when the
+        // snapshot interval equals the frame duration.
+        let frame_time = state.frame.unwrap().ideal_start_time;
+        if next_publish_time.is_none_or(|next| frame_time >= next) {
+            // Publishing never blocks; it only replaces the latest snapshot.
+            let _ = link.snapshot_sender.send(Some(PublishedSnapshot {
+                sequence,
+                snapshot: Arc::new(snapshot(&state)),
+            }));
+            sequence += 1;
+            let next = next_publish_time.unwrap_or(frame_time) + timing.snapshot_interval;
+            next_publish_time = Some(next.max(frame_time));
+        }
+
+        let frame = state.frame.unwrap();
+        let skipped = previous_frame.map_or(0, |previous| frame.index - previous.index - 1);
+        room_metrics.record_frame(frame.start_time.elapsed(), skipped);
+        if let Some(timings) = system_timings.as_ref().filter(|_| dispatched) {
+            room_metrics.record_systems(&timings.durations());
+        }
+        room_metrics.set_agents(state.world.read_storage::<Position>().count());
+        room_metrics.set_inbox_depth(inbox_depth);
+        room_metrics.set_scheduled_commands(commands.values().map(Vec::len).sum());
+    }
+    state
+}
+
+/// Captures the state of every agent at the end of the current frame.
+fn snapshot(state: &State<'_, '_>) -> Snapshot {
+    let entities = state.world.entities();
+    let positions = state.world.read_storage::<Position>();
+    let groups = state.world.read_storage::<Group>();
+    let agents = (&entities, &positions, &groups)
+        .join()
+        .map(|(entity, pos, group)| AgentSnapshot {
+            id: entity.id(),
+            x: pos.x,
+            y: pos.y,
+            group: group.0,
+        })
+        .collect();
+    let frame = state.frame.unwrap();
+    Snapshot {
+        frame: frame.index,
+        time: clock::millis(frame.start_time),
+        agents,
+    }
+}
+
+/// Queues every command that clients have sent since the last frame, or
+/// rejects it if it targets a frame too far ahead. Also applies every message
+/// received from peers. Returns the number of messages received.
+fn apply_messages(
+    state: &mut State<'_, '_>,
+    receiver: &mut UnboundedReceiver<MessageToSimulation>,
+    commands: &mut CommandQueue,
+    shard: Option<&Shard>,
+    metrics: &Metrics,
+) -> usize {
+    let next_frame = state.frame.map_or(0, |frame| frame.index + 1);
+    let mut received = 0;
+    while let Ok(msg) = receiver.try_recv() {
+        received += 1;
+        match msg {
+            MessageToSimulation::Command {
+                id,
+                command,
+                frame,
+                reply,
+            } => {
+                let max = next_frame + MAX_FRAMES_AHEAD;
+                match frame {
+                    Some(frame) if frame > max => {
+                        let error = CommandError::FrameTooFarAhead { frame, max };
+                        send_reply(
+                            &reply,
+                            MessageToClient::CommandRejected { id, error },
+                            metrics,
+                        );
+                    }
+                    // Commands that target a frame that has already started are
+                    // applied at the start of the next one.
+                    _ => commands
+                        .entry(frame.unwrap_or(0))
+                        .or_default()
+                        .push(PendingCommand { id, command, reply }),
+                }
+            }
+            // Peer messages only arrive if this process has a shard.
+            MessageToSimulation::FromPeer(msg) => {
+                if let Some(shard) = shard {
+                    shard.apply_peer_message(&mut state.world, msg, Instant::now());
+                }
+            }
+        }
+    }
+
+    // Commit any entities that were created or deleted by the messages.
+    state.world.maintain();
+    received
+}
+
+/// Applies every queued command that targets the current frame or an earlier
+/// one and replies to each client with the outcome of its command.
+fn apply_due_commands(state: &mut State<'_, '_>, commands: &mut CommandQueue, metrics: &Metrics) {
+    let frame = state.frame.unwrap().index;
+    let later = commands.split_off(&(frame + 1));
+    for pending in mem::replace(commands, later).into_values().flatten() {
+        let PendingCommand { id, command, reply } = pending;
+        let outcome = match command::apply(&mut state.world, &command) {
+            Ok(()) => MessageToClient::CommandApplied { id, frame },
+            Err(error) => MessageToClient::CommandRejected { id, error },
+        };
+        send_reply(&reply, outcome, metrics);
+    }
+
+    // Commit any entities that were created or deleted by the commands.
+    state.world.maintain();
+}
+
+fn send_reply(reply: &Sender<MessageToConnectionHandler>, msg: MessageToClient, metrics: &Metrics) {
+    if let Err(TrySendError::Full(_)) = reply.try_send(MessageToConnectionHandler::Send(msg)) {
+        metrics.record_dropped_messages(1);
+    }
+}
+
+/// Starts the next frame and runs the dispatcher unless the simulation is
+/// paused. Returns whether the dispatcher ran.
+fn step(
+    state: &mut State<'_, '_>,
+    frame_duration: Duration,
+    commands: &mut CommandQueue,
+    metrics: &Metrics,
+) -> bool {
+    if let Some(frame) = state.frame {
+        let duration_since_ideal_start = Instant::now() - frame.ideal_start_time;
+        if duration_since_ideal_start < frame.ideal_duration {
+            // Wait until it's time for the next frame to start.
+            thread::sleep(frame.ideal_duration - duration_since_ideal_start);
+        }
+        let next_frame = frame.next(Instant::now());
+        state.frame = Some(next_frame);
+        state.world.insert(DurationSinceLastFrame(
+            next_frame.start_time - frame.start_time,
+        ))
+    } else {
+        state.frame = Some(Frame::new(frame_duration, Instant::now()));
+        state.world.insert(DurationSinceLastFrame::default());
+    }
+    apply_due_commands(state, commands, metrics);
+
+    if state.world.read_resource::<Paused>().0 {
+        return false;
+    }
+
+    // Executate a frame of the simulation.
+    state.dispatcher.dispatch(&state.world);
+    state.world.maintain();
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::MessageToSimulation;
+    use tokio::sync::mpsc::{self, Receiver, UnboundedSender};
+
+    /// Long enough that a frame of the test world never overruns, so that no
+    /// frame is skipped.
+    const FRAME_DURATION: Duration = Duration::from_millis(20);
+
+    fn state() -> State<'static, 'static> {
+        State::new(&Scenario {
+            agents: vec![],
+            ..Scenario::default()
+        })
+    }
+
+    fn spawn_agents() -> Command {
+        Command::SpawnAgents {
+            x: 8.0,
+            y: 8.0,
+            count: 3,
+            group: 0,
+        }
+    }
+
+    fn delete_agents() -> Command {
+        Command::DeleteAgents {
+            x: 8.0,
+            y: 8.0,
+            radius: 16.0,
+        }
+    }
+
+    fn send_command(
+        sender: &UnboundedSender<MessageToSimulation>,
+        reply: &Sender<MessageToConnectionHandler>,
+        id: u64,
+        command: Command,
+        frame: Option<u64>,
+    ) {
+        let reply = reply.clone();
+        let msg = MessageToSimulation::Command {
+            id,
+            command,
+            frame,
+            reply,
+        };
+        sender.send(msg).unwrap();
+    }
+
+    /// Returns the id of each command that has been replied to and the frame
+    /// it was applied at or the reason it was rejected, in the order of the
+    /// replies.
+    fn outcomes(
+        replies: &mut Receiver<MessageToConnectionHandler>,
+    ) -> Vec<(u64, std::result::Result<u64, CommandError>)> {
+        let mut outcomes = vec![];
+        while let Ok(MessageToConnectionHandler::Send(msg)) = replies.try_recv() {
+            match msg {
+                MessageToClient::CommandApplied { id, frame } => outcomes.push((id, Ok(frame))),
+                MessageToClient::CommandRejected { id, error } => outcomes.push((id, Err(error))),
+                msg => panic!("unexpected reply {:?}", msg),
+            }
+        }
+        outcomes
+    }
+
+    fn agents(state: &State<'_, '_>) -> usize {
+        state.world.read_storage::<Position>().count()
+    }
+
+    #[test]
+    fn room_names_go_before_the_extension() {
+        let cases = [
+            ("world.json", "world.lobby.json"),
+            ("saves/world", "saves/world.lobby"),
+            ("/tmp/world.v2.json", "/tmp/world.v2.lobby.json"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(
+                room_save_path(Path::new(path), "lobby"),
+                Path::new(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn scheduled_commands_are_applied_at_their_frame_in_the_order_received() {
+        let mut state = state();
+        let metrics = Metrics::new();
+        let mut commands = CommandQueue::new();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (reply, mut replies) = mpsc::channel(16);
+        send_command(&sender, &reply, 1, spawn_agents(), Some(2));
+        send_command(&sender, &reply, 2, delete_agents(), Some(2));
+        send_command(&sender, &reply, 3, spawn_agents(), None);
+        send_command(&sender, &reply, 4, spawn_agents(), Some(1));
+        let received = apply_messages(&mut state, &mut receiver, &mut commands, None, &metrics);
+        assert_eq!(received, 4);
+        assert!(outcomes(&mut replies).is_empty());
+
+        // Frame 0 applies the command without a frame.
+        assert!(step(&mut state, FRAME_DURATION, &mut commands, &metrics));
+        assert_eq!(outcomes(&mut replies), vec![(3, Ok(0))]);
+        assert_eq!(agents(&state), 3);
+
+        assert!(step(&mut state, FRAME_DURATION, &mut commands, &metrics));
+        assert_eq!(outcomes(&mut replies), vec![(4, Ok(1))]);
+        assert_eq!(agents(&state), 6);
+
+        // The spawn and the delete both target frame 2, and the delete was
+        // received last, so it removes every agent including the new ones.
+        assert!(step(&mut state, FRAME_DURATION, &mut commands, &metrics));
+        assert_eq!(outcomes(&mut replies), vec![(1, Ok(2)), (2, Ok(2))]);
+        assert_eq!(agents(&state), 0);
+        assert!(commands.is_empty());
+    }
+
+    #[test]
+    fn commands_for_frames_that_have_started_are_applied_at_the_next_frame() {
+        let mut state = state();
+        let metrics = Metrics::new();
+        let mut commands = CommandQueue::new();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (reply, mut replies) = mpsc::channel(16);
+        step(&mut state, FRAME_DURATION, &mut commands, &metrics);
+        step(&mut state, FRAME_DURATION, &mut commands, &metrics);
+
+        send_command(&sender, &reply, 1, spawn_agents(), Some(0));
+        apply_messages(&mut state, &mut receiver, &mut commands, None, &metrics);
+        step(&mut state, FRAME_DURATION, &mut commands, &metrics);
+        assert_eq!(outcomes(&mut replies), vec![(1, Ok(2))]);
+    }
+
+    #[test]
+    fn commands_too_far_ahead_are_rejected() {
+        let mut state = state();
+        let metrics = Metrics::new();
+        let mut commands = CommandQueue::new();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (reply, mut replies) = mpsc::channel(16);
+        send_command(&sender, &reply, 1, spawn_agents(), Some(MAX_FRAMES_AHEAD));
+        send_command(
+            &sender,
+            &reply,
+            2,
+            spawn_agents(),
+            Some(MAX_FRAMES_AHEAD + 1),
+        );
+        apply_messages(&mut state, &mut receiver, &mut commands, None, &metrics);
+        let error = CommandError::FrameTooFarAhead {
+            frame: MAX_FRAMES_AHEAD + 1,
+            max: MAX_FRAMES_AHEAD,
+        };
+        assert_eq!(outcomes(&mut replies), vec![(2, Err(error))]);
+        assert_eq!(
+            commands.keys().copied().collect::<Vec<_>>(),
+            vec![MAX_FRAMES_AHEAD]
+        );
+
+        // The limit moves with the simulation.
+        step(&mut state, FRAME_DURATION, &mut commands, &metrics);
+        send_command(
+            &sender,
+            &reply,
+            3,
+            spawn_agents(),
+            Some(MAX_FRAMES_AHEAD + 1),
+        );
+        apply_messages(&mut state, &mut receiver, &mut commands, None, &metrics);
+        assert!(outcomes(&mut replies).is_empty());
+    }
+
+    #[test]
+    fn paused_frames_apply_commands_without_dispatching() {
+        let mut state = state();
+        let metrics = Metrics::new();
+        let mut commands = CommandQueue::new();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (reply, mut replies) = mpsc::channel(16);
+        send_command(
+            &sender,
+            &reply,
+            1,
+            Command::SetPaused { paused: true },
+            None,
+        );
+        apply_messages(&mut state, &mut receiver, &mut commands, None, &metrics);
+        assert!(!step(&mut state, FRAME_DURATION, &mut commands, &metrics));
+        assert_eq!(outcomes(&mut replies), vec![(1, Ok(0))]);
+    }
+
+    #[tokio::test]
+    async fn the_loop_publishes_snapshots_and_replies_until_shut_down() {
+        let config = Config::from_toml("frame_rate = 50.0").unwrap();
+        let metrics = Arc::new(Metrics::new());
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (snapshot_sender, mut snapshots) = watch::channel(None);
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let link = RoomLink {
+            name: "lobby".to_string(),
+            receiver,
+            snapshot_sender,
+            shutdown,
+            occupied: Arc::new(AtomicBool::new(true)),
+        };
+        let scenario = Scenario {
+            agents: vec![],
+            ..Scenario::default()
+        };
+        let done = spawn(&config, scenario, link, None, metrics.clone()).unwrap();
+        assert!(metrics.render(&[]).contains("simulation_rooms 1\n"));
+
+        let (reply, mut replies) = mpsc::channel(16);
+        send_command(&sender, &reply, 1, spawn_agents(), None);
+        let reply = replies.recv().await.unwrap();
+        let frame = match reply {
+            MessageToConnectionHandler::Send(MessageToClient::CommandApplied { id: 1, frame }) => {
+                frame
+            }
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+        loop {
+            snapshots.changed().await.unwrap();
+            let snapshot = snapshots.borrow().clone().unwrap().snapshot;
+            if snapshot.frame >= frame {
+                assert_eq!(snapshot.agents.len(), 3);
+                break;
+            }
+        }
+
+        shutdown_sender.send(true).unwrap();
+        done.await.unwrap();
+        assert!(metrics.render(&[]).contains("simulation_rooms 0\n"));
+    }
+}
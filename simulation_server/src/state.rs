@@ -1,15 +1,27 @@
+use crate::scenario::Scenario;
 use simulation::{
-    collections::grid::RowMajorGrid,
-    component::{Position, Velocity},
+    collections::grid::{Grid, StaggeredGrid},
+    component::{Group, Position, Velocity},
     frame::Frame,
-    resources::continuum_crowds::{GroupCell, GroupGrids, SharedCell, SharedGrid},
+    profile::WithTimed,
+    resources::continuum_crowds::{
+        CrowdGrid, GhostCells, GroupCell, GroupCellFace, GroupFaces, GroupGoals, GroupGrids,
+        SharedCellFace, SharedFaces, SharedGrid,
+    },
     systems::{
-        continuum_crowds::{AssignDensitiesAndVelocities, PrintDensityGrid, ResetShared},
+        continuum_crowds::{
+            ApplyGhostCells, AssignDensitiesAndVelocities, PrintDensityGrid, ResetShared,
+        },
         UpdatePos,
     },
 };
 use specs::prelude::*;
 
+/// Whether frames are currently skipped. Paused frames still advance the frame
+/// counter and publish snapshots, but don't run the dispatcher.
+#[derive(Debug, Default)]
+pub struct Paused(pub bool);
+
 pub struct State<'a, 'b> {
     pub world: World,
     pub dispatcher: Dispatcher<'a, 'b>,
@@ -17,30 +29,31 @@ pub struct State<'a, 'b> {
 }
 
 impl State<'_, '_> {
-    pub fn new() -> Self {
+    pub fn new(scenario: &Scenario) -> Self {
         let mut world = World::new();
 
         Self::register_component(&mut world);
-        Self::initialize_entities(&mut world);
-        Self::initialize_resources(&mut world);
+        Self::initialize_entities(&mut world, scenario);
+        Self::initialize_resources(&mut world, scenario);
 
         let mut dispatcher = DispatcherBuilder::new()
-            .with(ResetShared, "reset_shared", &[])
-            .with(
+            .with_timed(ResetShared, "reset_shared", &[])
+            .with_timed(
                 AssignDensitiesAndVelocities,
                 "assign_densities_and_velocities",
                 &["reset_shared"],
             )
-            .with(
-                PrintDensityGrid,
-                "print_density_grid",
+            .with_timed(
+                ApplyGhostCells,
+                "apply_ghost_cells",
                 &["assign_densities_and_velocities"],
             )
-            .with(
-                UpdatePos,
-                "update_pos",
-                &["assign_densities_and_velocities"],
+            .with_timed(
+                PrintDensityGrid,
+                "print_density_grid",
+                &["apply_ghost_cells"],
             )
+            .with_timed(UpdatePos, "update_pos", &["apply_ghost_cells"])
             .build();
         dispatcher.setup(&mut world);
 
@@ -54,39 +67,58 @@ impl State<'_, '_> {
     fn register_component(world: &mut World) {
         world.register::<Position>();
         world.register::<Velocity>();
+        world.register::<Group>();
     }
 
-    fn initialize_entities(world: &mut World) {
-        for x in 1..=10 {
-            for y in 1..=10 {
-                world
-                    .create_entity()
-                    .with(Position {
-                        x: x as f32 * 0.3,
-                        y: y as f32 * 0.3,
-                    })
-                    .with(Velocity { x: 0.2, y: 0.2 })
-                    .build();
-            }
+    fn initialize_entities(world: &mut World, scenario: &Scenario) {
+        for agent in &scenario.agents {
+            world
+                .create_entity()
+                .with(Position {
+                    x: agent.x,
+                    y: agent.y,
+                })
+                .with(Velocity {
+                    x: agent.velocity_x,
+                    y: agent.velocity_y,
+                })
+                .with(Group(agent.group))
+                .build();
         }
     }
 
-    fn initialize_resources(world: &mut World) {
+    fn initialize_resources(world: &mut World, scenario: &Scenario) {
         // 1 cell is 4 m wide
-        const GRID_WIDTH: usize = 16;
-        const GRID_HEIGHT: usize = 16;
-        let shared_grid = SharedGrid(RowMajorGrid::new(
-            GRID_WIDTH,
-            GRID_HEIGHT,
-            SharedCell::default(),
-        ));
+        let (width, height) = (scenario.width, scenario.height);
+        let mut shared_grid = SharedGrid::new(width, height);
+        for cell in &scenario.cells {
+            shared_grid.discomfort.set(cell.x, cell.y, cell.discomfort);
+            shared_grid
+                .is_obstacle
+                .set(cell.x, cell.y, cell.is_obstacle);
+        }
         let group_grids = GroupGrids(
-            RowMajorGrid::new(GRID_WIDTH, GRID_HEIGHT, GroupCell::default()),
-            RowMajorGrid::new(GRID_WIDTH, GRID_HEIGHT, GroupCell::default()),
-            RowMajorGrid::new(GRID_WIDTH, GRID_HEIGHT, GroupCell::default()),
-            RowMajorGrid::new(GRID_WIDTH, GRID_HEIGHT, GroupCell::default()),
+            CrowdGrid::new(width, height, GroupCell::default()),
+            CrowdGrid::new(width, height, GroupCell::default()),
+            CrowdGrid::new(width, height, GroupCell::default()),
+            CrowdGrid::new(width, height, GroupCell::default()),
+        );
+        let group_faces = GroupFaces(
+            StaggeredGrid::new(width, height, GroupCellFace::default()),
+            StaggeredGrid::new(width, height, GroupCellFace::default()),
+            StaggeredGrid::new(width, height, GroupCellFace::default()),
+            StaggeredGrid::new(width, height, GroupCellFace::default()),
         );
         world.insert(shared_grid);
+        world.insert(SharedFaces(StaggeredGrid::new(
+            width,
+            height,
+            SharedCellFace::default(),
+        )));
         world.insert(group_grids);
+        world.insert(group_faces);
+        world.insert(GhostCells::default());
+        world.insert(Paused::default());
+        world.insert(GroupGoals(scenario.goals));
     }
 }
@@ -0,0 +1,96 @@
+//! Helpers shared by the tests that run the server as a separate process.
+
+// Each test crate compiles this module on its own and uses only some of it.
+#![allow(dead_code)]
+
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tungstenite::Message;
+
+pub type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A server process that is killed if the test ends before it exits.
+pub struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+impl Server {
+    pub fn start(args: &[&str]) -> Server {
+        let child = Command::new(env!("CARGO_BIN_EXE_simulation_server"))
+            .args(args)
+            .args(["--log-level", "warn"])
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        Server(child)
+    }
+
+    /// Asks the server to shut down, which saves its rooms, and waits for it
+    /// to exit.
+    pub fn stop(mut self) {
+        let status = Command::new("kill")
+            .arg("-TERM")
+            .arg(self.0.id().to_string())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert!(self.0.wait().unwrap().success());
+    }
+}
+
+/// Returns a localhost address with a port that nothing is listening on.
+pub fn free_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+pub async fn connect(address: &str) -> Client {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match tokio_tungstenite::connect_async(format!("ws://{}", address)).await {
+            Ok((client, _)) => return client,
+            Err(_) if Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_millis(50)).await
+            }
+            Err(e) => panic!("failed to connect to {}: {}", address, e),
+        }
+    }
+}
+
+pub async fn send(client: &mut Client, msg: Value) {
+    client.send(Message::Text(msg.to_string())).await.unwrap();
+}
+
+/// Returns the first message from the server that satisfies `done`.
+pub async fn receive_until(client: &mut Client, done: impl Fn(&Value) -> bool) -> Value {
+    let receive = async {
+        loop {
+            if let Some(Ok(Message::Text(text))) = client.next().await {
+                let msg: Value = serde_json::from_str(&text).unwrap();
+                if done(&msg) {
+                    return msg;
+                }
+            }
+        }
+    };
+    tokio::time::timeout(TIMEOUT, receive)
+        .await
+        .expect("timed out waiting for a message")
+}
+
+pub async fn join_lobby(client: &mut Client) {
+    send(client, json!({ "type": "join_room", "name": "lobby" })).await;
+    receive_until(client, |msg| msg["type"] == "joined_room").await;
+}
@@ -0,0 +1,160 @@
+//! Resumes sessions over real connections and checks what happens to the
+//! connections on both ends.
+
+mod common;
+
+use common::{connect, free_address, join_lobby, receive_until, send, Server, TIMEOUT};
+use futures::StreamExt;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tungstenite::Message;
+
+const SECRET: &str = "secret";
+
+/// Starts a server that only accepts tokens signed with `SECRET` and returns
+/// it with its address.
+fn start_with_auth() -> (Server, String) {
+    let address = free_address();
+    let config = std::env::temp_dir().join(format!(
+        "simulation-sessions-{}-{}.toml",
+        std::process::id(),
+        address.replace(':', "-")
+    ));
+    let contents = format!(
+        "bind_address = {:?}\n[auth]\nhmac_secret = {:?}\n",
+        address, SECRET
+    );
+    fs::write(&config, contents).unwrap();
+    let server = Server::start(&["--config", config.to_str().unwrap()]);
+    (server, address)
+}
+
+/// Returns an operator token signed with `SECRET` that expires at the next
+/// whole second at least a second from now, and when it expires.
+fn short_lived_token() -> (String, SystemTime) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let expires_at = now.as_secs() + 2;
+    let payload = format!("operator:{}", expires_at);
+    let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+    mac.update(payload.as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+    let token = format!("{}:{}", payload, signature);
+    (token, UNIX_EPOCH + Duration::from_secs(expires_at))
+}
+
+async fn sleep_until(time: SystemTime) {
+    if let Ok(duration) = time.duration_since(SystemTime::now()) {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+#[tokio::test]
+async fn resuming_the_connections_own_session_fails_without_closing_it() {
+    let address = free_address();
+    let _server = Server::start(&["--bind-address", &address]);
+    let mut client = connect(&address).await;
+    send(&mut client, json!({ "type": "authenticate", "token": "" })).await;
+    let authenticated = receive_until(&mut client, |msg| msg["type"] == "authenticated").await;
+    let session_id = authenticated["session_id"].clone();
+
+    let resume = json!({ "type": "resume", "session_id": session_id, "last_frame": null });
+    send(&mut client, resume).await;
+    let failed = receive_until(&mut client, |msg| msg["type"] == "resume_failed").await;
+    assert_eq!(failed["session_id"], session_id);
+
+    // The connection still serves its session.
+    send(&mut client, json!({ "type": "ping", "client_time": 1.0 })).await;
+    receive_until(&mut client, |msg| msg["type"] == "pong").await;
+}
+
+#[tokio::test]
+async fn resuming_a_session_held_by_another_connection_closes_that_connection() {
+    let address = free_address();
+    let _server = Server::start(&["--bind-address", &address]);
+    let mut holder = connect(&address).await;
+    send(&mut holder, json!({ "type": "authenticate", "token": "" })).await;
+    let authenticated = receive_until(&mut holder, |msg| msg["type"] == "authenticated").await;
+    let session_id = authenticated["session_id"].clone();
+    join_lobby(&mut holder).await;
+
+    let mut client = connect(&address).await;
+    let resume = json!({ "type": "resume", "session_id": session_id, "last_frame": null });
+    send(&mut client, resume).await;
+    let resumed = receive_until(&mut client, |msg| msg["type"] == "resumed").await;
+    assert_eq!(resumed["session_id"], session_id);
+    assert_eq!(resumed["room"], "lobby");
+
+    let close = async {
+        loop {
+            match holder.next().await {
+                Some(Ok(Message::Close(frame))) => return frame,
+                Some(Ok(_)) => {}
+                other => panic!("expected a close frame, got {:?}", other),
+            }
+        }
+    };
+    let frame = tokio::time::timeout(TIMEOUT, close)
+        .await
+        .expect("timed out waiting for the connection to close");
+    assert_eq!(frame.unwrap().reason, "session resumed elsewhere");
+}
+
+#[tokio::test]
+async fn sessions_cant_be_resumed_once_their_token_expires() {
+    let (_server, address) = start_with_auth();
+    let mut client = connect(&address).await;
+    let (token, expires_at) = short_lived_token();
+    send(
+        &mut client,
+        json!({ "type": "authenticate", "token": token }),
+    )
+    .await;
+    let authenticated = receive_until(&mut client, |msg| msg["type"] == "authenticated").await;
+    let session_id = authenticated["session_id"].clone();
+    join_lobby(&mut client).await;
+    // Dropping the client ends the connection without a close handshake, so
+    // the session is kept.
+    drop(client);
+
+    sleep_until(expires_at).await;
+    let mut client = connect(&address).await;
+    let resume = json!({ "type": "resume", "session_id": session_id, "last_frame": null });
+    send(&mut client, resume).await;
+    let failed = receive_until(&mut client, |msg| msg["type"] == "resume_failed").await;
+    assert_eq!(failed["session_id"], session_id);
+}
+
+#[tokio::test]
+async fn commands_are_rejected_once_the_token_expires() {
+    let (_server, address) = start_with_auth();
+    let mut client = connect(&address).await;
+    let (token, expires_at) = short_lived_token();
+    send(
+        &mut client,
+        json!({ "type": "authenticate", "token": token }),
+    )
+    .await;
+    receive_until(&mut client, |msg| msg["type"] == "authenticated").await;
+    join_lobby(&mut client).await;
+
+    let command = json!({ "type": "set_goal", "group": 0, "x": 1.0, "y": 1.0 });
+    send(
+        &mut client,
+        json!({ "type": "command", "id": 1, "command": command }),
+    )
+    .await;
+    receive_until(&mut client, |msg| msg["type"] == "command_applied").await;
+
+    sleep_until(expires_at).await;
+    send(
+        &mut client,
+        json!({ "type": "command", "id": 2, "command": command }),
+    )
+    .await;
+    let rejected = receive_until(&mut client, |msg| msg["type"] == "command_rejected").await;
+    assert_eq!(rejected["id"], 2);
+    assert_eq!(rejected["error"]["type"], "token_expired");
+}
@@ -0,0 +1,118 @@
+//! Runs the two-process split from the README on localhost and checks that the
+//! processes exchange border cells and hand agents off to each other.
+
+mod common;
+
+use common::{connect, free_address, join_lobby, receive_until, send, Server};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Returns the TOML block of the README that starts with `# <name>`.
+fn readme_config(name: &str) -> String {
+    let readme = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/../README.md")).unwrap();
+    let start = readme
+        .find(&format!("```toml\n# {}\n", name))
+        .unwrap_or_else(|| panic!("README has no {} block", name));
+    let block = &readme[start + "```toml\n".len()..];
+    block[..block.find("```").unwrap()].to_string()
+}
+
+/// The addresses in the README's configs, which the tests replace with free
+/// ones so that they don't collide with anything else listening on them.
+const README_ADDRESSES: [&str; 4] = [
+    "127.0.0.1:8081",
+    "127.0.0.1:8082",
+    "127.0.0.1:9081",
+    "127.0.0.1:9082",
+];
+
+/// Writes the README's config for the process, preceded by the scenario and
+/// a save path, which must come before its `[[peers]]` tables. Each of
+/// `README_ADDRESSES` is replaced with the address at the same index of
+/// `addresses`.
+fn write_config(dir: &Path, name: &str, scenario: &Path, addresses: &[String]) -> PathBuf {
+    let path = dir.join(name);
+    let mut config = format!(
+        "scenario = {:?}\nsave_path = {:?}\n{}",
+        scenario,
+        dir.join(name.replace(".toml", ".json")),
+        readme_config(name)
+    );
+    for (readme_address, address) in README_ADDRESSES.iter().zip(addresses) {
+        config = config.replace(readme_address, address);
+    }
+    fs::write(&path, config).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn processes_exchange_border_cells_and_hand_off_agents() {
+    let dir = std::env::temp_dir().join(format!("simulation-sharding-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // A single agent that starts in the west region and walks east.
+    let scenario = dir.join("scenario.json");
+    let agent = json!({ "x": 7.5, "y": 4.5, "velocity_x": 2.0, "velocity_y": 0.0, "group": 0 });
+    let world = json!({
+        "width": 16,
+        "height": 16,
+        "goals": [null, null, null, null],
+        "agents": [agent],
+        "cells": [],
+    });
+    fs::write(&scenario, world.to_string()).unwrap();
+
+    let addresses: Vec<String> = README_ADDRESSES.iter().map(|_| free_address()).collect();
+    let west_config = write_config(&dir, "west.toml", &scenario, &addresses);
+    let west = Server::start(&["--config", west_config.to_str().unwrap()]);
+    let east_config = write_config(&dir, "east.toml", &scenario, &addresses);
+    let east = Server::start(&["--config", east_config.to_str().unwrap()]);
+    let mut east_client = connect(&addresses[1]).await;
+    join_lobby(&mut east_client).await;
+    let mut west_client = connect(&addresses[0]).await;
+    join_lobby(&mut west_client).await;
+
+    // The east process doesn't start with the agent, so it can only have come
+    // from the west process.
+    receive_until(&mut east_client, |msg| {
+        msg["type"] == "snapshot"
+            && msg["agents"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|agent| agent["x"].as_f64().unwrap() >= 8.0)
+    })
+    .await;
+
+    // Cell (7, 4) is on the west side of the border, so the east process only
+    // learns of its discomfort as a ghost cell.
+    let paint = json!({
+        "type": "paint_discomfort",
+        "x": 7.5,
+        "y": 4.5,
+        "radius": 0.0,
+        "discomfort": 2.5,
+    });
+    send(
+        &mut west_client,
+        json!({ "type": "command", "id": 1, "command": paint }),
+    )
+    .await;
+    receive_until(&mut west_client, |msg| msg["type"] == "command_applied").await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    drop(west_client);
+    drop(east_client);
+    east.stop();
+    west.stop();
+    let saved: Value =
+        serde_json::from_str(&fs::read_to_string(dir.join("east.lobby.json")).unwrap()).unwrap();
+    let ghost_cell = saved["cells"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|cell| cell["x"] == 7 && cell["y"] == 4);
+    assert_eq!(ghost_cell.unwrap()["discomfort"], 2.5);
+    let _ = fs::remove_dir_all(&dir);
+}
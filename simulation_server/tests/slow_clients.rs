@@ -0,0 +1,106 @@
+//! Runs a server whose snapshots are too large for clients that stop reading
+//! to keep up with, and checks what each slow client policy does to them.
+
+mod common;
+
+use common::{connect, free_address, join_lobby, receive_until, send, Server, TIMEOUT};
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Enough agents that a few snapshots fill the buffers of a socket that isn't
+/// read from.
+const AGENTS: usize = 20_000;
+
+/// Starts a server with the policy and returns it with its client and admin
+/// addresses and the directory that holds its scenario and config.
+fn start(policy: &str) -> (Server, String, String, PathBuf) {
+    let dir = std::env::temp_dir().join(format!(
+        "simulation-slow-clients-{}-{}",
+        policy,
+        std::process::id()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    let agents: Vec<_> = (0..AGENTS)
+        .map(|i| {
+            let (x, y) = ((i % 64) as f32 + 0.5, (i / 64 % 64) as f32 + 0.5);
+            json!({ "x": x, "y": y, "velocity_x": 0.0, "velocity_y": 0.0, "group": 0 })
+        })
+        .collect();
+    let world = json!({
+        "width": 64,
+        "height": 64,
+        "goals": [null, null, null, null],
+        "agents": agents,
+        "cells": [],
+    });
+    let scenario = dir.join("scenario.json");
+    fs::write(&scenario, world.to_string()).unwrap();
+
+    let address = free_address();
+    let admin_address = free_address();
+    let config = dir.join("config.toml");
+    let contents = format!(
+        "bind_address = {:?}\nadmin_bind_address = {:?}\nscenario = {:?}\n\
+         frame_rate = 30.0\nslow_client_policy = {:?}\n",
+        address, admin_address, scenario, policy
+    );
+    fs::write(&config, contents).unwrap();
+    let server = Server::start(&["--config", config.to_str().unwrap()]);
+    (server, address, admin_address, dir)
+}
+
+/// Returns the value of a metric without labels from the admin endpoint.
+async fn metric(admin_address: &str, name: &str) -> f64 {
+    let mut stream = TcpStream::connect(admin_address).await.unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.0\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+        .unwrap_or_else(|| panic!("no {} metric in {}", name, response))
+}
+
+#[tokio::test]
+async fn stale_snapshots_are_skipped_for_clients_that_fall_behind() {
+    let (_server, address, admin_address, dir) = start("drop_stale");
+    let mut client = connect(&address).await;
+    join_lobby(&mut client).await;
+    receive_until(&mut client, |msg| msg["type"] == "snapshot").await;
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(metric(&admin_address, "simulation_dropped_messages_total").await > 0.0);
+
+    // The client is still connected and gets replies once it reads again.
+    send(&mut client, json!({ "type": "ping", "client_time": 1.0 })).await;
+    receive_until(&mut client, |msg| msg["type"] == "pong").await;
+    assert_eq!(metric(&admin_address, "simulation_connections").await, 1.0);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn clients_that_never_read_are_disconnected() {
+    let (_server, address, admin_address, dir) = start("disconnect");
+    let mut client = connect(&address).await;
+    join_lobby(&mut client).await;
+
+    // The client never reads again, so only the server can end the
+    // connection.
+    let deadline = Instant::now() + TIMEOUT;
+    while metric(&admin_address, "simulation_connections").await > 0.0 {
+        assert!(
+            Instant::now() < deadline,
+            "timed out waiting for the server to disconnect the client"
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    drop(client);
+    let _ = fs::remove_dir_all(&dir);
+}